@@ -1,4 +1,5 @@
 use crate::vec3::Point3;
+use crate::vec3::Vec3;
 use crate::ray::Ray;
 
 #[derive(Copy, Clone)]
@@ -27,10 +28,6 @@ impl AABB {
             t_min = t0.max(t_min);
             t_max = t1.min(t_max);
 
-            if t_min.is_infinite() || t_min.is_nan() {
-                eprintln!("t_min issues");
-            }
-        
             if t_max <= t_min {
                 return false;
             } 
@@ -57,6 +54,28 @@ impl AABB {
         // }
         // true
     }
+
+    // Slab test with the inverse ray direction worked out by the caller, so a
+    // BVH traversal divides once per ray instead of once per box.
+    pub fn hit_with_inverse(&self, origin: &Point3, inv_direction: &Vec3, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+
+        for a in 0..3 {
+            let mut t0 = (self.minimum[a] - origin[a]) * inv_direction[a];
+            let mut t1 = (self.maximum[a] - origin[a]) * inv_direction[a];
+            if inv_direction[a] < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
 }
 
 pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
@@ -68,5 +87,5 @@ pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
                             box0.max().y().max(box1.max().y()), 
                             box0.max().z().max(box1.max().z()));
 
-    return AABB{minimum: small, maximum: big};
+    AABB{minimum: small, maximum: big}
 }
\ No newline at end of file
@@ -68,5 +68,5 @@ pub fn surrounding_box(box0: &AABB, box1: &AABB) -> AABB {
                             box0.max().y().max(box1.max().y()), 
                             box0.max().z().max(box1.max().z()));
 
-    return AABB{minimum: small, maximum: big};
+    AABB{minimum: small, maximum: big}
 }
\ No newline at end of file
@@ -1,7 +1,10 @@
 use crate::hittable::Hittable;
 use crate::hittable::HitRecord;
+use crate::hittable::area_light_power;
 use crate::aabb::AABB;
 
+use crate::mat4::Mat4;
+
 use crate::ray::Ray;
 
 use crate::material::Material;
@@ -11,11 +14,10 @@ use crate::rtweekend::random_double_range;
 use crate::vec3::Vec3;
 use crate::vec3::Point3;
 
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
 pub struct XYRect {
-    mp: Rc<RefCell<dyn Material>>,
+    mp: Arc<dyn Material>,
     x0: f64, 
     x1: f64,
     y0: f64,
@@ -24,7 +26,7 @@ pub struct XYRect {
 }
 
 impl Hittable for XYRect {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let t = (self.k - r.origin().z()) / r.direction().z();
 
         if (t < t_min) || (t > t_max) {
@@ -44,7 +46,7 @@ impl Hittable for XYRect {
         rec.set_face_normal(r, outward_normal);
         rec.mat_ptr = self.mp.clone();
         rec.p = r.at(t);
-        return true;
+        true
 
     }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
@@ -55,25 +57,33 @@ impl Hittable for XYRect {
             minimum: Point3(self.x0, self.y0, self.k - 0.0001),
             maximum: Point3(self.x1, self.y1, self.k + 0.0001)
         };
-        return true;
+        true
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        area_light_power(self.area(matrix), self.mp.as_ref())
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        matrix.transformed_area(&Vec3(self.x1 - self.x0, 0.0, 0.0), &Vec3(0.0, self.y1 - self.y0, 0.0))
     }
 }
 
 impl XYRect {
-    pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mp: Rc<RefCell<dyn Material>>) -> Self {
+    pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mp: Arc<dyn Material>) -> Self {
         XYRect {
-            mp: mp,
-            x0: x0, 
-            x1: x1,
-            y0: y0,
-            y1: y1,
-            k: k,
+            mp,
+            x0, 
+            x1,
+            y0,
+            y1,
+            k,
         }
     }
 }
 
 pub struct XZRect {
-    mp: Rc<RefCell<dyn Material>>,
+    mp: Arc<dyn Material>,
     x0: f64, 
     x1: f64,
     z0: f64,
@@ -82,7 +92,7 @@ pub struct XZRect {
 }
 
 impl Hittable for XZRect {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let t = (self.k - r.origin().y()) / r.direction().y();
 
         if (t < t_min) || (t > t_max) {
@@ -102,7 +112,7 @@ impl Hittable for XZRect {
         rec.set_face_normal(r, outward_normal);
         rec.mat_ptr = self.mp.clone();
         rec.p = r.at(t);
-        return true;
+        true
 
     }
 
@@ -114,21 +124,21 @@ impl Hittable for XZRect {
             minimum: Point3(self.x0, self.k - 0.0001, self.z0),
             maximum: Point3(self.x1, self.k + 0.0001, self.z1)
         };
-        return true;
+        true
     }
 
-    fn pdf_value(&mut self, o: &Vec3, v: &Vec3) -> f64 {
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
         let mut rec: HitRecord = HitRecord {
             p: Point3(0.0, 0.0, 0.0),
             normal: Vec3(0.0, 0.0, 0.0),
-            mat_ptr: Rc::new(RefCell::new(DefaultMaterial)),
+            mat_ptr: Arc::new(DefaultMaterial),
             t: 0.0,
             u: 0.0,
             v: 0.0,
             front_face: false,
         };
 
-        if !self.hit(&Ray { origin: *o, direction: *v, tm: 0.0 }, 0.001, f64::INFINITY, &mut rec) {
+        if !self.hit(&Ray { origin: *o, direction: *v, tm: 0.0, wavelength: 0.0 }, 0.001, f64::INFINITY, &mut rec) {
             return 0.0;
         }
 
@@ -143,23 +153,31 @@ impl Hittable for XZRect {
        let random_point = Point3(random_double_range(self.x0, self.x1), self.k, random_double_range(self.z0, self.z1));
        random_point - *o 
     }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        area_light_power(self.area(matrix), self.mp.as_ref())
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        matrix.transformed_area(&Vec3(self.x1 - self.x0, 0.0, 0.0), &Vec3(0.0, 0.0, self.z1 - self.z0))
+    }
 }
 
 impl XZRect {
-    pub fn new(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mp: Rc<RefCell<dyn Material>>) -> Self {
+    pub fn new(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mp: Arc<dyn Material>) -> Self {
         XZRect {
-            mp: mp,
-            x0: x0, 
-            x1: x1,
-            z0: z0,
-            z1: z1,
-            k: k,
+            mp,
+            x0, 
+            x1,
+            z0,
+            z1,
+            k,
         }
     }
 }
 
 pub struct YZRect {
-    mp: Rc<RefCell<dyn Material>>,
+    mp: Arc<dyn Material>,
     y0: f64, 
     y1: f64,
     z0: f64,
@@ -168,7 +186,7 @@ pub struct YZRect {
 }
 
 impl Hittable for YZRect {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let t = (self.k - r.origin().x()) / r.direction().x();
 
         if (t < t_min) || (t > t_max) {
@@ -188,7 +206,7 @@ impl Hittable for YZRect {
         rec.set_face_normal(r, outward_normal);
         rec.mat_ptr = self.mp.clone();
         rec.p = r.at(t);
-        return true;
+        true
 
     }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
@@ -199,19 +217,27 @@ impl Hittable for YZRect {
             minimum: Point3(self.k - 0.0001, self.y0, self.z0),
             maximum: Point3(self.k + 0.0001, self.y1, self.z1)
         };
-        return true;
+        true
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        area_light_power(self.area(matrix), self.mp.as_ref())
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        matrix.transformed_area(&Vec3(0.0, self.y1 - self.y0, 0.0), &Vec3(0.0, 0.0, self.z1 - self.z0))
     }
 }
 
 impl YZRect {
-    pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mp: Rc<RefCell<dyn Material>>) -> Self {
+    pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mp: Arc<dyn Material>) -> Self {
         YZRect {
-            mp: mp,
-            y0: y0, 
-            y1: y1,
-            z0: z0,
-            z1: z1,
-            k: k,
+            mp,
+            y0, 
+            y1,
+            z0,
+            z1,
+            k,
         }
     }
 }
\ No newline at end of file
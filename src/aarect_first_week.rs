@@ -9,10 +9,9 @@ use crate::material_first_week::Material;
 use crate::vec3::Vec3;
 use crate::vec3::Point3;
 
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 pub struct XYRect {
-    mp: Rc<RefCell<dyn Material>>,
+    mp: Arc<dyn Material>,
     x0: f64, 
     x1: f64,
     y0: f64,
@@ -21,7 +20,7 @@ pub struct XYRect {
 }
 
 impl Hittable for XYRect {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let t = (self.k - r.origin().z()) / r.direction().z();
 
         if (t < t_min) || (t > t_max) {
@@ -41,7 +40,7 @@ impl Hittable for XYRect {
         rec.set_face_normal(r, outward_normal);
         rec.mat_ptr = self.mp.clone();
         rec.p = r.at(t);
-        return true;
+        true
 
     }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
@@ -52,25 +51,25 @@ impl Hittable for XYRect {
             minimum: Point3(self.x0, self.y0, self.k - 0.0001),
             maximum: Point3(self.x1, self.y1, self.k + 0.0001)
         };
-        return true;
+        true
     }
 }
 
 impl XYRect {
-    pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mp: Rc<RefCell<dyn Material>>) -> Self {
+    pub fn new(x0: f64, x1: f64, y0: f64, y1: f64, k: f64, mp: Arc<dyn Material>) -> Self {
         XYRect {
-            mp: mp,
-            x0: x0, 
-            x1: x1,
-            y0: y0,
-            y1: y1,
-            k: k,
+            mp,
+            x0, 
+            x1,
+            y0,
+            y1,
+            k,
         }
     }
 }
 
 pub struct XZRect {
-    mp: Rc<RefCell<dyn Material>>,
+    mp: Arc<dyn Material>,
     x0: f64, 
     x1: f64,
     z0: f64,
@@ -79,7 +78,7 @@ pub struct XZRect {
 }
 
 impl Hittable for XZRect {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let t = (self.k - r.origin().y()) / r.direction().y();
 
         if (t < t_min) || (t > t_max) {
@@ -99,7 +98,7 @@ impl Hittable for XZRect {
         rec.set_face_normal(r, outward_normal);
         rec.mat_ptr = self.mp.clone();
         rec.p = r.at(t);
-        return true;
+        true
 
     }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
@@ -110,25 +109,25 @@ impl Hittable for XZRect {
             minimum: Point3(self.x0, self.k - 0.0001, self.z0),
             maximum: Point3(self.x1, self.k + 0.0001, self.z1)
         };
-        return true;
+        true
     }
 }
 
 impl XZRect {
-    pub fn new(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mp: Rc<RefCell<dyn Material>>) -> Self {
+    pub fn new(x0: f64, x1: f64, z0: f64, z1: f64, k: f64, mp: Arc<dyn Material>) -> Self {
         XZRect {
-            mp: mp,
-            x0: x0, 
-            x1: x1,
-            z0: z0,
-            z1: z1,
-            k: k,
+            mp,
+            x0, 
+            x1,
+            z0,
+            z1,
+            k,
         }
     }
 }
 
 pub struct YZRect {
-    mp: Rc<RefCell<dyn Material>>,
+    mp: Arc<dyn Material>,
     y0: f64, 
     y1: f64,
     z0: f64,
@@ -137,7 +136,7 @@ pub struct YZRect {
 }
 
 impl Hittable for YZRect {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let t = (self.k - r.origin().x()) / r.direction().x();
 
         if (t < t_min) || (t > t_max) {
@@ -157,7 +156,7 @@ impl Hittable for YZRect {
         rec.set_face_normal(r, outward_normal);
         rec.mat_ptr = self.mp.clone();
         rec.p = r.at(t);
-        return true;
+        true
 
     }
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
@@ -168,19 +167,19 @@ impl Hittable for YZRect {
             minimum: Point3(self.k - 0.0001, self.y0, self.z0),
             maximum: Point3(self.k + 0.0001, self.y1, self.z1)
         };
-        return true;
+        true
     }
 }
 
 impl YZRect {
-    pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mp: Rc<RefCell<dyn Material>>) -> Self {
+    pub fn new(y0: f64, y1: f64, z0: f64, z1: f64, k: f64, mp: Arc<dyn Material>) -> Self {
         YZRect {
-            mp: mp,
-            y0: y0, 
-            y1: y1,
-            z0: z0,
-            z1: z1,
-            k: k,
+            mp,
+            y0, 
+            y1,
+            z0,
+            z1,
+            k,
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,203 @@
+// Compares the pointer tree BvhNode with the flattened LinearBvh on the final
+// scene of Ray Tracing: The Next Week. Both get the same objects, only the two
+// BVHs over the boxes and the spheres differ.
+//
+// cargo run --release --bin bvh_bench
+
+use ray_tracing_rust::vec3::Vec3;
+use ray_tracing_rust::vec3::Point3;
+use ray_tracing_rust::vec3::Color;
+
+use ray_tracing_rust::hittable::Hittable;
+use ray_tracing_rust::hittable::HitRecord;
+use ray_tracing_rust::hittable::Translate;
+use ray_tracing_rust::hittable::RotateY;
+use ray_tracing_rust::hittable::FlipFace;
+use ray_tracing_rust::hittable_list::HittableList;
+
+use ray_tracing_rust::material::DefaultMaterial;
+use ray_tracing_rust::material::Lambertian;
+use ray_tracing_rust::material::Metal;
+use ray_tracing_rust::material::Dialectric;
+use ray_tracing_rust::material::DiffuseLight;
+
+use ray_tracing_rust::sphere::Sphere;
+use ray_tracing_rust::moving_sphere::MovingSphere;
+use ray_tracing_rust::aarect::XZRect;
+use ray_tracing_rust::abox::ABox;
+use ray_tracing_rust::constant_medium::ConstantMedium;
+
+use ray_tracing_rust::texture::NoiseTexture;
+use ray_tracing_rust::perlin::Perlin;
+
+use ray_tracing_rust::bvh_node::BvhNode;
+use ray_tracing_rust::linear_bvh::LinearBvh;
+
+use ray_tracing_rust::integrator::PathIntegrator;
+use ray_tracing_rust::renderer::Renderer;
+use ray_tracing_rust::renderer::RenderSettings;
+use ray_tracing_rust::scene::CameraSettings;
+
+use ray_tracing_rust::rtweekend::random_double_range;
+use ray_tracing_rust::rtweekend::seed_random;
+
+use std::sync::Arc;
+use std::time::Duration;
+use std::time::Instant;
+
+// Objects of the final scene. The ground boxes and the cluster of spheres
+// are kept apart, to be put into the BVH under test.
+struct FinalScene {
+    boxes: Vec<Arc<dyn Hittable>>,
+    spheres: Vec<Arc<dyn Hittable>>,
+    others: Vec<Arc<dyn Hittable>>,
+    light: Arc<dyn Hittable>,
+}
+
+fn final_scene() -> FinalScene {
+    let mut boxes: Vec<Arc<dyn Hittable>> = Vec::new();
+    let ground = Arc::new(Lambertian::new(&Color(0.48, 0.83, 0.53)));
+    let boxes_per_side = 20;
+    for i in 0..boxes_per_side {
+        for j in 0..boxes_per_side {
+            let w = 100.0;
+            let x0 = -1000.0 + (i as f64*w);
+            let z0 = -1000.0 + (j as f64*w);
+            let y0 = 0.0;
+            let x1 = x0 + w;
+            let y1 = random_double_range(1.0, 101.0);
+            let z1 = z0 + w;
+            boxes.push(Arc::new(ABox::new(&Point3(x0, y0, z0), &Point3(x1, y1, z1), ground.clone())));
+        }
+    }
+
+    let mut others: Vec<Arc<dyn Hittable>> = Vec::new();
+    let light: Arc<dyn Hittable> = Arc::new(FlipFace::new(Arc::new(XZRect::new(123.0, 423.0, 147.0, 412.0, 554.0, Arc::new(DiffuseLight::new(Color(7.0, 7.0, 7.0)))))));
+    others.push(light.clone());
+
+    let center1 = Point3(400.0, 400.0, 200.0);
+    others.push(Arc::new(MovingSphere {
+        center0: center1,
+        center1: center1 + Vec3(30.0, 0.0, 0.0),
+        time0: 0.0,
+        time1: 1.0,
+        radius: 50.0,
+        mat_ptr: Arc::new(Lambertian::new(&Color(0.7, 0.3, 0.1))),
+    }));
+    others.push(Arc::new(Sphere { center: Point3(260.0, 150.0, 45.0), radius: 50.0, mat_ptr: Arc::new(Dialectric { ir: 1.5 }) }));
+    others.push(Arc::new(Sphere { center: Point3(0.0, 150.0, 145.0), radius: 50.0, mat_ptr: Arc::new(Metal { albedo: Color(0.8, 0.8, 0.9), fuzz: 1.0 }) }));
+
+    let boundary: Arc<dyn Hittable> = Arc::new(Sphere { center: Point3(360.0, 150.0, 145.0), radius: 70.0, mat_ptr: Arc::new(Dialectric { ir: 1.5 }) });
+    others.push(boundary.clone());
+    others.push(Arc::new(ConstantMedium::new(boundary, 0.2, Color(0.2, 0.4, 0.9))));
+    let boundary: Arc<dyn Hittable> = Arc::new(Sphere { center: Point3(0.0, 0.0, 0.0), radius: 5000.0, mat_ptr: Arc::new(Dialectric { ir: 1.5 }) });
+    others.push(Arc::new(ConstantMedium::new(boundary, 0.0001, Color(1.0, 1.0, 1.0))));
+
+    let pertext = Arc::new(NoiseTexture { noise: Perlin::new(), scale: 0.1 });
+    others.push(Arc::new(Sphere { center: Point3(400.0, 200.0, 400.0), radius: 100.0, mat_ptr: Arc::new(Lambertian::new(&Color(0.2, 0.3, 0.8))) }));
+    others.push(Arc::new(Sphere { center: Point3(220.0, 280.0, 300.0), radius: 80.0, mat_ptr: Arc::new(Lambertian { albedo: pertext }) }));
+
+    let mut spheres: Vec<Arc<dyn Hittable>> = Vec::new();
+    let white = Arc::new(Lambertian::new(&Color(0.73, 0.73, 0.73)));
+    for _ in 0..1000 {
+        spheres.push(Arc::new(Sphere { center: Point3::random_range(0.0, 165.0), radius: 10.0, mat_ptr: white.clone() }));
+    }
+
+    FinalScene {
+        boxes,
+        spheres,
+        others,
+        light,
+    }
+}
+
+// Builds one BVH over a list of objects
+type BvhBuilder = dyn Fn(&[Arc<dyn Hittable>]) -> Arc<dyn Hittable>;
+
+// The world with the boxes and spheres in BVHs made by `bvh`
+fn world(scene: &FinalScene, bvh: &BvhBuilder) -> Arc<dyn Hittable> {
+    let mut objects = scene.others.clone();
+    objects.push(bvh(&scene.boxes));
+    objects.push(Arc::new(Translate::new(Arc::new(RotateY::new(bvh(&scene.spheres), 15.0)), Vec3(-100.0, 270.0, 395.0))));
+    Arc::new(HittableList { objects })
+}
+
+fn camera_settings() -> CameraSettings {
+    let mut camera = CameraSettings::new();
+    camera.lookfrom = Point3(478.0, 278.0, -600.0);
+    camera.lookat = Point3(278.0, 278.0, 0.0);
+    camera.vfov = 40.0;
+    camera
+}
+
+// Closest hits of one ray through every pixel, on one thread
+fn trace_camera_rays(world: &dyn Hittable, size: u64) -> (u64, Duration) {
+    let camera = camera_settings().camera(1.0);
+    let mut rec = HitRecord {
+        p: Point3(0.0, 0.0, 0.0),
+        normal: Vec3(0.0, 0.0, 0.0),
+        mat_ptr: Arc::new(DefaultMaterial),
+        t: 0.0,
+        u: 0.0,
+        v: 0.0,
+        front_face: false,
+    };
+
+    let start = Instant::now();
+    let mut hits = 0;
+    for j in 0..size {
+        for i in 0..size {
+            let r = camera.get_ray((i as f64 + 0.5) / size as f64, (j as f64 + 0.5) / size as f64);
+            if world.hit(&r, 0.001, f64::INFINITY, &mut rec) {
+                hits += 1;
+            }
+        }
+    }
+    (hits, start.elapsed())
+}
+
+fn render(world: Arc<dyn Hittable>, light: Arc<dyn Hittable>) -> Duration {
+    let mut settings = RenderSettings::new(200, 200, 16, 50);
+    settings.seed = Some(1);
+    let integrator = Arc::new(PathIntegrator { world, lights: Some(light) });
+    let renderer = Renderer::new(integrator, camera_settings().camera(1.0), Color(0.0, 0.0, 0.0), settings);
+
+    let start = Instant::now();
+    renderer.render();
+    start.elapsed()
+}
+
+fn main() {
+    seed_random(1);
+    let scene = final_scene();
+    let size = 500;
+
+    let start = Instant::now();
+    let tree = world(&scene, &|objects| {
+        let mut objects = objects.to_vec();
+        let n = objects.len();
+        Arc::new(BvhNode::new(&mut objects, 0, n, 0.0, 1.0).unwrap())
+    });
+    let tree_build = start.elapsed();
+
+    let start = Instant::now();
+    let linear = world(&scene, &|objects| Arc::new(LinearBvh::new(objects, 0.0, 1.0).unwrap()));
+    let linear_build = start.elapsed();
+
+    seed_random(2);
+    let (tree_hits, tree_trace) = trace_camera_rays(tree.as_ref(), size);
+    seed_random(2);
+    let (linear_hits, linear_trace) = trace_camera_rays(linear.as_ref(), size);
+    let tree_render = render(tree, scene.light.clone());
+    let linear_render = render(linear, scene.light.clone());
+
+    let rays = (size * size) as f64;
+    println!("final_scene: {} boxes and {} spheres", scene.boxes.len(), scene.spheres.len());
+    println!("{:<10} {:>10} {:>18} {:>12}", "", "build", "camera rays", "render");
+    println!("{:<10} {:>8.2}ms {:>11.3} Mray/s {:>11.2}s", "BvhNode", tree_build.as_secs_f64()*1e3, rays / tree_trace.as_secs_f64() / 1e6, tree_render.as_secs_f64());
+    println!("{:<10} {:>8.2}ms {:>11.3} Mray/s {:>11.2}s", "LinearBvh", linear_build.as_secs_f64()*1e3, rays / linear_trace.as_secs_f64() / 1e6, linear_render.as_secs_f64());
+    println!("Speedup: {:.2}x on camera rays, {:.2}x on the render", tree_trace.as_secs_f64() / linear_trace.as_secs_f64(), tree_render.as_secs_f64() / linear_render.as_secs_f64());
+    if tree_hits != linear_hits {
+        println!("Warning: the BVHs disagree, {} against {} camera rays hit", tree_hits, linear_hits);
+    }
+}
@@ -21,6 +21,6 @@ fn main() {
         }
     }
     let n = sqrt_n as f64 * sqrt_n as f64;
-    println!("Regular    Estimate of Pi = {:.10}", (4.0*(inside_circle as f64)) / n as f64);
-    println!("Stratified Estimate of Pi = {:.10}", (4.0*(inside_circle_stratified as f64)) / n as f64);
+    println!("Regular    Estimate of Pi = {:.10}", (4.0*(inside_circle as f64)) / n);
+    println!("Stratified Estimate of Pi = {:.10}", (4.0*(inside_circle_stratified as f64)) / n);
 }
\ No newline at end of file
@@ -1,29 +1,21 @@
 use ray_tracing_rust::material::Dialectric;
-use ray_tracing_rust::material::ScatterRecord;
-use ray_tracing_rust::pdf::HittablePdf;
-use ray_tracing_rust::pdf::MixturePdf;
-use ray_tracing_rust::pdf::SpherePdf;
 use ray_tracing_rust::vec3::Vec3;
 use ray_tracing_rust::vec3::Point3;
 use ray_tracing_rust::vec3::Color;
 
-use ray_tracing_rust::color::write_color;
-
-use ray_tracing_rust::camera::Camera;
-
-use ray_tracing_rust::ray::Ray;
+use ray_tracing_rust::scene::Scene;
+use ray_tracing_rust::scene::CameraSettings;
 
 use ray_tracing_rust::hittable;
-use ray_tracing_rust::hittable::HitRecord;
 use ray_tracing_rust::hittable::Hittable;
 
 use ray_tracing_rust::material;
-use ray_tracing_rust::material::DefaultMaterial;
 use ray_tracing_rust::material::Lambertian;
 
 use ray_tracing_rust::hittable_list::HittableList;
 
-use ray_tracing_rust::rtweekend::random_double;
+use ray_tracing_rust::light_sampler::LightSampler;
+use ray_tracing_rust::light_sampler::LightStrategy;
 
 use ray_tracing_rust::aarect;
 
@@ -31,152 +23,111 @@ use ray_tracing_rust::abox;
 
 use ray_tracing_rust::sphere::Sphere;
 
-use ray_tracing_rust::pdf::Pdf;
-
-use std::vec::Vec;
-
-use std::rc::Rc;
-use std::cell::RefCell;
-
-fn ray_color(r: &Ray, background: &Color, world: &mut dyn Hittable, depth: u64, light_ptr: Rc<RefCell<dyn Hittable>>) -> Color {
-    let mut rec: HitRecord = HitRecord {
-        p: Point3(0.0, 0.0, 0.0),
-        normal: Vec3(0.0, 0.0, 0.0),
-        mat_ptr: Rc::new(RefCell::new(DefaultMaterial)),
-        t: 0.0,
-        u: 0.0,
-        v: 0.0,
-        front_face: false,
-    };
-
-    // Limits the callback length. Guards against stack overflow
-    if depth <= 0 {
-        return Color(0.0, 0.0, 0.0);
-    }
-
-    if !world.hit(r, 0.001, f64::INFINITY, &mut rec) {
-        return *background;    
-    }
-
-    let mut srec: ScatterRecord = ScatterRecord { 
-        attenuation: Vec3(0.0, 0.0, 0.0), 
-        pdf_ptr: Rc::new(RefCell::new(SpherePdf())), 
-        skip_pdf: false, 
-        skip_pdf_ray: 
-            Ray { 
-                origin: Vec3(0.0, 0.0, 0.0), 
-                direction: Vec3(0.0, 0.0, 0.0), 
-                tm: 0.0 } 
-    };
-    let color_from_emission = rec.mat_ptr.borrow().emitted(r, &rec, rec.u, rec.v, &rec.p);
-
-    if !rec.mat_ptr.borrow().scatter(r, &rec, &mut srec) {
-        return color_from_emission;
-    }
-
-    if srec.skip_pdf {
-        return srec.attenuation * ray_color(&mut srec.skip_pdf_ray, background, world, depth-1, light_ptr)
-    }
+use ray_tracing_rust::renderer::RenderSettings;
 
-    let light_pdf = Rc::new(RefCell::new(HittablePdf {objects: light_ptr.clone() as Rc<RefCell<dyn Hittable>>, origin: rec.p}));
-    let mut p = MixturePdf(light_pdf, srec.pdf_ptr);
+use ray_tracing_rust::rtweekend::seed_random;
 
-    let mut scattered = Ray {origin: rec.p, direction: p.generate(), tm: r.time()};
-    let pdf_val = p.value(&scattered.direction());
+use ray_tracing_rust::cli::Options;
 
-    let scattering_pdf: f64 = rec.mat_ptr.borrow().scattering_pdf(r, &rec, &mut scattered);
+use std::vec::Vec;
 
-    let color_from_scatter = (srec.attenuation * scattering_pdf * ray_color(&scattered, background, world, depth-1, light_ptr)) / pdf_val;
+use std::sync::Arc;
 
-    return color_from_emission + color_from_scatter;
-}
+use std::process;
 
-fn cornell_box() -> HittableList {
+fn cornell_box() -> Scene {
     let mut objects: HittableList = HittableList {objects: Vec::new() };
 
-    let red   = Rc::new(RefCell::new(Lambertian::new( &Color(0.65, 0.05, 0.05))));
-    let white = Rc::new(RefCell::new(Lambertian::new( &Color(0.73, 0.73, 0.73))));
-    let green = Rc::new(RefCell::new(Lambertian::new( &Color(0.12, 0.45, 0.15))));
-    let light = Rc::new(RefCell::new(material::DiffuseLight::new( Color(15.0, 15.0, 15.0))));
-
-    objects.add(Rc::new(RefCell::new( aarect::YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green))));
-    objects.add(Rc::new(RefCell::new( aarect::YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red))));
-    objects.add(Rc::new(RefCell::new( aarect::XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light))));
-    objects.add(Rc::new(RefCell::new( aarect::XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white.clone()))));
-    objects.add(Rc::new(RefCell::new( aarect::XZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white.clone()))));
-    objects.add(Rc::new(RefCell::new( aarect::XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white.clone()))));
+    let red   = Arc::new(Lambertian::new( &Color(0.65, 0.05, 0.05)));
+    let white = Arc::new(Lambertian::new( &Color(0.73, 0.73, 0.73)));
+    let green = Arc::new(Lambertian::new( &Color(0.12, 0.45, 0.15)));
+    let light = Arc::new(material::DiffuseLight::new( Color(15.0, 15.0, 15.0)));
+
+    objects.add(Arc::new( aarect::YZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, green)));
+    objects.add(Arc::new( aarect::YZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, red)));
+    objects.add(Arc::new( hittable::FlipFace::new(Arc::new( aarect::XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light)))));
+    objects.add(Arc::new( aarect::XZRect::new(0.0, 555.0, 0.0, 555.0, 0.0, white.clone())));
+    objects.add(Arc::new( aarect::XZRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
+    objects.add(Arc::new( aarect::XYRect::new(0.0, 555.0, 0.0, 555.0, 555.0, white.clone())));
     
-    // let aluminium = Rc::new(RefCell::new(Metal{albedo: Color(0.8, 0.85, 0.88), fuzz: 0.0}));
-    let mut box1: Rc<RefCell<dyn Hittable>> = Rc::new(RefCell::new(abox::ABox::new(&Point3(0.0, 0.0, 0.0), &Point3(165.0, 330.0, 165.0), white)));
-    box1 = Rc::new(RefCell::new(hittable::RotateY::new(box1, 15.0)));
-    box1 = Rc::new(RefCell::new(hittable::Translate::new(box1, Vec3(265.0, 0.0, 295.0))));
+    // let aluminium = Arc::new(Metal{albedo: Color(0.8, 0.85, 0.88), fuzz: 0.0});
+    let mut box1: Arc<dyn Hittable> = Arc::new(abox::ABox::new(&Point3(0.0, 0.0, 0.0), &Point3(165.0, 330.0, 165.0), white));
+    box1 = Arc::new(hittable::RotateY::new(box1, 15.0));
+    box1 = Arc::new(hittable::Translate::new(box1, Vec3(265.0, 0.0, 295.0)));
     objects.add(box1);
 
-    // let mut box2: Rc<RefCell<dyn Hittable>> = Rc::new(RefCell::new(abox::ABox::new(&Point3(0.0, 0.0, 0.0), &Point3(165.0, 165.0, 165.0), white.clone())));
-    // box2 = Rc::new(RefCell::new(hittable::RotateY::new(box2, -18.0)));
-    // box2 = Rc::new(RefCell::new(hittable::Translate::new(box2, Vec3(130.0, 0.0, 65.0))));
+    // let mut box2: Arc<dyn Hittable> = Arc::new(abox::ABox::new(&Point3(0.0, 0.0, 0.0), &Point3(165.0, 165.0, 165.0), white.clone()));
+    // box2 = Arc::new(hittable::RotateY::new(box2, -18.0));
+    // box2 = Arc::new(hittable::Translate::new(box2, Vec3(130.0, 0.0, 65.0)));
     // objects.add(box2);
     // Glass Sphere
-    let glass = Rc::new(RefCell::new(Dialectric {ir: 1.5}));
-    objects.add(Rc::new(RefCell::new(Sphere { center: Point3(190.0, 90.0, 190.0), radius: 90.0, mat_ptr: glass })));
+    let glass = Arc::new(Dialectric {ir: 1.5});
+    let glass_sphere: Arc<dyn Hittable> = Arc::new(Sphere { center: Point3(190.0, 90.0, 190.0), radius: 90.0, mat_ptr: glass });
+    objects.add(glass_sphere.clone());
+
+    // Light Sources: the light is found in the world, and the glass sphere
+    // is sampled too, as in the book
+    let world: Arc<dyn Hittable> = Arc::new(objects);
+    let lights = LightSampler::from_world(&world, &[glass_sphere], LightStrategy::Tree, 0.0, 1.0).unwrap();
+
+    // Camera
+    let camera = CameraSettings {
+        lookfrom: Point3(278.0, 278.0, -800.0),
+        lookat: Point3(278.0, 278.0, 0.0),
+        vup: Vec3(0.0, 1.0, 0.0),
+        vfov: 40.0,
+        aperture: 0.0,
+        focus_dist: 10.0,
+        time0: 0.0,
+        time1: 1.0,
+    };
 
-    return objects;
+    // Image
+    let aspect_ratio: f64 = 1.0;
+    let image_width: u64 = 500;
+    let image_height: u64 = (image_width as f64/aspect_ratio) as u64;
+    let samples_per_pixel: u64 = 1000;
+    const MAX_DEPTH: u64 = 50;
+
+    Scene {
+        camera,
+        background: Color(0.0, 0.0, 0.0),
+        world,
+        lights: lights.map(|lights| Arc::new(lights) as Arc<dyn Hittable>),
+        environment: None,
+        delta_lights: Vec::new(),
+        settings: RenderSettings::new(image_width, image_height, samples_per_pixel, MAX_DEPTH),
+    }
 }
 
+const SCENES: [&str; 1] = ["cornell_box"];
+
 fn main() {
-        // Light Sources
-        let lights: Rc<RefCell<HittableList>>  = Rc::new(RefCell::new(HittableList {objects: Vec::new() }));
-        let m = Rc::new(RefCell::new(DefaultMaterial));
-        lights.borrow_mut().add(Rc::new(RefCell::new(aarect::XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, m.clone()))));
-        lights.borrow_mut().add(Rc::new(RefCell::new(Sphere {
-            center: Point3(190.0, 90.0, 190.0),
-            radius: 90.0,
-            mat_ptr: m.clone(),
-        })));
-        // let light_tex = Rc::new(RefCell::new(material::DiffuseLight::new( Color(15.0, 15.0, 15.0))));
-        // let light = Rc::new(RefCell::new( aarect::XZRect::new(213.0, 343.0, 227.0, 332.0, 554.0, light_tex)));
-        // let light_sphere = Rc::new(RefCell::new(Sphere {
-        //     center: Point3(190.0, 90.0, 190.0),
-        //     radius: 90.0,
-        //     mat_ptr: Rc::new(RefCell::new(DefaultMaterial)),
-        // }));
-
-        // Image
-        let aspect_ratio: f64 = 1.0/1.0;
-        let image_width: u64 = 500;
-        let image_height: u64 = (image_width as f64/aspect_ratio) as u64;
-        let samples_per_pixel: u64 = 1000;
-        const MAX_DEPTH: u64 = 50;
-    
-        // World
-        let mut world: HittableList = cornell_box();
-        let background: Color = Color(0.0, 0.0, 0.0);
-
-        // Camera
-        let lookfrom: Point3 = Point3(278.0, 278.0, -800.0);
-        let lookat: Point3 = Point3(278.0, 278.0, 0.0);
-        let vup: Vec3 = Vec3(0.0, 1.0, 0.0);
-        let dist_to_focus: f64 = 10.0;
-        let aperture: f64 = 0.0;
-        let vfov: f64 = 40.0;
-        let time0: f64 = 0.0;
-        let time1: f64 = 1.0;
-        
-        let cam: Camera = Camera::new(lookfrom, lookat, vup, vfov, aspect_ratio, aperture, dist_to_focus, time0, time1); 
-        // Render
-        print!("P3\n{} {}\n255\n", image_width, image_height);
-        for j in (0..image_height).rev() {
-            eprintln!("Scanlines remaining: {}", j);
-            for i in 0..image_width {
-                let mut pixel_color: Color = Color(0.0, 0.0, 0.0);
-                for _k in 0..samples_per_pixel {
-                    let u: f64 = (i as f64 + random_double()) / (image_width - 1) as f64;
-                    let v: f64 = (j as f64 + random_double()) / (image_height - 1) as f64;
-                    let r: Ray = cam.get_ray(u, v);
-                    pixel_color += ray_color(&r, &background, &mut world, MAX_DEPTH, lights.clone());
-                }
-                write_color(pixel_color, samples_per_pixel);
-            }
-        }
+    let options = Options::from_env(&SCENES);
+    if let Some(seed) = options.seed {
+        seed_random(seed);
+    }
+
+    // World: a built-in scene, or else a scene file
+    let name = options.scene.clone().unwrap_or(String::from(SCENES[0]));
+    let mut scene: Scene = match name.as_str() {
+        "cornell_box" => cornell_box(),
+        path => match Scene::load(path) {
+            Ok(scene) => scene,
+            Err(e) => {
+                eprintln!("Could not load scene '{}': {}", path, e);
+                eprintln!("Built-in scenes: {}", SCENES.join(", "));
+                process::exit(1);
+            },
+        },
+    };
+    options.apply(&mut scene.settings);
+
+    // Render
+    let renderer = scene.renderer();
+    let film = options.render(&renderer);
+
+    // Output: an image file if a path is given, otherwise a PPM on stdout
+    options.write(&film);
     eprintln!("Done. ");
-}
\ No newline at end of file
+}
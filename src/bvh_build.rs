@@ -0,0 +1,305 @@
+// Builds bounding volume hierarchies with the surface area heuristic (SAH).
+//
+// The builder only looks at the bounding boxes of the primitives, so the BVH
+// node types of both books share it. At each node the primitive centroids are
+// sorted into BIN_COUNT bins along each axis, and the split between two bins
+// with the lowest expected cost of tracing a ray is chosen. A node becomes a
+// leaf when splitting would cost more than testing its primitives, which is
+// only allowed up to MAX_LEAF_SIZE primitives.
+//
+// Nothing is random, so the same boxes always give the same tree.
+
+use crate::aabb::AABB;
+use crate::aabb::surrounding_box;
+
+use crate::vec3::Point3;
+
+use std::fmt;
+
+pub const BIN_COUNT: usize = 16;
+pub const MAX_LEAF_SIZE: usize = 4;
+
+// Cost of visiting a node, relative to testing one primitive
+const TRAVERSAL_COST: f64 = 0.125;
+
+pub enum BuildNode {
+    // `count` primitives starting at `first` in the order of the build
+    Leaf { bounds: AABB, first: usize, count: usize },
+    Interior { bounds: AABB, axis: u32, left: Box<BuildNode>, right: Box<BuildNode> },
+}
+
+impl BuildNode {
+    pub fn bounds(&self) -> AABB {
+        match self {
+            BuildNode::Leaf { bounds, .. } => *bounds,
+            BuildNode::Interior { bounds, .. } => *bounds,
+        }
+    }
+}
+
+pub struct BvhBuild {
+    pub root: BuildNode,
+    // Index of the primitive at each position of the tree order. Leaves refer
+    // to ranges of this list.
+    pub order: Vec<usize>,
+    pub node_count: usize,
+}
+
+#[derive(Debug)]
+pub enum BvhError {
+    Empty,
+    // The primitive at `index` of the input has no bounding box
+    NoBoundingBox { index: usize },
+}
+
+impl fmt::Display for BvhError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BvhError::Empty => write!(f, "a BVH needs at least one object"),
+            BvhError::NoBoundingBox { index } => write!(f, "object {} has no bounding box, so it cannot be put in a BVH", index),
+        }
+    }
+}
+
+impl std::error::Error for BvhError {}
+
+// A primitive while the tree is being built
+struct Item {
+    index: usize,
+    bounds: AABB,
+    centroid: Point3,
+}
+
+pub fn build_bvh(boxes: &[AABB]) -> Result<BvhBuild, BvhError> {
+    if boxes.is_empty() {
+        return Err(BvhError::Empty);
+    }
+
+    let mut items: Vec<Item> = boxes.iter().enumerate().map(|(i, b)| Item {
+        index: i,
+        bounds: *b,
+        centroid: 0.5*(b.minimum + b.maximum),
+    }).collect();
+
+    let mut node_count = 0;
+    let root = build_node(&mut items, 0, &mut node_count);
+
+    Ok(BvhBuild {
+        root,
+        order: items.iter().map(|item| item.index).collect(),
+        node_count,
+    })
+}
+
+pub fn surface_area(b: &AABB) -> f64 {
+    let d = b.maximum - b.minimum;
+    2.0*(d.x()*d.y() + d.y()*d.z() + d.z()*d.x())
+}
+
+fn build_node(items: &mut [Item], offset: usize, node_count: &mut usize) -> BuildNode {
+    *node_count += 1;
+    let n = items.len();
+    let bounds = union(items.iter().map(|item| item.bounds));
+    let leaf = BuildNode::Leaf { bounds, first: offset, count: n };
+    if n == 1 {
+        return leaf;
+    }
+
+    let centroid_bounds = union(items.iter().map(|item| AABB { minimum: item.centroid, maximum: item.centroid }));
+    let mut best: Option<(f64, u32, usize)> = None;
+
+    for axis in 0..3 {
+        let lo = centroid_bounds.minimum[axis];
+        let extent = centroid_bounds.maximum[axis] - lo;
+        if extent <= 0.0 {
+            continue;
+        }
+
+        let mut counts = [0usize; BIN_COUNT];
+        let mut boxes: [Option<AABB>; BIN_COUNT] = [None; BIN_COUNT];
+        for item in items.iter() {
+            let b = bin(item.centroid[axis], lo, extent);
+            counts[b] += 1;
+            boxes[b] = Some(match boxes[b] {
+                Some(bin_box) => surrounding_box(&bin_box, &item.bounds),
+                None => item.bounds,
+            });
+        }
+
+        // Area and count left of each split, then the same from the right
+        let mut left_cost = [0.0; BIN_COUNT];
+        let mut left_count = [0usize; BIN_COUNT];
+        let mut running: Option<AABB> = None;
+        let mut count = 0;
+        for split in 0..BIN_COUNT - 1 {
+            running = merge(running, boxes[split]);
+            count += counts[split];
+            left_count[split] = count;
+            left_cost[split] = running.map_or(0.0, |b| count as f64 * surface_area(&b));
+        }
+        let mut running: Option<AABB> = None;
+        let mut count = 0;
+        for split in (0..BIN_COUNT - 1).rev() {
+            running = merge(running, boxes[split + 1]);
+            count += counts[split + 1];
+            if left_count[split] == 0 || count == 0 {
+                continue;
+            }
+            let right_cost = running.map_or(0.0, |b| count as f64 * surface_area(&b));
+
+            let cost = TRAVERSAL_COST + (left_cost[split] + right_cost) / surface_area(&bounds).max(f64::MIN_POSITIVE);
+            if best.is_none_or(|(best_cost, _, _)| cost < best_cost) {
+                best = Some((cost, axis, split));
+            }
+        }
+    }
+
+    let (axis, mid) = match best {
+        Some((cost, axis, split)) => {
+            if n <= MAX_LEAF_SIZE && cost >= n as f64 {
+                return leaf;
+            }
+            let lo = centroid_bounds.minimum[axis];
+            let extent = centroid_bounds.maximum[axis] - lo;
+            (axis, partition(items, |item| bin(item.centroid[axis], lo, extent) <= split))
+        },
+        // All centroids are in the same place, so no split can tell them apart
+        None => {
+            if n <= MAX_LEAF_SIZE {
+                return leaf;
+            }
+            (0, n / 2)
+        },
+    };
+
+    let (left_items, right_items) = items.split_at_mut(mid);
+    let left = build_node(left_items, offset, node_count);
+    let right = build_node(right_items, offset + mid, node_count);
+
+    BuildNode::Interior {
+        bounds,
+        axis,
+        left: Box::new(left),
+        right: Box::new(right),
+    }
+}
+
+fn bin(c: f64, lo: f64, extent: f64) -> usize {
+    (((c - lo) / extent * BIN_COUNT as f64) as usize).min(BIN_COUNT - 1)
+}
+
+fn union(mut boxes: impl Iterator<Item = AABB>) -> AABB {
+    let first = boxes.next().unwrap();
+    boxes.fold(first, |a, b| surrounding_box(&a, &b))
+}
+
+fn merge(a: Option<AABB>, b: Option<AABB>) -> Option<AABB> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(surrounding_box(&a, &b)),
+        (a, None) => a,
+        (None, b) => b,
+    }
+}
+
+// Moves the items for which `left` holds to the front, keeping the order on
+// each side, and returns how many there are.
+fn partition(items: &mut [Item], left: impl Fn(&Item) -> bool) -> usize {
+    // The sort is stable, so this is a stable partition
+    items.sort_by_key(|item| !left(item));
+    items.iter().take_while(|item| left(item)).count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::hittable::Hittable;
+    use crate::hittable::HitRecord;
+    use crate::linear_bvh::LinearBvh;
+    use crate::material::DefaultMaterial;
+    use crate::ray::Ray;
+    use crate::sphere::Sphere;
+
+    use std::sync::Arc;
+
+    // Small boxes scattered by a fixed sequence, so every run sees the same ones
+    fn scattered_boxes(n: usize) -> Vec<AABB> {
+        let mut state: u64 = 12345;
+        let mut next = || {
+            state = state.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+            (state >> 11) as f64 / (1u64 << 53) as f64
+        };
+        (0..n).map(|_| {
+            let minimum = Point3(10.0*next(), 10.0*next(), 10.0*next());
+            let size = 0.5*next();
+            AABB { minimum, maximum: minimum + Point3(size, size, size) }
+        }).collect()
+    }
+
+    // The shape of the tree, with the primitives of each leaf
+    fn describe(node: &BuildNode, order: &[usize]) -> String {
+        match node {
+            BuildNode::Leaf { first, count, .. } => format!("{:?}", &order[*first..first + count]),
+            BuildNode::Interior { axis, left, right, .. } => format!("({} {} {})", axis, describe(left, order), describe(right, order)),
+        }
+    }
+
+    fn leaves(node: &BuildNode, order: &[usize], found: &mut Vec<usize>) {
+        match node {
+            BuildNode::Leaf { first, count, .. } => {
+                assert!(*count >= 1);
+                found.extend_from_slice(&order[*first..first + count]);
+            },
+            BuildNode::Interior { left, right, .. } => {
+                leaves(left, order, found);
+                leaves(right, order, found);
+            },
+        }
+    }
+
+    #[test]
+    fn the_same_boxes_give_the_same_tree() {
+        let boxes = scattered_boxes(500);
+        let first = build_bvh(&boxes).unwrap();
+        let second = build_bvh(&boxes).unwrap();
+        assert_eq!(first.order, second.order);
+        assert_eq!(first.node_count, second.node_count);
+        assert_eq!(describe(&first.root, &first.order), describe(&second.root, &second.order));
+    }
+
+    #[test]
+    fn every_primitive_is_in_exactly_one_leaf() {
+        // Also boxes all in the same place, which no split can tell apart
+        let mut boxes = scattered_boxes(300);
+        boxes.extend(std::iter::repeat_n(boxes[0], 20));
+        let build = build_bvh(&boxes).unwrap();
+
+        let mut found = Vec::new();
+        leaves(&build.root, &build.order, &mut found);
+        found.sort();
+        assert_eq!(found, (0..boxes.len()).collect::<Vec<usize>>());
+    }
+
+    struct Unbounded;
+
+    impl Hittable for Unbounded {
+        fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64, _rec: &mut HitRecord) -> bool {
+            false
+        }
+
+        fn bounding_box(&self, _time0: f64, _time1: f64, _output_box: &mut AABB) -> bool {
+            false
+        }
+    }
+
+    #[test]
+    fn objects_without_a_box_are_reported() {
+        assert!(matches!(build_bvh(&[]), Err(BvhError::Empty)));
+
+        let objects: Vec<Arc<dyn Hittable>> = vec![
+            Arc::new(Sphere { center: Point3(0.0, 0.0, 0.0), radius: 1.0, mat_ptr: Arc::new(DefaultMaterial) }),
+            Arc::new(Unbounded),
+        ];
+        assert!(matches!(LinearBvh::new(&objects, 0.0, 1.0), Err(BvhError::NoBoundingBox { index: 1 })));
+    }
+}
@@ -1,26 +1,28 @@
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
 use crate::vec3::Point3;
 
-use rand::Rng;
-
 use crate::hittable_first_week::Hittable;
 use crate::hittable_first_week::HitRecord;
 use crate::ray_first_week::Ray;
+use crate::hittable_list_first_week::HittableList;
 
 use crate::aabb_first_week::AABB;
-use crate::aabb_first_week::surrounding_box;
+
+use crate::bvh_build::build_bvh;
+use crate::bvh_build::BuildNode;
+use crate::bvh_build::BvhError;
+use crate::aabb::AABB as BuildBox;
 
 
 pub struct BvhNode {
-    pub left: Rc<RefCell<dyn Hittable>>,
-    pub right: Rc<RefCell<dyn Hittable>>,
+    pub left: Arc<dyn Hittable>,
+    pub right: Arc<dyn Hittable>,
     pub abox: AABB
 }
 
 impl Hittable for BvhNode {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         if !self.abox.hit(r, t_min, t_max) {
             // eprintln!("Box no hit parameters, min: {}, max: {}", self.abox.min(), self.abox.max());
             return false;
@@ -28,111 +30,66 @@ impl Hittable for BvhNode {
             // eprintln!("Box hit parameters, min: {}, max: {}", self.abox.min(), self.abox.max());
         }
 
-        let hit_left: bool = self.left.borrow_mut().hit(r, t_min, t_max, rec);
-        let hit_right: bool = self.right.borrow_mut().hit(r, t_min, if hit_left { rec.t } else { t_max }, rec);
+        let hit_left: bool = self.left.hit(r, t_min, t_max, rec);
+        let hit_right: bool = self.right.hit(r, t_min, if hit_left { rec.t } else { t_max }, rec);
 
         hit_left || hit_right
     }
     
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
-        *output_box = self.abox.clone();
+        *output_box = self.abox;
         true
     }
 }
 
 impl BvhNode {
-    pub fn new(src_objects: &mut Vec<Rc<RefCell<dyn Hittable>>>, start: usize, end:usize, time0: f64, time1: f64) -> Self {
-        let left: Rc<RefCell<dyn Hittable>>;
-        let right: Rc<RefCell<dyn Hittable>>;
-        
-        let mut objects = src_objects;
-
-        let axis: i64 = rand::thread_rng().gen_range(0..3);
-        let comparator: &dyn Fn(&Rc<RefCell<dyn Hittable>>, &Rc<RefCell<dyn Hittable>>) -> std::cmp::Ordering;
-        if axis == 0 { 
-            comparator = &box_x_compare; 
-        } 
-        else if axis == 1 { 
-            comparator = &box_y_compare;
-        } 
-        else { 
-            comparator = &box_z_compare;
-        }
-        
-        let object_span: usize = end - start;
-
-        if object_span == 1 {
-            left = Rc::clone(&objects[start]);
-            right = Rc::clone(&objects[start]);
-        } else if object_span == 2 {
-            if comparator(&objects[start], &objects[start+1]) == std::cmp::Ordering::Less {
-                left = Rc::clone(&objects[start]);
-                right = Rc::clone(&objects[start+1]);
-            } else {
-                left = Rc::clone(&objects[start+1]);
-                right = Rc::clone(&objects[start]);
+    // Builds the tree over src_objects[start..end] with the surface area
+    // heuristic, see bvh_build.rs. The objects in that range are put in the
+    // order of the tree.
+    pub fn new(src_objects: &mut [Arc<dyn Hittable>], start: usize, end:usize, time0: f64, time1: f64) -> Result<Self, BvhError> {
+        let objects = &mut src_objects[start..end];
+
+        let mut boxes: Vec<BuildBox> = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            let mut output_box = AABB {
+                minimum: Point3(0.0, 0.0, 0.0),
+                maximum: Point3(0.0, 0.0, 0.0)
+            };
+            if !object.bounding_box(time0, time1, &mut output_box) {
+                return Err(BvhError::NoBoundingBox { index: start + i });
             }
-        } else {
-            objects[start..end].sort_unstable_by(comparator);
-
-            let mid: usize = start + (object_span/2);
-            left = Rc::new(RefCell::new(BvhNode::new(&mut objects, start, mid, time0, time1)));
-            right = Rc::new(RefCell::new(BvhNode::new(&mut objects, mid, end, time0, time1)));
-        }
-
-        let mut box_left: AABB = AABB {
-            minimum: Point3(0.0, 0.0, 0.0),
-            maximum: Point3(0.0, 0.0, 0.0)
-        };
-        let mut box_right: AABB = AABB {
-            minimum: Point3(0.0, 0.0, 0.0),
-            maximum: Point3(0.0, 0.0, 0.0)
-        };
-
-        if !left.borrow().bounding_box(time0, time1, &mut box_left) || !right.borrow().bounding_box(time0, time1,  &mut box_right) {
-            println!("No bounding box in bvh_node constructor.");
+            boxes.push(BuildBox { minimum: output_box.minimum, maximum: output_box.maximum });
         }
 
-        BvhNode {
-            left: left,
-            right: right,
-            abox: surrounding_box(&box_left, &box_right),
+        let build = build_bvh(&boxes)?;
+        let ordered: Vec<Arc<dyn Hittable>> = build.order.iter().map(|&i| objects[i].clone()).collect();
+        objects.clone_from_slice(&ordered);
+
+        match &build.root {
+            BuildNode::Interior { bounds, left, right, .. } => Ok(BvhNode {
+                left: BvhNode::subtree(left, objects),
+                right: BvhNode::subtree(right, objects),
+                abox: AABB { minimum: bounds.minimum, maximum: bounds.maximum },
+            }),
+            // Too few objects to be worth splitting. The right side is left
+            // empty, so nothing is tested twice.
+            leaf => Ok(BvhNode {
+                left: BvhNode::subtree(leaf, objects),
+                right: Arc::new(HittableList { objects: Vec::new() }),
+                abox: AABB { minimum: leaf.bounds().minimum, maximum: leaf.bounds().maximum },
+            }),
         }
     }
-}
 
-fn box_compare(a: &Rc<RefCell<dyn Hittable>>, b: &Rc<RefCell<dyn Hittable>>, axis: u32) -> std::cmp::Ordering {
-    let mut box_a: AABB = AABB {
-        minimum: Point3(0.0, 0.0, 0.0),
-        maximum: Point3(0.0, 0.0, 0.0)
-    };
-    let mut box_b: AABB= AABB {
-        minimum: Point3(0.0, 0.0, 0.0),
-        maximum: Point3(0.0, 0.0, 0.0)
-    };
-    
-    if !a.borrow_mut().bounding_box(0.0, 0.0, &mut box_a) || !b.borrow_mut().bounding_box(0.0, 0.0,&mut box_b) {
-        println!("No bounding box in bvh_node consctructor.");
+    fn subtree(node: &BuildNode, objects: &[Arc<dyn Hittable>]) -> Arc<dyn Hittable> {
+        match node {
+            BuildNode::Leaf { first, count: 1, .. } => objects[*first].clone(),
+            BuildNode::Leaf { first, count, .. } => Arc::new(HittableList { objects: objects[*first..*first + *count].to_vec() }),
+            BuildNode::Interior { bounds, left, right, .. } => Arc::new(BvhNode {
+                left: BvhNode::subtree(left, objects),
+                right: BvhNode::subtree(right, objects),
+                abox: AABB { minimum: bounds.minimum, maximum: bounds.maximum },
+            }),
+        }
     }
-    box_a.min()[axis].partial_cmp(&box_b.min()[axis]).expect("unexpected NaN in bounding box y")
-
-    // if box_a.min()[axis] < box_b.min()[axis] {
-    //     return std::cmp::Ordering::Less;
-    // } else if box_a.max()[axis] > box_b.max()[axis] {
-    //     return std::cmp::Ordering::Greater;
-    // } else {
-    //     return std::cmp::Ordering::Equal;
-    // }
 }
-
-fn box_x_compare(a: &Rc<RefCell<dyn Hittable>>, b: &Rc<RefCell<dyn Hittable>>) -> std::cmp::Ordering {
-    box_compare(a, b, 0)
-}
-
-fn box_y_compare(a: &Rc<RefCell<dyn Hittable>>, b: &Rc<RefCell<dyn Hittable>>) -> std::cmp::Ordering {
-    box_compare(a, b, 1)
-}
-
-fn box_z_compare(a: &Rc<RefCell<dyn Hittable>>, b: &Rc<RefCell<dyn Hittable>>) -> std::cmp::Ordering {
-    box_compare(a, b, 2)
-}
\ No newline at end of file
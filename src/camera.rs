@@ -28,10 +28,12 @@ impl Camera {
         Ray {
             origin: self.origin + offset,
             direction: self.lower_left_corner + (s*self.horizontal) + (t*self.vertical) - self.origin - offset,
-            tm: random_double_range(self.time0, self.time1)
+            tm: random_double_range(self.time0, self.time1),
+            wavelength: 0.0
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub fn new(lookfrom: Point3, lookat: Point3, vup: Vec3, vfov: f64, aspect_ratio: f64, aperture: f64, focus_dist: f64, _time0: f64, _time1: f64) -> Self  {
             let theta: f64 = degrees_to_radians(vfov);
             let h = (theta/2.0).tan();
@@ -51,9 +53,9 @@ impl Camera {
                 horizontal: hor,
                 vertical: ver,
                 lower_left_corner: lookfrom - hor/2.0 - ver/2.0 - focus_dist*w,
-                u: u,
-                v: v,
-                w: w,
+                u,
+                v,
+                w,
                 lens_radius: aperture/2.0,
                 time0: _time0,
                 time1: _time1,
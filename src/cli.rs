@@ -0,0 +1,292 @@
+// Command line options shared by the render binaries.
+
+use crate::film::Film;
+use crate::film::OutputFormat;
+
+use crate::renderer::Renderer;
+use crate::renderer::RenderSettings;
+use crate::renderer::MIN_IMAGE_SIZE;
+
+use std::env;
+use std::path::Path;
+use std::process;
+use std::time::Duration;
+
+// Options that are followed by a value
+const VALUE_FLAGS: [&str; 11] = ["--scene", "--width", "--height", "--spp", "--max-depth", "--output", "-o", "--format", "--threads", "--seed", "--time-budget"];
+
+// Every field is None when the option was not given, so the scene's own
+// settings are kept.
+#[derive(Default)]
+pub struct Options {
+    // Name of a built-in scene, or path to a scene file
+    pub scene: Option<String>,
+    pub width: Option<u64>,
+    pub height: Option<u64>,
+    pub samples_per_pixel: Option<u64>,
+    pub max_depth: Option<u64>,
+    pub output: Option<String>,
+    pub format: Option<OutputFormat>,
+    pub threads: Option<usize>,
+    pub seed: Option<u64>,
+    pub time_budget: Option<Duration>,
+    pub help: bool,
+}
+
+impl Options {
+    // Parses the arguments after the program name. A bare argument is the
+    // output file, as in `the_rest_of_your_life c.png`.
+    pub fn parse(args: &[String]) -> Result<Options, String> {
+        let mut options = Options::default();
+        let mut i = 0;
+
+        while i < args.len() {
+            let arg = &args[i];
+            i += 1;
+
+            if !arg.starts_with('-') {
+                if options.output.is_some() {
+                    return Err(format!("unexpected argument '{}'", arg));
+                }
+                options.output = Some(arg.clone());
+                continue;
+            }
+
+            // Both `--flag value` and `--flag=value` are accepted
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag, Some(value.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if flag == "--help" || flag == "-h" {
+                options.help = true;
+                continue;
+            }
+            if !VALUE_FLAGS.contains(&flag) {
+                return Err(format!("unknown option '{}'", flag));
+            }
+
+            let value = match inline_value {
+                Some(value) => value,
+                None if i < args.len() => {
+                    i += 1;
+                    args[i - 1].clone()
+                },
+                None => return Err(format!("{} needs a value", flag)),
+            };
+
+            match flag {
+                "--scene" => options.scene = Some(value),
+                "--width" => options.width = Some(parse_dimension(flag, &value)?),
+                "--height" => options.height = Some(parse_dimension(flag, &value)?),
+                "--spp" => options.samples_per_pixel = Some(parse_count(flag, &value)?),
+                "--max-depth" => options.max_depth = Some(parse_count(flag, &value)?),
+                "--output" | "-o" => options.output = Some(value),
+                "--format" => match OutputFormat::from_extension(&value) {
+                    Some(format) => options.format = Some(format),
+                    None => return Err(format!("unknown image format '{}'", value)),
+                },
+                "--threads" => options.threads = Some(parse_count(flag, &value)? as usize),
+                "--seed" => match value.parse::<u64>() {
+                    Ok(seed) => options.seed = Some(seed),
+                    Err(_) => return Err(format!("{} {} is not a whole number", flag, value)),
+                },
+                "--time-budget" => match value.parse::<f64>() {
+                    Ok(secs) if secs > 0.0 && secs.is_finite() => options.time_budget = Some(Duration::from_secs_f64(secs)),
+                    _ => return Err(format!("{} {} is not a positive number of seconds", flag, value)),
+                },
+                _ => unreachable!(),
+            }
+        }
+
+        if options.format.is_some() && options.output.is_none() {
+            return Err(String::from("--format needs an output file"));
+        }
+        Ok(options)
+    }
+
+    // Parses the arguments of this process. Prints the usage and exits on
+    // --help or when the arguments are wrong.
+    pub fn from_env(scenes: &[&str]) -> Options {
+        let args: Vec<String> = env::args().collect();
+        let program = args.first()
+            .and_then(|arg| Path::new(arg).file_stem())
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+
+        match Options::parse(&args[1..]) {
+            Ok(options) if options.help => {
+                print!("{}", usage(&program, scenes));
+                process::exit(0);
+            },
+            Ok(options) => options,
+            Err(message) => {
+                eprintln!("{}: {}", program, message);
+                eprint!("{}", usage(&program, scenes));
+                process::exit(2);
+            },
+        }
+    }
+
+    // Overrides the scene's settings with the ones given on the command line.
+    // When only one of width and height is given, the aspect ratio is kept.
+    pub fn apply(&self, settings: &mut RenderSettings) {
+        let aspect_ratio = settings.image_width as f64 / settings.image_height as f64;
+        match (self.width, self.height) {
+            (Some(width), Some(height)) => {
+                settings.image_width = width;
+                settings.image_height = height;
+            },
+            (Some(width), None) => {
+                settings.image_width = width;
+                settings.image_height = ((width as f64 / aspect_ratio) as u64).max(MIN_IMAGE_SIZE);
+            },
+            (None, Some(height)) => {
+                settings.image_width = ((height as f64 * aspect_ratio) as u64).max(MIN_IMAGE_SIZE);
+                settings.image_height = height;
+            },
+            (None, None) => {},
+        }
+
+        // A time budget without --spp renders for as long as the budget lasts
+        if let Some(samples_per_pixel) = self.samples_per_pixel {
+            settings.samples_per_pixel = samples_per_pixel;
+        } else if self.time_budget.is_some() {
+            settings.samples_per_pixel = u64::MAX;
+        }
+        if let Some(max_depth) = self.max_depth {
+            settings.max_depth = max_depth;
+        }
+        if let Some(threads) = self.threads {
+            settings.threads = threads;
+        }
+        if self.seed.is_some() {
+            settings.seed = self.seed;
+        }
+    }
+
+    pub fn render(&self, renderer: &Renderer) -> Film {
+        match self.time_budget {
+            Some(budget) => renderer.render_for(budget),
+            None => renderer.render(),
+        }
+    }
+
+    // Writes the film to the output file, or as a PPM to stdout when there is
+    // none. Exits if the file cannot be written.
+    pub fn write(&self, film: &Film) {
+        let path = match &self.output {
+            Some(path) => path,
+            None => return film.write_ppm(),
+        };
+        let result = match self.format {
+            Some(format) => film.save_as(path, format),
+            None => film.save(path),
+        };
+        if let Err(e) = result {
+            eprintln!("Could not save '{}': {}", path, e);
+            process::exit(1);
+        }
+    }
+}
+
+pub fn usage(program: &str, scenes: &[&str]) -> String {
+    let mut text = format!("Usage: {} [options] [output]\n\n", program);
+    text += "Options:\n";
+    text += "  --scene NAME|FILE   built-in scene or scene file\n";
+    text += "  --width N           image width (at least 2), keeping the aspect ratio unless --height is given\n";
+    text += "  --height N          image height (at least 2), keeping the aspect ratio unless --width is given\n";
+    text += "  --spp N             samples per pixel\n";
+    text += "  --max-depth N       maximum number of bounces\n";
+    text += "  -o, --output FILE   image file to write, in the format of its extension\n";
+    text += "  --format FORMAT     png, jpg, tga, bmp, ppm, exr, hdr or pfm, whatever the extension\n";
+    text += "  --threads N         number of render threads (default: all cores)\n";
+    text += "  --seed N            seed for repeatable renders\n";
+    text += "  --time-budget SECS  render in passes until the time is up or --spp is reached\n";
+    text += "  -h, --help          show this help\n\n";
+    if !scenes.is_empty() {
+        text += &format!("Built-in scenes: {} (default: {})\n", scenes.join(", "), scenes[0]);
+    }
+    text += "Without an output file the image is written to stdout as a PPM.\n";
+    text
+}
+
+fn parse_dimension(flag: &str, value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(n) if n >= MIN_IMAGE_SIZE => Ok(n),
+        _ => Err(format!("{} {} is not a whole number of at least {}", flag, value, MIN_IMAGE_SIZE)),
+    }
+}
+
+fn parse_count(flag: &str, value: &str) -> Result<u64, String> {
+    match value.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(format!("{} {} is not a positive whole number", flag, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        Options::parse(&args)
+    }
+
+    fn error_of(args: &[&str]) -> String {
+        match parse(args) {
+            Ok(_) => panic!("{:?} was accepted", args),
+            Err(message) => message,
+        }
+    }
+
+    #[test]
+    fn values_follow_the_flag_or_an_equals_sign() {
+        let options = parse(&["--width", "320", "--height=240", "--spp=16", "--seed", "7", "-o", "out.exr", "--format=pfm"]).unwrap();
+        assert_eq!(options.width, Some(320));
+        assert_eq!(options.height, Some(240));
+        assert_eq!(options.samples_per_pixel, Some(16));
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.output.as_deref(), Some("out.exr"));
+        assert_eq!(options.format, Some(OutputFormat::Pfm));
+        assert!(!options.help);
+    }
+
+    #[test]
+    fn a_bare_argument_is_the_output() {
+        let options = parse(&["--scene=a b.scene", "image.png"]).unwrap();
+        assert_eq!(options.scene.as_deref(), Some("a b.scene"));
+        assert_eq!(options.output.as_deref(), Some("image.png"));
+        assert_eq!(error_of(&["a.png", "b.png"]), "unexpected argument 'b.png'");
+        assert!(parse(&["-h"]).unwrap().help);
+    }
+
+    #[test]
+    fn missing_and_wrong_values() {
+        assert_eq!(error_of(&["--spp"]), "--spp needs a value");
+        assert_eq!(error_of(&["--width", "1"]), "--width 1 is not a whole number of at least 2");
+        assert_eq!(error_of(&["--height=0"]), "--height 0 is not a whole number of at least 2");
+        assert_eq!(error_of(&["--spp", "-3"]), "--spp -3 is not a positive whole number");
+        assert_eq!(error_of(&["--seed=x"]), "--seed x is not a whole number");
+        assert_eq!(error_of(&["--time-budget", "0"]), "--time-budget 0 is not a positive number of seconds");
+        assert_eq!(error_of(&["--format", "gif", "a.gif"]), "unknown image format 'gif'");
+        assert_eq!(error_of(&["--format=png"]), "--format needs an output file");
+        assert_eq!(error_of(&["--fast"]), "unknown option '--fast'");
+    }
+
+    #[test]
+    fn one_dimension_keeps_the_aspect_ratio() {
+        let mut settings = RenderSettings::new(400, 200, 10, 10);
+        parse(&["--width", "100"]).unwrap().apply(&mut settings);
+        assert_eq!((settings.image_width, settings.image_height), (100, 50));
+
+        let mut settings = RenderSettings::new(400, 2, 10, 10);
+        parse(&["--width", "2"]).unwrap().apply(&mut settings);
+        assert_eq!((settings.image_width, settings.image_height), (2, MIN_IMAGE_SIZE));
+
+        let mut settings = RenderSettings::new(400, 200, 10, 10);
+        parse(&["--height", "300", "--time-budget", "5"]).unwrap().apply(&mut settings);
+        assert_eq!((settings.image_width, settings.image_height), (600, 300));
+        assert_eq!(settings.samples_per_pixel, u64::MAX);
+    }
+}
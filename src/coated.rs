@@ -0,0 +1,262 @@
+// A clear dielectric coat over another material, like varnish on wood, the
+// clear coat of car paint or the glossy skin of plastic.
+//
+// Light is reflected by the top of the coat, a GGX surface with index of
+// refraction `ior`, or passes through it to the base and back out. On the way
+// it is refracted towards the normal, loses what the coat reflects going in
+// and out, and is absorbed by the coat in proportion to the length of its
+// path. `color` is the fraction of light that gets through the coat once
+// straight down when it is `thickness` thick. The base is any material that
+// scatters with a pdf; it is seen through the coat at the refracted
+// directions, as if the coat were flat (Weidlich and Wilkie, "Arbitrarily
+// Layered Micro-Facet Surfaces", GRAPHITE 2007).
+//
+// Much of the light the base sends back up is reflected down again by the
+// underside of the coat, all of it beyond the critical angle, and bounces
+// between the two before it gets out. For the part of the base that scatters
+// evenly (Material::diffuse_albedo) the bounces add up to a factor of
+// 1 / (1 - albedo * reflectance of the coat from below * absorption), so a
+// white base under a clear coat reflects all the light it gets.
+//
+// Directions are drawn from the coat, from the base's own pdf refracted out
+// through the coat, and from a cosine distribution for the light that
+// bounced around. A direction from the base that cannot leave the coat is
+// turned into one below the surface, where the pdf is zero and the path
+// ends.
+
+use crate::vec3::Vec3;
+use crate::vec3::Color;
+
+use crate::material::Material;
+use crate::material::ScatterRecord;
+use crate::microfacet::Ggx;
+use crate::microfacet::ShadingFrame;
+use crate::microfacet::MicrofacetReflectionPdf;
+use crate::microfacet::fresnel_dielectric;
+use crate::microfacet::reflection;
+use crate::pdf::Pdf;
+use crate::pdf::CosinePdf;
+use crate::pdf::WeightedMixturePdf;
+
+use crate::ray::Ray;
+use crate::hittable::HitRecord;
+
+use std::sync::Arc;
+
+// Steps of the integrals over the hemisphere done when the coat is made
+const STEPS: usize = 64;
+
+pub struct Coated {
+    base: Arc<dyn Material>,
+    ior: f64,
+    distribution: Ggx,
+    color: Color,
+    thickness: f64,
+    // Fraction of evenly spread light from below that the coat reflects
+    // back down
+    internal_reflectance: f64,
+    // Fraction of evenly spread light that gets through the coat once
+    diffuse_transmittance: Color,
+}
+
+impl Coated {
+    // A clear coat with index of refraction `ior` (at least 1) and a
+    // roughness from 0 to 1
+    pub fn new(base: Arc<dyn Material>, ior: f64, roughness: f64) -> Self {
+        let ior = ior.max(1.0);
+        let mut internal_reflectance = 0.0;
+        for i in 0..STEPS {
+            let mu = (i as f64 + 0.5) / STEPS as f64;
+            internal_reflectance += fresnel_dielectric(mu, 1.0 / ior) * 2.0 * mu / STEPS as f64;
+        }
+        Coated {
+            base,
+            ior,
+            distribution: Ggx::from_roughness(roughness, roughness),
+            color: Color(1.0, 1.0, 1.0),
+            thickness: 0.0,
+            internal_reflectance,
+            diffuse_transmittance: Color(1.0, 1.0, 1.0),
+        }
+    }
+
+    // Lets the coat absorb light: `color` gets through once straight down
+    // a coat of thickness 1, and `thickness` scales the length of every path
+    pub fn with_absorption(mut self, color: Color, thickness: f64) -> Self {
+        self.color = Color(color.x().clamp(0.0, 1.0), color.y().clamp(0.0, 1.0), color.z().clamp(0.0, 1.0));
+        self.thickness = thickness.max(0.0);
+        let mut transmittance = Color(0.0, 0.0, 0.0);
+        for i in 0..STEPS {
+            let mu = (i as f64 + 0.5) / STEPS as f64;
+            transmittance += (2.0 * mu / STEPS as f64) * self.absorption(mu);
+        }
+        self.diffuse_transmittance = transmittance;
+        self
+    }
+
+    // Fraction of the light getting through the coat at cos_theta to the
+    // normal inside it
+    fn absorption(&self, cos_theta: f64) -> Color {
+        if self.thickness == 0.0 {
+            return Color(1.0, 1.0, 1.0);
+        }
+        let exponent = self.thickness / cos_theta.max(1.0e-4);
+        Color(self.color.x().powf(exponent), self.color.y().powf(exponent), self.color.z().powf(exponent))
+    }
+
+    // The ray the base sees: from the refracted wo, hitting the same point
+    fn inner_ray(&self, r_in: &Ray, frame: &ShadingFrame, wo: &Vec3) -> Ray {
+        let inner_wo = refract_in(wo, self.ior);
+        Ray { origin: r_in.origin(), direction: -frame.to_world(&inner_wo), tm: r_in.time(), wavelength: r_in.wavelength() }
+    }
+}
+
+impl Material for Coated {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let frame = ShadingFrame::new(&rec.normal, &Vec3(1.0, 0.0, 0.0));
+        let wo = frame.to_local(&-Vec3::unit_vector(r_in.direction()));
+        if wo.z() <= 0.0 {
+            return false;
+        }
+
+        // The base's record. Its attenuation is kept in ours for
+        // scattering_color.
+        let inner_ray = self.inner_ray(r_in, &frame, &wo);
+        let base_scatters = self.base.scatter(&inner_ray, rec, srec);
+        if base_scatters && srec.skip_pdf {
+            // A base that scatters into one direction only is not seen
+            // through the coat, but as it is
+            return self.base.scatter(r_in, rec, srec);
+        }
+
+        let coat_weight = fresnel_dielectric(wo.z(), self.ior).max(0.25);
+        let mut pdfs: Vec<(f64, Arc<dyn Pdf>)> = vec![
+            (coat_weight, Arc::new(MicrofacetReflectionPdf {
+                frame,
+                wo,
+                distribution: self.distribution,
+            })),
+        ];
+        if base_scatters {
+            pdfs.push((0.5 * (1.0 - coat_weight), Arc::new(RefractedPdf {
+                base: srec.pdf_ptr.clone(),
+                frame,
+                eta: self.ior,
+            })));
+            pdfs.push((0.5 * (1.0 - coat_weight), Arc::new(CosinePdf::new(&rec.normal))));
+        } else {
+            srec.attenuation = Color(0.0, 0.0, 0.0);
+        }
+        srec.pdf_ptr = Arc::new(WeightedMixturePdf::new(pdfs));
+        srec.skip_pdf = false;
+        true
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let mut srec = empty_scatter_record(r_in);
+        if !self.scatter(r_in, rec, &mut srec) || srec.skip_pdf {
+            return 0.0;
+        }
+        srec.pdf_ptr.value(&scattered.direction())
+    }
+
+    fn scattering_color(&self, r_in: &Ray, rec: &HitRecord, srec: &ScatterRecord, scattered: &Ray) -> Color {
+        let frame = ShadingFrame::new(&rec.normal, &Vec3(1.0, 0.0, 0.0));
+        let wo = frame.to_local(&-Vec3::unit_vector(r_in.direction()));
+        let wi = frame.to_local(&Vec3::unit_vector(scattered.direction()));
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Color(0.0, 0.0, 0.0);
+        }
+
+        let mut color = match reflection(&self.distribution, &wo, &wi) {
+            Some((m, value)) => fresnel_dielectric(Vec3::dot(wo, m), self.ior) * value * Color(1.0, 1.0, 1.0),
+            None => Color(0.0, 0.0, 0.0),
+        };
+
+        if srec.attenuation.near_zero() {
+            return color;
+        }
+        let inner_wo = refract_in(&wo, self.ior);
+        let inner_wi = refract_in(&wi, self.ior);
+        let inner_ray = self.inner_ray(r_in, &frame, &wo);
+        let inner_scattered = Ray { origin: scattered.origin(), direction: frame.to_world(&inner_wi), tm: scattered.time(), wavelength: scattered.wavelength() };
+        let base_record = ScatterRecord {
+            attenuation: srec.attenuation,
+            pdf_ptr: srec.pdf_ptr.clone(),
+            skip_pdf: false,
+            skip_pdf_ray: *scattered,
+        };
+        let base = self.base.scattering_color(&inner_ray, rec, &base_record, &inner_scattered);
+
+        let transmittance = (1.0 - fresnel_dielectric(wo.z(), self.ior)) * (1.0 - fresnel_dielectric(wi.z(), self.ior));
+        let absorption = self.absorption(inner_wo.z()) * self.absorption(inner_wi.z());
+        // Bounces between the base and the underside of the coat
+        let albedo = self.base.diffuse_albedo(rec);
+        let trapped = self.internal_reflectance * self.diffuse_transmittance * self.diffuse_transmittance;
+        let coupling = Color(
+            1.0 / (1.0 - albedo.x() * trapped.x()).max(1.0e-3),
+            1.0 / (1.0 - albedo.y() * trapped.y()).max(1.0e-3),
+            1.0 / (1.0 - albedo.z() * trapped.z()).max(1.0e-3),
+        );
+        // The base's value is per unit solid angle inside the coat, which is
+        // 1 / ior^2 times as large as outside, and has the cosine inside
+        let jacobian = wi.z() / (self.ior * self.ior * inner_wi.z());
+        color += transmittance * jacobian * absorption * coupling * base;
+        color
+    }
+}
+
+// The base's pdf, seen from outside the coat: directions drawn inside are
+// refracted out
+struct RefractedPdf {
+    base: Arc<dyn Pdf>,
+    frame: ShadingFrame,
+    eta: f64,
+}
+
+impl Pdf for RefractedPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = self.frame.to_local(&Vec3::unit_vector(*direction));
+        if wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let inner_wi = refract_in(&wi, self.eta);
+        self.base.value(&self.frame.to_world(&inner_wi)) * wi.z() / (self.eta * self.eta * inner_wi.z())
+    }
+
+    fn generate(&self) -> Vec3 {
+        let inner_wi = self.frame.to_local(&Vec3::unit_vector(self.base.generate()));
+        if inner_wi.z() > 0.0 {
+            if let Some(wi) = refract_out(&inner_wi, self.eta) {
+                return self.frame.to_world(&wi);
+            }
+        }
+        // Caught in the coat
+        self.frame.to_world(&Vec3(0.0, 0.0, -1.0))
+    }
+}
+
+// A direction above a flat coat of index eta, as seen inside it, both
+// pointing away from the surface
+fn refract_in(w: &Vec3, eta: f64) -> Vec3 {
+    let sin2 = (1.0 - w.z()*w.z()).max(0.0) / (eta*eta);
+    Vec3(w.x() / eta, w.y() / eta, (1.0 - sin2).sqrt())
+}
+
+// The other way round, None beyond the critical angle
+fn refract_out(w: &Vec3, eta: f64) -> Option<Vec3> {
+    let sin2 = (1.0 - w.z()*w.z()).max(0.0) * eta*eta;
+    if sin2 >= 1.0 {
+        return None;
+    }
+    Some(Vec3(w.x() * eta, w.y() * eta, (1.0 - sin2).sqrt()))
+}
+
+fn empty_scatter_record(r_in: &Ray) -> ScatterRecord {
+    ScatterRecord {
+        attenuation: Color(0.0, 0.0, 0.0),
+        pdf_ptr: Arc::new(CosinePdf::new(&Vec3(0.0, 0.0, 1.0))),
+        skip_pdf: false,
+        skip_pdf_ray: *r_in,
+    }
+}
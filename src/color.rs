@@ -2,15 +2,17 @@ use crate::vec3::Color;
 
 use crate::rtweekend::clamp;
 
-pub fn write_color(pixel_color: Color, samples_per_pixel: u64) {
+// Averages the summed samples, gamma corrects them (gamma 2) and quantises the
+// result to 8 bits per channel.
+pub fn color_to_rgb8(pixel_color: Color, samples_per_pixel: u64) -> [u8; 3] {
     let mut r: f64 = pixel_color.x();
     let mut g: f64 = pixel_color.y();
     let mut b: f64 = pixel_color.z();
 
     // Replace NaN components with zero. See explanation in Ray Tracing: The Rest of Your Life.
-    if r != r { r = 0.0; }
-    if g != g { g = 0.0; }
-    if b != b { b = 0.0; }
+    if r.is_nan() { r = 0.0; }
+    if g.is_nan() { g = 0.0; }
+    if b.is_nan() { b = 0.0; }
 
     // Divide the color by the number of samples
     let scale: f64 = 1.0/samples_per_pixel as f64;
@@ -19,8 +21,19 @@ pub fn write_color(pixel_color: Color, samples_per_pixel: u64) {
     g *= scale; g = g.sqrt();
     b *= scale; b = b.sqrt();
 
-    println!("{} {} {}", 
-        (256.0 * clamp(r, 0.0, 0.999)) as u64, 
-        (256.0 * clamp(g, 0.0, 0.999)) as u64,
-        (256.0 * clamp(b, 0.0, 0.999)) as u64)
-}
\ No newline at end of file
+    [
+        (256.0 * clamp(r, 0.0, 0.999)) as u8,
+        (256.0 * clamp(g, 0.0, 0.999)) as u8,
+        (256.0 * clamp(b, 0.0, 0.999)) as u8,
+    ]
+}
+
+// Relative luminance of a linear colour (Rec. 709 primaries)
+pub fn luminance(c: Color) -> f64 {
+    0.2126*c.x() + 0.7152*c.y() + 0.0722*c.z()
+}
+
+pub fn write_color(pixel_color: Color, samples_per_pixel: u64) {
+    let [r, g, b] = color_to_rgb8(pixel_color, samples_per_pixel);
+    println!("{} {} {}", r, g, b)
+}
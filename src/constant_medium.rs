@@ -14,17 +14,16 @@ use crate::aabb::AABB;
 
 use crate::rtweekend::random_double;
 
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
 pub struct ConstantMedium {
-    boundary: Rc<RefCell<dyn Hittable>>,
-    phase_function: Rc<RefCell<dyn Material>>,
+    boundary: Arc<dyn Hittable>,
+    phase_function: Arc<dyn Material>,
     neg_inv_density: f64
 }
 
 impl Hittable for ConstantMedium {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         // Print occasional samples when debugging. To enable, set enable_debug true.
         let enable_debug: bool = false;
         let debugging: bool = enable_debug && random_double() < 0.00001;
@@ -32,7 +31,7 @@ impl Hittable for ConstantMedium {
         let mut rec1: HitRecord = HitRecord {
             p: Point3(0.0, 0.0, 0.0),
             normal: Vec3(0.0, 0.0, 0.0),
-            mat_ptr: Rc::new(RefCell::new(DefaultMaterial)),
+            mat_ptr: Arc::new(DefaultMaterial),
             t: 0.0,
             u: 0.0,
             v: 0.0,
@@ -42,18 +41,18 @@ impl Hittable for ConstantMedium {
         let mut rec2: HitRecord = HitRecord {
             p: Point3(0.0, 0.0, 0.0),
             normal: Vec3(0.0, 0.0, 0.0),
-            mat_ptr: Rc::new(RefCell::new(DefaultMaterial)),
+            mat_ptr: Arc::new(DefaultMaterial),
             t: 0.0,
             u: 0.0,
             v: 0.0,
             front_face: false,
         };
 
-        if !self.boundary.borrow_mut().hit(r, f64::NEG_INFINITY, f64::INFINITY, &mut rec1) {
+        if !self.boundary.hit(r, f64::NEG_INFINITY, f64::INFINITY, &mut rec1) {
             return false;
         }
 
-        if !self.boundary.borrow_mut().hit(r, rec1.t + 0.0001, f64::INFINITY, &mut rec2) {
+        if !self.boundary.hit(r, rec1.t + 0.0001, f64::INFINITY, &mut rec2) {
             return false;
         }
 
@@ -93,19 +92,19 @@ impl Hittable for ConstantMedium {
         rec.front_face = true;     // also arbitrary
         rec.mat_ptr = self.phase_function.clone();
 
-        return true;
+        true
     }
 
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
-        return self.boundary.borrow().bounding_box(time0, time1, output_box);
+        self.boundary.bounding_box(time0, time1, output_box)
     }
 }
 
 impl ConstantMedium {
-    pub fn new(b: Rc<RefCell<dyn Hittable>>, d: f64, c: Color) -> Self {
+    pub fn new(b: Arc<dyn Hittable>, d: f64, c: Color) -> Self {
         ConstantMedium {
             boundary: b,
-            phase_function: Rc::new(RefCell::new(Isotropic::new(c))),
+            phase_function: Arc::new(Isotropic::new(c)),
             neg_inv_density: -1.0/d,
         }
     }
@@ -0,0 +1,143 @@
+// Lights with no size: a point light, a spot light and a directional light.
+//
+// A ray drawn from a material's pdf never hits a point or comes from exactly
+// one direction, so these lights can only be found by asking them for the
+// direction towards them. MisPathIntegrator does that at every diffuse
+// bounce for each of them, and sends a shadow ray to see whether the light
+// is blocked. There is nothing to weigh with MIS, since no other strategy
+// could have found them.
+//
+// Each light has a colour and an intensity that scales it. Point and spot
+// lights give intensity * color per steradian, so the light falls off with
+// the squared distance. A directional light gives intensity * color per
+// unit area facing it, the same everywhere, like sunlight.
+
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+use crate::vec3::Color;
+
+use crate::rtweekend::degrees_to_radians;
+
+// Light arriving at a point from a delta light
+pub struct DeltaLightSample {
+    // Unit vector from the point towards the light
+    pub direction: Vec3,
+    // Distance to the light, infinite for a directional light
+    pub distance: f64,
+    // Irradiance at the point on a surface facing the light
+    pub irradiance: Color,
+}
+
+pub trait DeltaLight: Send + Sync {
+    // The light reaching `p`, or None when none does
+    fn sample(&self, p: &Point3) -> Option<DeltaLightSample>;
+}
+
+pub struct PointLight {
+    pub position: Point3,
+    pub color: Color,
+    pub intensity: f64,
+}
+
+impl DeltaLight for PointLight {
+    fn sample(&self, p: &Point3) -> Option<DeltaLightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_square();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        Some(DeltaLightSample {
+            direction: Vec3::unit_vector(to_light),
+            distance: distance_squared.sqrt(),
+            irradiance: self.intensity * self.color / distance_squared,
+        })
+    }
+}
+
+// A point light shining into a cone around `direction`. Inside `falloff`
+// degrees of the axis it is as bright as a point light, and it fades
+// smoothly to nothing at `cone` degrees.
+pub struct SpotLight {
+    pub position: Point3,
+    // Unit vector along the axis of the cone, away from the light
+    direction: Vec3,
+    pub color: Color,
+    pub intensity: f64,
+    cos_cone: f64,
+    cos_falloff: f64,
+}
+
+impl SpotLight {
+    pub fn new(position: Point3, direction: Vec3, cone: f64, falloff: f64, color: Color, intensity: f64) -> Self {
+        let cone = cone.clamp(0.0, 180.0);
+        SpotLight {
+            position,
+            direction: Vec3::unit_vector(direction),
+            color,
+            intensity,
+            cos_cone: degrees_to_radians(cone).cos(),
+            cos_falloff: degrees_to_radians(falloff.clamp(0.0, cone)).cos(),
+        }
+    }
+
+    // Fraction of the light sent along `w`, a unit vector from the light
+    fn falloff(&self, w: &Vec3) -> f64 {
+        let cos_theta = Vec3::dot(*w, self.direction);
+        if cos_theta >= self.cos_falloff {
+            return 1.0;
+        }
+        if cos_theta <= self.cos_cone {
+            return 0.0;
+        }
+        let t = (cos_theta - self.cos_cone) / (self.cos_falloff - self.cos_cone);
+        t * t * (3.0 - 2.0*t)
+    }
+}
+
+impl DeltaLight for SpotLight {
+    fn sample(&self, p: &Point3) -> Option<DeltaLightSample> {
+        let to_light = self.position - *p;
+        let distance_squared = to_light.length_square();
+        if distance_squared == 0.0 {
+            return None;
+        }
+        let direction = Vec3::unit_vector(to_light);
+        let falloff = self.falloff(&-direction);
+        if falloff <= 0.0 {
+            return None;
+        }
+        Some(DeltaLightSample {
+            direction,
+            distance: distance_squared.sqrt(),
+            irradiance: self.intensity * falloff * self.color / distance_squared,
+        })
+    }
+}
+
+// Light from infinitely far away, all travelling along `direction`
+pub struct DirectionalLight {
+    // Unit vector in which the light travels
+    direction: Vec3,
+    pub color: Color,
+    pub intensity: f64,
+}
+
+impl DirectionalLight {
+    pub fn new(direction: Vec3, color: Color, intensity: f64) -> Self {
+        DirectionalLight {
+            direction: Vec3::unit_vector(direction),
+            color,
+            intensity,
+        }
+    }
+}
+
+impl DeltaLight for DirectionalLight {
+    fn sample(&self, _p: &Point3) -> Option<DeltaLightSample> {
+        Some(DeltaLightSample {
+            direction: -self.direction,
+            distance: f64::INFINITY,
+            irradiance: self.intensity * self.color,
+        })
+    }
+}
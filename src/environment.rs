@@ -0,0 +1,314 @@
+// Light arriving from infinitely far away, seen by rays that hit nothing.
+//
+// The first book draws a gradient from white to blue for rays that miss,
+// the later ones a constant colour. An Environment gives the radiance for
+// any direction, and ones with much brighter parts than others, like a sky
+// with a sun in an HDR image, can also pick directions for next event
+// estimation, in proportion to how much light comes from them.
+//
+// ImageEnvironment maps an equirectangular (latitude-longitude) image onto
+// the sphere of directions, y up. The centre of the image is seen looking
+// down -z, and u grows towards +x. Directions are drawn from a 2D CDF over
+// the luminance of its pixels (SphereDistribution): first a row from the
+// marginal distribution of the rows, then a pixel in that row, then a point
+// in the pixel. Each row is weighted with sin(theta), since the rows near
+// the poles cover less of the sphere.
+
+use crate::vec3::Vec3;
+use crate::vec3::Color;
+
+use crate::color::luminance;
+use crate::rtweekend::random_double;
+use crate::rtweekend::degrees_to_radians;
+
+use std::f64::consts::PI;
+
+pub trait Environment: Send + Sync {
+    // Radiance seen looking along `direction`, which need not be of unit length
+    fn value(&self, direction: &Vec3) -> Color;
+
+    // Density over solid angle with which `sample` picks `direction`.
+    // Zero when the environment is not sampled.
+    fn pdf_value(&self, _direction: &Vec3) -> f64 {
+        0.0
+    }
+
+    // A unit direction to sample directly, or None when the environment is
+    // not worth sampling, like one that is the same everywhere
+    fn sample(&self) -> Option<Vec3> {
+        None
+    }
+}
+
+pub struct ConstantEnvironment {
+    pub color: Color,
+}
+
+impl Environment for ConstantEnvironment {
+    fn value(&self, _direction: &Vec3) -> Color {
+        self.color
+    }
+}
+
+// Blends from `bottom` looking straight down to `top` looking straight up,
+// by the height of the direction, like ray_color in the first book
+pub struct GradientEnvironment {
+    pub bottom: Color,
+    pub top: Color,
+}
+
+impl GradientEnvironment {
+    // The sky of the first book
+    pub fn sky() -> Self {
+        GradientEnvironment {
+            bottom: Color(1.0, 1.0, 1.0),
+            top: Color(0.5, 0.7, 1.0),
+        }
+    }
+}
+
+impl Environment for GradientEnvironment {
+    fn value(&self, direction: &Vec3) -> Color {
+        let unit_direction = Vec3::unit_vector(*direction);
+        let t = 0.5*(unit_direction.y() + 1.0);
+        (1.0-t)*self.bottom + t*self.top
+    }
+}
+
+// A distribution over the directions of the sphere, constant over each
+// pixel of a latitude-longitude grid, from `width` by `height` weights given
+// row by row from the top. Each row is weighted with sin(theta) as well, so
+// directions are picked in proportion to weight times solid angle.
+pub struct SphereDistribution {
+    width: usize,
+    height: usize,
+    // Turn about the y axis, in radians
+    rotation: f64,
+    // Cumulative distribution over the rows, height + 1 entries from 0 to 1
+    row_cdf: Vec<f64>,
+    // Cumulative distribution in each row, width + 1 entries per row
+    pixel_cdf: Vec<f64>,
+    // Sum of all weights. Zero when nothing can be sampled.
+    total: f64,
+}
+
+impl SphereDistribution {
+    pub fn new(width: usize, height: usize, weights: &[f64], rotation: f64) -> Self {
+        assert!(width > 0 && height > 0 && weights.len() == width * height, "distribution has the wrong size");
+
+        let mut row_cdf = Vec::with_capacity(height + 1);
+        let mut pixel_cdf = Vec::with_capacity(height * (width + 1));
+        row_cdf.push(0.0);
+        for j in 0..height {
+            let sin_theta = (PI * (j as f64 + 0.5) / height as f64).sin();
+            let start = pixel_cdf.len();
+            let mut sum = 0.0;
+            pixel_cdf.push(0.0);
+            for i in 0..width {
+                sum += weights[j*width + i].max(0.0) * sin_theta;
+                pixel_cdf.push(sum);
+            }
+            if sum > 0.0 {
+                for c in &mut pixel_cdf[start..] {
+                    *c /= sum;
+                }
+            }
+            row_cdf.push(row_cdf[j] + sum);
+        }
+
+        let total = row_cdf[height];
+        if total > 0.0 {
+            for c in &mut row_cdf {
+                *c /= total;
+            }
+        }
+
+        SphereDistribution {
+            width,
+            height,
+            rotation,
+            row_cdf,
+            pixel_cdf,
+            total,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total <= 0.0
+    }
+
+    // Image coordinates of a direction, both from 0 to 1, and sin(theta)
+    fn uv(&self, direction: &Vec3) -> (f64, f64, f64) {
+        let d = Vec3::unit_vector(*direction);
+        let theta = d.y().clamp(-1.0, 1.0).acos();
+        let phi = d.x().atan2(-d.z()) - self.rotation;
+        let u = (phi / (2.0*PI) + 0.5).rem_euclid(1.0);
+        (u, theta / PI, theta.sin())
+    }
+
+    // The pixel a direction falls in
+    pub fn pixel(&self, direction: &Vec3) -> (usize, usize) {
+        let (u, v, _) = self.uv(direction);
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        (i, j)
+    }
+
+    // Probability of picking pixel (i, j)
+    fn pixel_probability(&self, i: usize, j: usize) -> f64 {
+        let row = (self.row_cdf[j+1] - self.row_cdf[j]).max(0.0);
+        let start = j * (self.width + 1);
+        row * (self.pixel_cdf[start+i+1] - self.pixel_cdf[start+i]).max(0.0)
+    }
+
+    // The density is constant over each pixel in image coordinates, where
+    // the whole image has area 1 and covers 2 pi^2 sin(theta) of solid angle
+    // per unit area
+    pub fn pdf_value(&self, direction: &Vec3) -> f64 {
+        if self.is_empty() {
+            return 0.0;
+        }
+        let (_, _, sin_theta) = self.uv(direction);
+        if sin_theta <= 0.0 {
+            return 0.0;
+        }
+        let (i, j) = self.pixel(direction);
+        let density = self.pixel_probability(i, j) * (self.width * self.height) as f64;
+        density / (2.0*PI*PI*sin_theta)
+    }
+
+    pub fn sample(&self) -> Option<Vec3> {
+        if self.is_empty() {
+            return None;
+        }
+        let j = find_interval(&self.row_cdf, random_double());
+        let start = j * (self.width + 1);
+        let i = find_interval(&self.pixel_cdf[start..start + self.width + 1], random_double());
+
+        let u = (i as f64 + random_double()) / self.width as f64;
+        let v = (j as f64 + random_double()) / self.height as f64;
+        let theta = PI * v;
+        let phi = (u - 0.5) * 2.0*PI + self.rotation;
+        Some(Vec3(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos()))
+    }
+}
+
+// Index of the interval of `cdf` that holds `x`, skipping empty intervals
+fn find_interval(cdf: &[f64], x: f64) -> usize {
+    let last = cdf.len() - 2;
+    let mut i = cdf.partition_point(|&c| c <= x).saturating_sub(1).min(last);
+    // Rounding may land on an interval of zero width at the end
+    while i > 0 && cdf[i+1] <= cdf[i] {
+        i -= 1;
+    }
+    i
+}
+
+pub struct ImageEnvironment {
+    width: usize,
+    // Row by row from the top, already scaled by the intensity
+    pixels: Vec<[f32; 3]>,
+    // Over the luminance of the pixels
+    distribution: SphereDistribution,
+}
+
+impl ImageEnvironment {
+    // Loads an equirectangular image. HDR and EXR files keep their values,
+    // other formats are read like ImageTexture, as 0 to 1. `rotate` turns
+    // the image about the y axis, in degrees.
+    pub fn open(filename: &str, intensity: f64, rotate: f64) -> Result<Self, image::ImageError> {
+        let image = image::open(filename)?.into_rgb32f();
+        let (width, height) = image.dimensions();
+        let scale = intensity as f32;
+        let pixels = image.pixels().map(|p| [scale * p[0], scale * p[1], scale * p[2]]).collect();
+        Ok(ImageEnvironment::new(width as usize, height as usize, pixels, rotate))
+    }
+
+    // An environment from `width` by `height` linear pixels, row by row
+    // from the top
+    pub fn new(width: usize, height: usize, pixels: Vec<[f32; 3]>, rotate: f64) -> Self {
+        let weights: Vec<f64> = pixels.iter().map(|p| luminance(Color(p[0] as f64, p[1] as f64, p[2] as f64))).collect();
+        ImageEnvironment {
+            width,
+            distribution: SphereDistribution::new(width, height, &weights, degrees_to_radians(rotate)),
+            pixels,
+        }
+    }
+}
+
+impl Environment for ImageEnvironment {
+    fn value(&self, direction: &Vec3) -> Color {
+        let (i, j) = self.distribution.pixel(direction);
+        let p = self.pixels[j*self.width + i];
+        Color(p[0] as f64, p[1] as f64, p[2] as f64)
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> f64 {
+        self.distribution.pdf_value(direction)
+    }
+
+    fn sample(&self) -> Option<Vec3> {
+        self.distribution.sample()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pdf::checks::histogram_distance;
+    use crate::pdf::checks::sphere_integral;
+    use crate::rtweekend::seed_random;
+
+    // 8 by 4 pixels, one of them much brighter and one black
+    fn weights() -> Vec<f64> {
+        let mut weights: Vec<f64> = (0..32).map(|k| 1.0 + (k % 5) as f64).collect();
+        weights[10] = 50.0;
+        weights[21] = 0.0;
+        weights
+    }
+
+    #[test]
+    fn the_density_integrates_to_one_and_matches_the_samples() {
+        for rotation in [0.0, 1.0] {
+            let distribution = SphereDistribution::new(8, 4, &weights(), rotation);
+
+            let total = sphere_integral(|v| distribution.pdf_value(v), 400);
+            assert!((total - 1.0).abs() < 0.01, "rotation {}: integral {}", rotation, total);
+
+            seed_random(1);
+            let distance = histogram_distance(|| distribution.sample().unwrap(), |v| distribution.pdf_value(v), 200000);
+            assert!(distance < 0.02, "rotation {}: distance {}", rotation, distance);
+        }
+    }
+
+    #[test]
+    fn samples_land_in_pixels_in_proportion_to_weight_and_solid_angle() {
+        let weights = weights();
+        let distribution = SphereDistribution::new(8, 4, &weights, 0.5);
+        let mut counts = [0usize; 32];
+        seed_random(2);
+        let samples = 200000;
+        for _ in 0..samples {
+            let (i, j) = distribution.pixel(&distribution.sample().unwrap());
+            counts[j*8 + i] += 1;
+        }
+
+        let row_weight = |j: usize| (PI * (j as f64 + 0.5) / 4.0).sin();
+        let total: f64 = (0..32).map(|k| weights[k] * row_weight(k / 8)).sum();
+        for k in 0..32 {
+            let expected = weights[k] * row_weight(k / 8) / total;
+            let found = counts[k] as f64 / samples as f64;
+            assert!((found - expected).abs() < 0.005, "pixel {}: {} expected {}", k, found, expected);
+        }
+        assert_eq!(counts[21], 0);
+    }
+
+    #[test]
+    fn a_black_image_cannot_be_sampled() {
+        let distribution = SphereDistribution::new(4, 2, &[0.0; 8], 0.0);
+        assert!(distribution.is_empty());
+        assert!(distribution.sample().is_none());
+        assert_eq!(distribution.pdf_value(&Vec3(0.0, 1.0, 0.0)), 0.0);
+    }
+}
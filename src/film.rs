@@ -0,0 +1,251 @@
+use crate::vec3::Color;
+
+use crate::color::write_color;
+use crate::color::color_to_rgb8;
+
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::io::Write;
+use std::path::Path;
+
+use image::codecs::hdr::HdrEncoder;
+
+// File formats a Film can be saved as.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum OutputFormat {
+    Png,
+    Jpeg,
+    Tga,
+    Bmp,
+    // Binary (P6) PPM
+    Ppm,
+    // High dynamic range formats. These store the linear radiance as floats,
+    // without gamma correction or clamping.
+    Exr,
+    Hdr,
+    Pfm,
+}
+
+impl OutputFormat {
+    // Picks the format from a file extension such as "png" or "jpg".
+    pub fn from_extension(ext: &str) -> Option<OutputFormat> {
+        match ext.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "tga" => Some(OutputFormat::Tga),
+            "bmp" => Some(OutputFormat::Bmp),
+            "ppm" => Some(OutputFormat::Ppm),
+            "exr" => Some(OutputFormat::Exr),
+            "hdr" => Some(OutputFormat::Hdr),
+            "pfm" => Some(OutputFormat::Pfm),
+            _ => None,
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        path.extension().and_then(|ext| ext.to_str()).and_then(OutputFormat::from_extension)
+    }
+
+    pub fn is_hdr(self) -> bool {
+        matches!(self, OutputFormat::Exr | OutputFormat::Hdr | OutputFormat::Pfm)
+    }
+}
+
+#[derive(Debug)]
+pub enum FilmError {
+    UnknownFormat(String),
+    Io(io::Error),
+    Image(image::ImageError),
+}
+
+impl fmt::Display for FilmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilmError::UnknownFormat(path) => write!(f, "cannot tell the image format of '{}' from its extension", path),
+            FilmError::Io(e) => write!(f, "{}", e),
+            FilmError::Image(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for FilmError {}
+
+impl From<io::Error> for FilmError {
+    fn from(e: io::Error) -> Self {
+        FilmError::Io(e)
+    }
+}
+
+impl From<image::ImageError> for FilmError {
+    fn from(e: image::ImageError) -> Self {
+        FilmError::Image(e)
+    }
+}
+
+// In-memory framebuffer. Each pixel keeps the sum of its radiance samples and
+// how many samples went into it, so passes can be added on top of each other.
+// Row 0 is the top of the image.
+pub struct Film {
+    pub width: u64,
+    pub height: u64,
+    sums: Vec<Color>,
+    samples: Vec<u64>,
+}
+
+impl Film {
+    pub fn new(width: u64, height: u64) -> Self {
+        let n = (width*height) as usize;
+        Film {
+            width,
+            height,
+            sums: vec![Color(0.0, 0.0, 0.0); n],
+            samples: vec![0; n],
+        }
+    }
+
+    // Adds `n` samples whose radiance sums to `sum` to pixel (x, y).
+    pub fn add_samples(&mut self, x: u64, y: u64, sum: Color, n: u64) {
+        let index = self.index(x, y);
+        self.sums[index] += sum;
+        self.samples[index] += n;
+    }
+
+    // Sum of all samples taken at pixel (x, y).
+    pub fn sum(&self, x: u64, y: u64) -> Color {
+        self.sums[self.index(x, y)]
+    }
+
+    pub fn sample_count(&self, x: u64, y: u64) -> u64 {
+        self.samples[self.index(x, y)]
+    }
+
+    // Average linear radiance at pixel (x, y).
+    pub fn pixel(&self, x: u64, y: u64) -> Color {
+        let n = self.sample_count(x, y);
+        if n == 0 {
+            return Color(0.0, 0.0, 0.0);
+        }
+        self.sum(x, y) / n as f64
+    }
+
+    // Prints the image to stdout as an ASCII (P3) PPM.
+    pub fn write_ppm(&self) {
+        print!("P3\n{} {}\n255\n", self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                write_color(self.sum(x, y), self.sample_count(x, y).max(1));
+            }
+        }
+    }
+
+    // Gamma corrected 8-bit RGB pixels, row by row from the top of the image.
+    pub fn to_rgb8(&self) -> Vec<u8> {
+        let mut data = Vec::with_capacity((3*self.width*self.height) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                data.extend_from_slice(&color_to_rgb8(self.sum(x, y), self.sample_count(x, y).max(1)));
+            }
+        }
+        data
+    }
+
+    // Averaged linear radiance as 32-bit floats, RGB row by row from the top of
+    // the image. NaN components are written as zero, like in write_color.
+    pub fn to_rgb32f(&self) -> Vec<f32> {
+        let mut data = Vec::with_capacity((3*self.width*self.height) as usize);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let c = self.pixel(x, y);
+                for i in 0..3 {
+                    data.push(if c[i].is_nan() { 0.0 } else { c[i] as f32 });
+                }
+            }
+        }
+        data
+    }
+
+    // Saves the image, choosing the format from the extension of `path`.
+    pub fn save(&self, path: &str) -> Result<(), FilmError> {
+        match OutputFormat::from_path(Path::new(path)) {
+            Some(format) => self.save_as(path, format),
+            None => Err(FilmError::UnknownFormat(path.to_string())),
+        }
+    }
+
+    pub fn save_as(&self, path: &str, format: OutputFormat) -> Result<(), FilmError> {
+        match format {
+            OutputFormat::Png => self.write_image(path, image::ImageFormat::Png),
+            OutputFormat::Jpeg => self.write_image(path, image::ImageFormat::Jpeg),
+            OutputFormat::Tga => self.write_image(path, image::ImageFormat::Tga),
+            OutputFormat::Bmp => self.write_image(path, image::ImageFormat::Bmp),
+            OutputFormat::Ppm => self.write_p6(path),
+            OutputFormat::Exr => self.write_exr(path),
+            OutputFormat::Hdr => self.write_hdr(path),
+            OutputFormat::Pfm => self.write_pfm(path),
+        }
+    }
+
+    fn write_image(&self, path: &str, format: image::ImageFormat) -> Result<(), FilmError> {
+        image::save_buffer_with_format(
+            path,
+            &self.to_rgb8(),
+            self.width as u32,
+            self.height as u32,
+            image::ColorType::Rgb8,
+            format,
+        )?;
+        Ok(())
+    }
+
+    fn write_p6(&self, path: &str) -> Result<(), FilmError> {
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "P6\n{} {}\n255\n", self.width, self.height)?;
+        out.write_all(&self.to_rgb8())?;
+        out.flush()?;
+        Ok(())
+    }
+
+    fn write_exr(&self, path: &str) -> Result<(), FilmError> {
+        let bytes: Vec<u8> = self.to_rgb32f().iter().flat_map(|c| c.to_ne_bytes()).collect();
+        image::save_buffer_with_format(
+            path,
+            &bytes,
+            self.width as u32,
+            self.height as u32,
+            image::ColorType::Rgb32F,
+            image::ImageFormat::OpenExr,
+        )?;
+        Ok(())
+    }
+
+    // Radiance RGBE (.hdr), run-length encoded by the image crate
+    fn write_hdr(&self, path: &str) -> Result<(), FilmError> {
+        let pixels: Vec<image::Rgb<f32>> = self.to_rgb32f().chunks(3).map(|c| image::Rgb([c[0], c[1], c[2]])).collect();
+        let out = BufWriter::new(File::create(path)?);
+        HdrEncoder::new(out).encode(&pixels, self.width as usize, self.height as usize)?;
+        Ok(())
+    }
+
+    // Portable float map. The scanlines are stored from the bottom of the image
+    // up, and the negative scale in the header marks them as little-endian.
+    fn write_pfm(&self, path: &str) -> Result<(), FilmError> {
+        let data = self.to_rgb32f();
+        let row_len = (3*self.width) as usize;
+
+        let mut out = BufWriter::new(File::create(path)?);
+        write!(out, "PF\n{} {}\n-1.0\n", self.width, self.height)?;
+        for row in data.chunks(row_len).rev() {
+            for value in row {
+                out.write_all(&value.to_le_bytes())?;
+            }
+        }
+        out.flush()?;
+        Ok(())
+    }
+
+    fn index(&self, x: u64, y: u64) -> usize {
+        (y*self.width + x) as usize
+    }
+}
@@ -7,14 +7,16 @@ use crate::material::Material;
 
 use crate::aabb::AABB;
 
-use std::rc::Rc;
-use std::cell::RefCell;
+use crate::mat4::Mat4;
+
+use std::sync::Arc;
+use std::f64::consts::PI;
 
 #[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
-    pub mat_ptr: Rc<RefCell<dyn Material>>,
+    pub mat_ptr: Arc<dyn Material>,
     pub t: f64,
     pub u: f64,
     pub v: f64,
@@ -28,45 +30,90 @@ impl HitRecord {
     }
 }
 
-pub trait Hittable {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
+pub trait Hittable: Send + Sync {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
 
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool;
 
-    fn pdf_value(&mut self, _o: &Point3, _v: &Vec3) -> f64 {
+    fn pdf_value(&self, _o: &Point3, _v: &Vec3) -> f64 {
         0.0
     }
 
     fn random(&self, _o: &Vec3) -> Vec3 {
         Vec3(1.0, 0.0, 0.0)
     }
+
+    // Estimate of the power of the light given off by the object, in the
+    // units of Material::average_emission. Light sampling picks lights in
+    // proportion to it.
+    fn emitted_power(&self) -> f64 {
+        self.transformed_emitted_power(&Mat4::identity())
+    }
+
+    // emitted_power of the object moved by `matrix`, of which only the upper
+    // 3x3 part matters. Objects that move others pass their own matrix on,
+    // after `matrix`, down to the shapes, which give off light in proportion
+    // to their area once moved.
+    fn transformed_emitted_power(&self, _matrix: &Mat4) -> f64 {
+        0.0
+    }
+
+    // Area of the surface of the object moved by `matrix`, like
+    // transformed_emitted_power. 0 for objects that have no surface.
+    fn area(&self, _matrix: &Mat4) -> f64 {
+        0.0
+    }
+
+    // Adds the parts of the object that give off light to `lights`, in a
+    // form that can be sampled. `this` is the object itself, for objects
+    // that add themselves.
+    fn collect_lights(&self, this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        if self.emitted_power() > 0.0 {
+            lights.push(this.clone());
+        }
+    }
+}
+
+// Every part of `object` that gives off light, see Hittable::collect_lights
+pub fn collect_lights(object: &Arc<dyn Hittable>) -> Vec<Arc<dyn Hittable>> {
+    let mut lights = Vec::new();
+    object.collect_lights(object, &mut lights);
+    lights
+}
+
+// Power of a surface of the given area that gives off the same radiance in
+// every direction, see Material::average_emission
+pub fn area_light_power(area: f64, mat: &dyn Material) -> f64 {
+    PI * area * mat.average_emission()
 }
 
 pub struct Translate {
-    ptr: Rc<RefCell<dyn Hittable>>,
+    ptr: Arc<dyn Hittable>,
     offset: Vec3
 }
 
 impl Hittable for Translate {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let moved_r: Ray = Ray {
             origin: r.origin() - self.offset,
             direction: r.direction(),
-            tm: r.time()
+            tm: r.time(),
+            wavelength: r.wavelength()
         };
 
-        if !self.ptr.borrow_mut().hit(&moved_r, t_min, t_max, rec) {
+        if !self.ptr.hit(&moved_r, t_min, t_max, rec) {
             return false;
         };
 
+        // The normal already faces the ray. Setting it again would lose
+        // which side was hit.
         rec.p += self.offset;
-        rec.set_face_normal(&moved_r, rec.normal);
 
-        return true;
+        true
     }
 
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
-        if !self.ptr.borrow().bounding_box(time0, time1, output_box) {
+        if !self.ptr.bounding_box(time0, time1, output_box) {
             return false;
         };
 
@@ -75,12 +122,35 @@ impl Hittable for Translate {
             maximum: output_box.max() + self.offset,
         };
 
-        return true;
+        true
+    }
+
+    // Moved lights are sampled by moving the origin the other way
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.ptr.pdf_value(&(*o - self.offset), v)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.ptr.random(&(*o - self.offset))
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.ptr.transformed_emitted_power(matrix)
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.ptr.area(matrix)
+    }
+
+    fn collect_lights(&self, _this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        for light in collect_lights(&self.ptr) {
+            lights.push(Arc::new(Translate::new(light, self.offset)));
+        }
     }
 }
 
 impl Translate {
-    pub fn new(p: Rc<RefCell<dyn Hittable>>, displacement: Vec3) -> Self {
+    pub fn new(p: Arc<dyn Hittable>, displacement: Vec3) -> Self {
         Translate {
             ptr: p,
             offset: displacement
@@ -89,7 +159,7 @@ impl Translate {
 }
 
 pub struct RotateY {
-    ptr: Rc<RefCell<dyn Hittable>>,
+    ptr: Arc<dyn Hittable>,
     sin_theta: f64,
     cos_theta: f64,
     hasbox: bool,
@@ -97,7 +167,7 @@ pub struct RotateY {
 }
 
 impl Hittable for RotateY {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let mut origin = r.origin();
         let mut direction = r.direction();
 
@@ -108,12 +178,13 @@ impl Hittable for RotateY {
         direction[2] = self.sin_theta*r.direction()[0] + self.cos_theta*r.direction()[2];
         
         let rotated_r: Ray = Ray {
-            origin: origin,
-            direction: direction,
-            tm: r.time()
+            origin,
+            direction,
+            tm: r.time(),
+            wavelength: r.wavelength()
         };
 
-        if !self.ptr.borrow_mut().hit(&rotated_r, t_min, t_max, rec) {
+        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
             return false;
         };
 
@@ -126,20 +197,45 @@ impl Hittable for RotateY {
         normal[0] =   self.cos_theta*rec.normal[0] +  self.sin_theta*rec.normal[2];
         normal[2] = - self.sin_theta*rec.normal[0] +  self.cos_theta*rec.normal[2];
 
+        // Like Translate, the rotated normal already faces the ray
         rec.p = p;
-        rec.set_face_normal(&rotated_r, normal);
+        rec.normal = normal;
 
-        return true;
+        true
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
-        *output_box = self.bbox.clone();
-        return self.hasbox;
+        *output_box = self.bbox;
+        self.hasbox
+    }
+
+    // Rotations keep solid angles, so the pdf can be looked up in object space
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.ptr.pdf_value(&self.to_object(o), &self.to_object(v))
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.to_world(&self.ptr.random(&self.to_object(o)))
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.ptr.transformed_emitted_power(&(*matrix * self.matrix()))
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.ptr.area(&(*matrix * self.matrix()))
+    }
+
+    fn collect_lights(&self, _this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        let angle = self.sin_theta.atan2(self.cos_theta).to_degrees();
+        for light in collect_lights(&self.ptr) {
+            lights.push(Arc::new(RotateY::new(light, angle)));
+        }
     }
 }
 
 impl RotateY {
-    pub fn new(p: Rc<RefCell<dyn Hittable>>, angle: f64) -> Self {
+    pub fn new(p: Arc<dyn Hittable>, angle: f64) -> Self {
         let radians = angle.to_radians();
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
@@ -147,7 +243,7 @@ impl RotateY {
             minimum: Point3(0.0, 0.0, 0.0),
             maximum: Point3(0.0, 0.0, 0.0),
          };
-        let hasbox = p.borrow().bounding_box(0.0, 1.0, &mut bbox);
+        let hasbox = p.bounding_box(0.0, 1.0, &mut bbox);
 
         let mut min = Point3(f64::INFINITY,  f64::INFINITY,  f64::INFINITY);
         let mut max = Point3(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
@@ -179,10 +275,129 @@ impl RotateY {
 
         RotateY {
             ptr: p.clone(),
-            sin_theta: sin_theta,
-            cos_theta: cos_theta,
-            hasbox: hasbox,
-            bbox: bbox,
+            sin_theta,
+            cos_theta,
+            hasbox,
+            bbox,
         }        
     }
-}
\ No newline at end of file
+
+    fn to_object(&self, v: &Vec3) -> Vec3 {
+        Vec3(self.cos_theta*v[0] - self.sin_theta*v[2], v[1], self.sin_theta*v[0] + self.cos_theta*v[2])
+    }
+
+    fn to_world(&self, v: &Vec3) -> Vec3 {
+        Vec3(self.cos_theta*v[0] + self.sin_theta*v[2], v[1], -self.sin_theta*v[0] + self.cos_theta*v[2])
+    }
+
+    // to_world as a matrix, like Mat4::rotate_y
+    fn matrix(&self) -> Mat4 {
+        let mut t = Mat4::identity();
+        t.m[0][0] = self.cos_theta;
+        t.m[0][2] = self.sin_theta;
+        t.m[2][0] = -self.sin_theta;
+        t.m[2][2] = self.cos_theta;
+        t
+    }
+}
+
+// Swaps the front and the back of a surface, as seen by materials. From
+// the book, where it turns the Cornell box light to face down.
+pub struct FlipFace {
+    ptr: Arc<dyn Hittable>,
+}
+
+impl Hittable for FlipFace {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self.ptr.hit(r, t_min, t_max, rec) {
+            return false;
+        }
+
+        // The normal still faces the ray
+        rec.front_face = !rec.front_face;
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.ptr.bounding_box(time0, time1, output_box)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.ptr.pdf_value(o, v)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.ptr.random(o)
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.ptr.transformed_emitted_power(matrix)
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.ptr.area(matrix)
+    }
+
+    fn collect_lights(&self, _this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        for light in collect_lights(&self.ptr) {
+            lights.push(Arc::new(FlipFace::new(light)));
+        }
+    }
+}
+
+impl FlipFace {
+    pub fn new(p: Arc<dyn Hittable>) -> Self {
+        FlipFace {
+            ptr: p
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::sphere::Sphere;
+    use crate::material::DefaultMaterial;
+
+    fn empty_record() -> HitRecord {
+        HitRecord {
+            p: Point3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 0.0, 0.0),
+            mat_ptr: Arc::new(DefaultMaterial),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+        }
+    }
+
+    fn unit_sphere(center: Point3) -> Arc<dyn Hittable> {
+        Arc::new(Sphere { center, radius: 1.0, mat_ptr: Arc::new(DefaultMaterial) })
+    }
+
+    // Hits `object` from `origin` along `direction`, and checks that the
+    // normal faces the ray
+    fn hit(object: &dyn Hittable, origin: Point3, direction: Vec3) -> HitRecord {
+        let r = Ray { origin, direction, tm: 0.0, wavelength: 0.0 };
+        let mut rec = empty_record();
+        assert!(object.hit(&r, 0.001, f64::INFINITY, &mut rec));
+        assert!(Vec3::dot(rec.normal, direction) < 0.0);
+        rec
+    }
+
+    #[test]
+    fn moved_objects_keep_their_back_faces() {
+        let moved = Translate::new(unit_sphere(Point3(0.0, 0.0, 0.0)), Vec3(5.0, 0.0, 0.0));
+        assert!(hit(&moved, Point3(0.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0)).front_face);
+        let rec = hit(&moved, Point3(5.0, 0.0, 0.0), Vec3(1.0, 0.0, 0.0));
+        assert!(!rec.front_face);
+        assert!((rec.p - Point3(6.0, 0.0, 0.0)).near_zero());
+
+        let rotated = RotateY::new(unit_sphere(Point3(3.0, 0.0, 0.0)), 90.0);
+        assert!(hit(&rotated, Point3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0)).front_face);
+        let rec = hit(&rotated, Point3(0.0, 0.0, -3.0), Vec3(0.0, 0.0, -1.0));
+        assert!(!rec.front_face);
+        assert!((rec.p - Point3(0.0, 0.0, -4.0)).near_zero());
+    }
+}
@@ -7,14 +7,13 @@ use crate::material_first_week::Material;
 
 use crate::aabb_first_week::AABB;
 
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
 #[derive(Clone)]
 pub struct HitRecord {
     pub p: Point3,
     pub normal: Vec3,
-    pub mat_ptr: Rc<RefCell<dyn Material>>,
+    pub mat_ptr: Arc<dyn Material>,
     pub t: f64,
     pub u: f64,
     pub v: f64,
@@ -28,36 +27,37 @@ impl HitRecord {
     }
 }
 
-pub trait Hittable {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
+pub trait Hittable: Send + Sync {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool;
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool;
 }
 
 pub struct Translate {
-    ptr: Rc<RefCell<dyn Hittable>>,
+    ptr: Arc<dyn Hittable>,
     offset: Vec3
 }
 
 impl Hittable for Translate {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let moved_r: Ray = Ray {
             origin: r.origin() - self.offset,
             direction: r.direction(),
-            tm: r.time()
+            tm: r.time(),
+            wavelength: r.wavelength()
         };
 
-        if !self.ptr.borrow_mut().hit(&moved_r, t_min, t_max, rec) {
+        if !self.ptr.hit(&moved_r, t_min, t_max, rec) {
             return false;
         };
 
         rec.p += self.offset;
         rec.set_face_normal(&moved_r, rec.normal);
 
-        return true;
+        true
     }
 
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
-        if !self.ptr.borrow().bounding_box(time0, time1, output_box) {
+        if !self.ptr.bounding_box(time0, time1, output_box) {
             return false;
         };
 
@@ -66,12 +66,12 @@ impl Hittable for Translate {
             maximum: output_box.max() + self.offset,
         };
 
-        return true;
+        true
     }
 }
 
 impl Translate {
-    pub fn new(p: Rc<RefCell<dyn Hittable>>, displacement: Vec3) -> Self {
+    pub fn new(p: Arc<dyn Hittable>, displacement: Vec3) -> Self {
         Translate {
             ptr: p,
             offset: displacement
@@ -80,7 +80,7 @@ impl Translate {
 }
 
 pub struct RotateY {
-    ptr: Rc<RefCell<dyn Hittable>>,
+    ptr: Arc<dyn Hittable>,
     sin_theta: f64,
     cos_theta: f64,
     hasbox: bool,
@@ -88,7 +88,7 @@ pub struct RotateY {
 }
 
 impl Hittable for RotateY {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let mut origin = r.origin();
         let mut direction = r.direction();
 
@@ -99,12 +99,13 @@ impl Hittable for RotateY {
         direction[2] = self.sin_theta*r.direction()[0] + self.cos_theta*r.direction()[2];
         
         let rotated_r: Ray = Ray {
-            origin: origin,
-            direction: direction,
-            tm: r.time()
+            origin,
+            direction,
+            tm: r.time(),
+            wavelength: r.wavelength()
         };
 
-        if !self.ptr.borrow_mut().hit(&rotated_r, t_min, t_max, rec) {
+        if !self.ptr.hit(&rotated_r, t_min, t_max, rec) {
             return false;
         };
 
@@ -120,17 +121,17 @@ impl Hittable for RotateY {
         rec.p = p;
         rec.set_face_normal(&rotated_r, normal);
 
-        return true;
+        true
     }
 
     fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
-        *output_box = self.bbox.clone();
-        return self.hasbox;
+        *output_box = self.bbox;
+        self.hasbox
     }
 }
 
 impl RotateY {
-    pub fn new(p: Rc<RefCell<dyn Hittable>>, angle: f64) -> Self {
+    pub fn new(p: Arc<dyn Hittable>, angle: f64) -> Self {
         let radians = angle.to_radians();
         let sin_theta = radians.sin();
         let cos_theta = radians.cos();
@@ -138,7 +139,7 @@ impl RotateY {
             minimum: Point3(0.0, 0.0, 0.0),
             maximum: Point3(0.0, 0.0, 0.0),
          };
-        let hasbox = p.borrow().bounding_box(0.0, 1.0, &mut bbox);
+        let hasbox = p.bounding_box(0.0, 1.0, &mut bbox);
 
         let mut min = Point3(f64::INFINITY,  f64::INFINITY,  f64::INFINITY);
         let mut max = Point3(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
@@ -170,10 +171,10 @@ impl RotateY {
 
         RotateY {
             ptr: p.clone(),
-            sin_theta: sin_theta,
-            cos_theta: cos_theta,
-            hasbox: hasbox,
-            bbox: bbox,
+            sin_theta,
+            cos_theta,
+            hasbox,
+            bbox,
         }        
     }
 }
\ No newline at end of file
@@ -1,5 +1,4 @@
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
 use crate::rtweekend::random_double_range;
 use crate::vec3::Vec3;
@@ -13,10 +12,12 @@ use crate::material::DefaultMaterial;
 use crate::aabb::AABB;
 use crate::aabb::surrounding_box;
 
+use crate::mat4::Mat4;
+
 // use std::time::Instant;
 
 pub struct HittableList{
-    pub objects: Vec<Rc<RefCell<dyn Hittable>>>,
+    pub objects: Vec<Arc<dyn Hittable>>,
 } 
 
 impl HittableList {
@@ -25,17 +26,17 @@ impl HittableList {
         self.objects.clear()
     }
 
-    pub fn add(&mut self, object: Rc<RefCell<dyn Hittable>>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object)
     }
 }
 
 impl Hittable for HittableList {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let mut temp_rec: HitRecord = HitRecord {
             p: Point3(0.0, 0.0, 0.0),
             normal: Vec3(0.0, 0.0, 0.0),
-            mat_ptr: Rc::new(RefCell::new(DefaultMaterial)),
+            mat_ptr: Arc::new(DefaultMaterial),
             t: 0.0,
             u: 0.0,
             v: 0.0,
@@ -45,7 +46,7 @@ impl Hittable for HittableList {
         let mut closest_so_far: f64 = t_max;
 
         for object in self.objects.iter() {
-            let j = object.borrow_mut().hit(r, t_min, closest_so_far, &mut temp_rec);
+            let j = object.hit(r, t_min, closest_so_far, &mut temp_rec);
             if j {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
@@ -53,7 +54,7 @@ impl Hittable for HittableList {
             }
         }
 
-        return hit_anything;
+        hit_anything
     }
 
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
@@ -69,7 +70,7 @@ impl Hittable for HittableList {
         let mut first_box: bool = true;
 
         for object in self.objects.iter() {
-            if !object.borrow().bounding_box(time0, time1, &mut temp_box) {return false;}
+            if !object.bounding_box(time0, time1, &mut temp_box) {return false;}
             *output_box = if first_box { temp_box } else { surrounding_box(output_box, &temp_box) };
             first_box = false;
         }
@@ -77,18 +78,32 @@ impl Hittable for HittableList {
         true
     }
 
-    fn pdf_value(&mut self, o: &Vec3, v: &Vec3) -> f64 {
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
         let weight = 1.0 / self.objects.len() as f64;
         let mut sum = 0.0;
 
         for i in 0..self.objects.len() {
-            sum += weight * self.objects[i].borrow_mut().pdf_value(o, v);
+            sum += weight * self.objects[i].pdf_value(o, v);
         }
         sum
     }
 
     fn random(&self, o: &Vec3) -> Vec3 {
         let int_size = self.objects.len();
-        self.objects[random_double_range(0.0, (int_size-1) as f64) as usize].borrow_mut().random(o)
+        self.objects[(random_double_range(0.0, int_size as f64) as usize).min(int_size-1)].random(o)
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.objects.iter().map(|object| object.transformed_emitted_power(matrix)).sum()
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.objects.iter().map(|object| object.area(matrix)).sum()
+    }
+
+    fn collect_lights(&self, _this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        for object in &self.objects {
+            object.collect_lights(object, lights);
+        }
     }
 }
\ No newline at end of file
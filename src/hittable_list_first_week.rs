@@ -1,5 +1,4 @@
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
 use crate::vec3::Vec3;
 use crate::vec3::Point3;
@@ -13,7 +12,7 @@ use crate::aabb_first_week::AABB;
 use crate::aabb_first_week::surrounding_box;
 
 pub struct HittableList{
-    pub objects: Vec<Rc<RefCell<dyn Hittable>>>,
+    pub objects: Vec<Arc<dyn Hittable>>,
 } 
 
 impl HittableList {
@@ -22,17 +21,17 @@ impl HittableList {
         self.objects.clear()
     }
 
-    pub fn add(&mut self, object: Rc<RefCell<dyn Hittable>>) {
+    pub fn add(&mut self, object: Arc<dyn Hittable>) {
         self.objects.push(object)
     }
 }
 
 impl Hittable for HittableList {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let mut temp_rec: HitRecord = HitRecord {
             p: Point3(0.0, 0.0, 0.0),
             normal: Vec3(0.0, 0.0, 0.0),
-            mat_ptr: Rc::new(RefCell::new(DefaultMaterial)),
+            mat_ptr: Arc::new(DefaultMaterial),
             t: 0.0,
             u: 0.0,
             v: 0.0,
@@ -42,7 +41,7 @@ impl Hittable for HittableList {
         let mut closest_so_far: f64 = t_max;
 
         for object in self.objects.iter() {
-            let j = object.borrow_mut().hit(r, t_min, closest_so_far, &mut temp_rec);
+            let j = object.hit(r, t_min, closest_so_far, &mut temp_rec);
             if j {
                 hit_anything = true;
                 closest_so_far = temp_rec.t;
@@ -50,7 +49,7 @@ impl Hittable for HittableList {
             }
         }
 
-        return hit_anything;
+        hit_anything
     }
 
     fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
@@ -66,7 +65,7 @@ impl Hittable for HittableList {
         let mut first_box: bool = true;
 
         for object in self.objects.iter() {
-            if !object.borrow().bounding_box(time0, time1, &mut temp_box) {return false;}
+            if !object.bounding_box(time0, time1, &mut temp_box) {return false;}
             *output_box = if first_box { temp_box } else { surrounding_box(output_box, &temp_box) };
             first_box = false;
         }
@@ -0,0 +1,450 @@
+// Instancing: many placements of the same geometry.
+//
+// An InstanceSet holds a few prototypes (a mesh, a BVH, a list, ...) and any
+// number of instances, each of which places one prototype with a matrix and
+// may give it another material. An instance only keeps its matrix and the
+// inverse, both without their last row of 0 0 0 1, and two indices, so a
+// million of them fit where a million copies of a mesh would not. The
+// instances sit in a flattened BVH of their own, above the BVHs inside the
+// prototypes, which makes this the top level of a two level BVH.
+//
+// Rays are taken into the space of the prototype like in Transform.
+
+use crate::hittable::Hittable;
+use crate::hittable::HitRecord;
+use crate::hittable::collect_lights;
+use crate::hittable::area_light_power;
+use crate::ray::Ray;
+
+use crate::material::Material;
+use crate::aabb::AABB;
+
+use crate::bvh_build::BvhError;
+use crate::linear_bvh::FlatBvh;
+use crate::mat4::Mat4;
+use crate::mat4::Affine;
+use crate::transform::Transform;
+
+use crate::rtweekend::random_double_range;
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// No material override
+const NO_MATERIAL: u32 = u32::MAX;
+
+struct Instance {
+    // Prototype to world
+    matrix: Affine,
+    // World to prototype
+    inverse: Affine,
+    prototype: u32,
+    // Index into the materials of the set, or NO_MATERIAL
+    material: u32,
+}
+
+pub struct InstanceSet {
+    tree: FlatBvh,
+    prototypes: Vec<Arc<dyn Hittable>>,
+    materials: Vec<Arc<dyn Material>>,
+    // In the order of the tree, so each leaf refers to a range
+    instances: Vec<Instance>,
+}
+
+impl InstanceSet {
+    pub fn len(&self) -> usize {
+        self.instances.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.instances.is_empty()
+    }
+
+    fn object_ray(instance: &Instance, r: &Ray) -> Ray {
+        Ray {
+            origin: instance.inverse.transform_point(&r.origin()),
+            direction: instance.inverse.transform_vector(&r.direction()),
+            tm: r.time(),
+            wavelength: r.wavelength(),
+        }
+    }
+}
+
+impl Hittable for InstanceSet {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        self.tree.traverse(r, t_min, t_max, |first, count, closest_so_far| {
+            let mut hit_anything = false;
+            let mut closest_so_far = closest_so_far;
+            for instance in &self.instances[first..first + count] {
+                let object_r = InstanceSet::object_ray(instance, r);
+                if !self.prototypes[instance.prototype as usize].hit(&object_r, t_min, closest_so_far, rec) {
+                    continue;
+                }
+
+                // The direction is not normalised, so t is the same in both spaces
+                rec.p = r.at(rec.t);
+                rec.normal = Vec3::unit_vector(instance.inverse.transform_normal(&rec.normal));
+                if instance.material != NO_MATERIAL {
+                    rec.mat_ptr = self.materials[instance.material as usize].clone();
+                }
+                hit_anything = true;
+                closest_so_far = rec.t;
+            }
+            if hit_anything { Some(closest_so_far) } else { None }
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = self.tree.bounds();
+        true
+    }
+
+    // Every instance is picked with the same probability, like HittableList.
+    // See Transform for the change of solid angle.
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let w = Vec3::unit_vector(*v);
+        let weight = 1.0 / self.instances.len() as f64;
+        let mut sum = 0.0;
+
+        for instance in &self.instances {
+            let object_v = instance.inverse.transform_vector(&w);
+            let stretch = object_v.length();
+            if stretch == 0.0 {
+                continue;
+            }
+            let object_pdf = self.prototypes[instance.prototype as usize].pdf_value(&instance.inverse.transform_point(o), &object_v);
+            sum += weight * object_pdf * instance.inverse.determinant3().abs() / (stretch * stretch * stretch);
+        }
+        sum
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        let int_size = self.instances.len();
+        let instance = &self.instances[(random_double_range(0.0, int_size as f64) as usize).min(int_size-1)];
+        instance.matrix.transform_vector(&self.prototypes[instance.prototype as usize].random(&instance.inverse.transform_point(o)))
+    }
+
+    // Like Transform. Instances with another material give off the light of
+    // that material over the whole area of their prototype.
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.instances.iter().map(|instance| {
+            let prototype = &self.prototypes[instance.prototype as usize];
+            if instance.material == NO_MATERIAL {
+                prototype.transformed_emitted_power(&(*matrix * instance.matrix.to_mat4()))
+            } else {
+                let material = &self.materials[instance.material as usize];
+                if material.average_emission() <= 0.0 {
+                    return 0.0;
+                }
+                area_light_power(prototype.area(&(*matrix * instance.matrix.to_mat4())), material.as_ref())
+            }
+        }).sum()
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.instances.iter().map(|instance| self.prototypes[instance.prototype as usize].area(&(*matrix * instance.matrix.to_mat4()))).sum()
+    }
+
+    // The lights of each prototype, placed once for every instance. An
+    // instance with another material is a light as a whole if that material
+    // gives off light, and not at all otherwise.
+    fn collect_lights(&self, _this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        let prototype_lights: Vec<Vec<Arc<dyn Hittable>>> = self.prototypes.iter().map(collect_lights).collect();
+        for instance in &self.instances {
+            if instance.material == NO_MATERIAL {
+                for part in &prototype_lights[instance.prototype as usize] {
+                    if let Some(light) = Transform::new(part.clone(), instance.matrix.to_mat4()) {
+                        lights.push(Arc::new(light));
+                    }
+                }
+                continue;
+            }
+
+            let material = &self.materials[instance.material as usize];
+            if material.average_emission() <= 0.0 {
+                continue;
+            }
+            if let Some(transform) = Transform::new(self.prototypes[instance.prototype as usize].clone(), instance.matrix.to_mat4()) {
+                let light = OverrideLight {
+                    transform,
+                    material: material.clone(),
+                };
+                if light.emitted_power() > 0.0 {
+                    lights.push(Arc::new(light));
+                }
+            }
+        }
+    }
+}
+
+// One instance with another material, as a light. The prototype keeps its
+// own materials, so the light is hit with the instance's material and its
+// power comes from that material over the area of the prototype.
+struct OverrideLight {
+    transform: Transform,
+    material: Arc<dyn Material>,
+}
+
+impl Hittable for OverrideLight {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        if !self.transform.hit(r, t_min, t_max, rec) {
+            return false;
+        }
+        rec.mat_ptr = self.material.clone();
+        true
+    }
+
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        self.transform.bounding_box(time0, time1, output_box)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        self.transform.pdf_value(o, v)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.transform.random(o)
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        area_light_power(self.transform.area(matrix), self.material.as_ref())
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.transform.area(matrix)
+    }
+}
+
+struct Placement {
+    matrix: Mat4,
+    inverse: Mat4,
+    prototype: u32,
+    material: u32,
+}
+
+// Collects the instances of an InstanceSet. Prototypes and materials are
+// told apart by their Arc, so passing the same one again shares it.
+pub struct InstanceSetBuilder {
+    prototypes: Vec<Arc<dyn Hittable>>,
+    prototype_index: HashMap<*const (), u32>,
+    materials: Vec<Arc<dyn Material>>,
+    material_index: HashMap<*const (), u32>,
+    placements: Vec<Placement>,
+}
+
+impl InstanceSetBuilder {
+    pub fn new() -> Self {
+        InstanceSetBuilder {
+            prototypes: Vec::new(),
+            prototype_index: HashMap::new(),
+            materials: Vec::new(),
+            material_index: HashMap::new(),
+            placements: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.placements.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.placements.is_empty()
+    }
+
+    // Places `prototype` with `matrix`, using `material` instead of its own
+    // materials if given. Returns false, and adds nothing, when the matrix
+    // cannot be inverted. Its last row is taken to be 0 0 0 1.
+    pub fn add(&mut self, prototype: &Arc<dyn Hittable>, matrix: Mat4, material: Option<&Arc<dyn Material>>) -> bool {
+        let inverse = match matrix.inverse() {
+            Some(inverse) => inverse,
+            None => return false,
+        };
+
+        let prototypes = &mut self.prototypes;
+        let prototype = *self.prototype_index.entry(Arc::as_ptr(prototype) as *const ()).or_insert_with(|| {
+            prototypes.push(prototype.clone());
+            (prototypes.len() - 1) as u32
+        });
+        let material = match material {
+            Some(material) => {
+                let materials = &mut self.materials;
+                *self.material_index.entry(Arc::as_ptr(material) as *const ()).or_insert_with(|| {
+                    materials.push(material.clone());
+                    (materials.len() - 1) as u32
+                })
+            },
+            None => NO_MATERIAL,
+        };
+
+        self.placements.push(Placement {
+            matrix,
+            inverse,
+            prototype,
+            material,
+        });
+        true
+    }
+
+    // The index in a BvhError is the one of the instance
+    pub fn build(self, time0: f64, time1: f64) -> Result<InstanceSet, BvhError> {
+        let mut prototype_boxes: Vec<Option<AABB>> = Vec::with_capacity(self.prototypes.len());
+        for prototype in &self.prototypes {
+            let mut output_box = AABB {
+                minimum: Point3(0.0, 0.0, 0.0),
+                maximum: Point3(0.0, 0.0, 0.0)
+            };
+            prototype_boxes.push(if prototype.bounding_box(time0, time1, &mut output_box) { Some(output_box) } else { None });
+        }
+
+        let mut boxes: Vec<AABB> = Vec::with_capacity(self.placements.len());
+        for (i, placement) in self.placements.iter().enumerate() {
+            match prototype_boxes[placement.prototype as usize] {
+                Some(bbox) => boxes.push(transform_box(&placement.matrix, &bbox)),
+                None => return Err(BvhError::NoBoundingBox { index: i }),
+            }
+        }
+
+        let (tree, order) = FlatBvh::new(&boxes)?;
+        drop(boxes);
+        let mut placements: Vec<Option<Placement>> = self.placements.into_iter().map(Some).collect();
+        let instances = order.iter().map(|&i| {
+            let placement = placements[i].take().unwrap();
+            Instance {
+                matrix: Affine::from_mat4(&placement.matrix),
+                inverse: Affine::from_mat4(&placement.inverse),
+                prototype: placement.prototype,
+                material: placement.material,
+            }
+        }).collect();
+
+        Ok(InstanceSet {
+            tree,
+            prototypes: self.prototypes,
+            materials: self.materials,
+            instances,
+        })
+    }
+}
+
+impl Default for InstanceSetBuilder {
+    fn default() -> Self {
+        InstanceSetBuilder::new()
+    }
+}
+
+// The box around the corners of `bbox` moved by `matrix`
+fn transform_box(matrix: &Mat4, bbox: &AABB) -> AABB {
+    let mut min = Point3(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+    let mut max = Point3(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+    for i in 0..8 {
+        let corner = Point3(
+            if i & 1 == 0 { bbox.min().x() } else { bbox.max().x() },
+            if i & 2 == 0 { bbox.min().y() } else { bbox.max().y() },
+            if i & 4 == 0 { bbox.min().z() } else { bbox.max().z() },
+        );
+        let tester = matrix.transform_point(&corner);
+        for c in 0..3 {
+            min[c] = min[c].min(tester[c]);
+            max[c] = max[c].max(tester[c]);
+        }
+    }
+
+    AABB {
+        minimum: min,
+        maximum: max
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::material::DefaultMaterial;
+    use crate::material::DiffuseLight;
+    use crate::material::Lambertian;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color;
+
+    use std::f64::consts::PI;
+
+    fn unit_sphere(material: &Arc<dyn Material>) -> Arc<dyn Hittable> {
+        Arc::new(Sphere { center: Point3(0.0, 0.0, 0.0), radius: 1.0, mat_ptr: material.clone() })
+    }
+
+    fn same(a: &Arc<dyn Material>, b: &Arc<dyn Material>) -> bool {
+        Arc::as_ptr(a) as *const () == Arc::as_ptr(b) as *const ()
+    }
+
+    fn hit(set: &InstanceSet, origin: Point3, direction: Vec3) -> Option<HitRecord> {
+        let r = Ray { origin, direction, tm: 0.0, wavelength: 0.0 };
+        let mut rec = HitRecord {
+            p: Point3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 0.0, 0.0),
+            mat_ptr: Arc::new(DefaultMaterial),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+        };
+        if set.hit(&r, 0.001, f64::INFINITY, &mut rec) { Some(rec) } else { None }
+    }
+
+    #[test]
+    fn instances_are_hit_where_they_are_placed() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(&Color(0.5, 0.5, 0.5)));
+        let red: Arc<dyn Material> = Arc::new(Lambertian::new(&Color(0.8, 0.1, 0.1)));
+        let ball = unit_sphere(&grey);
+
+        let mut builder = InstanceSetBuilder::new();
+        assert!(builder.add(&ball, Mat4::translate(Vec3(0.0, 0.0, -5.0)), None));
+        // Hidden behind the first one
+        assert!(builder.add(&ball, Mat4::translate(Vec3(0.0, 0.0, -10.0)), None));
+        assert!(builder.add(&ball, Mat4::translate(Vec3(4.0, 0.0, -5.0)) * Mat4::scale(Vec3(2.0, 1.0, 1.0)), Some(&red)));
+        assert!(!builder.add(&ball, Mat4::scale(Vec3(0.0, 1.0, 1.0)), None));
+        let set = builder.build(0.0, 1.0).unwrap();
+        assert_eq!(set.len(), 3);
+
+        let rec = hit(&set, Point3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0)).unwrap();
+        assert!((rec.t - 4.0).abs() < 1.0e-9);
+        assert!((rec.normal - Vec3(0.0, 0.0, 1.0)).near_zero());
+        assert!(same(&rec.mat_ptr, &grey));
+
+        // The stretched instance, from the side, with its own material
+        let rec = hit(&set, Point3(10.0, 0.0, -5.0), Vec3(-1.0, 0.0, 0.0)).unwrap();
+        assert!((rec.p - Point3(6.0, 0.0, -5.0)).near_zero());
+        assert!((rec.normal - Vec3(1.0, 0.0, 0.0)).near_zero());
+        assert!(same(&rec.mat_ptr, &red));
+
+        assert!(hit(&set, Point3(0.0, 3.0, 0.0), Vec3(0.0, 0.0, -1.0)).is_none());
+    }
+
+    #[test]
+    fn override_materials_decide_what_gives_off_light() {
+        let grey: Arc<dyn Material> = Arc::new(Lambertian::new(&Color(0.5, 0.5, 0.5)));
+        let lamp: Arc<dyn Material> = Arc::new(DiffuseLight::new(Color(1.0, 1.0, 1.0)));
+        let ball = unit_sphere(&grey);
+        let bulb = unit_sphere(&lamp);
+
+        let mut builder = InstanceSetBuilder::new();
+        builder.add(&ball, Mat4::translate(Vec3(0.0, 0.0, -5.0)), Some(&lamp));
+        builder.add(&bulb, Mat4::translate(Vec3(5.0, 0.0, -5.0)), Some(&grey));
+        builder.add(&bulb, Mat4::translate(Vec3(-5.0, 0.0, -5.0)), None);
+        builder.add(&ball, Mat4::translate(Vec3(0.0, 5.0, -5.0)), None);
+        let set = builder.build(0.0, 1.0).unwrap();
+        assert!(same(&hit(&set, Point3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0)).unwrap().mat_ptr, &lamp));
+        assert!(same(&hit(&set, Point3(5.0, 0.0, 0.0), Vec3(0.0, 0.0, -1.0)).unwrap().mat_ptr, &grey));
+
+        // Two unit spheres give off light, each with power pi * 4 pi
+        let set: Arc<dyn Hittable> = Arc::new(set);
+        let expected = 4.0 * PI * PI;
+        assert!((set.emitted_power() - 2.0 * expected).abs() < 1.0e-3 * expected, "power {}", set.emitted_power());
+
+        let lights = collect_lights(&set);
+        assert_eq!(lights.len(), 2);
+        for light in &lights {
+            assert!((light.emitted_power() - expected).abs() < 1.0e-3 * expected, "power {}", light.emitted_power());
+        }
+    }
+}
@@ -0,0 +1,366 @@
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+use crate::vec3::Color;
+
+use crate::ray::Ray;
+
+use crate::hittable::HitRecord;
+use crate::hittable::Hittable;
+
+use crate::material::DefaultMaterial;
+use crate::material::ScatterRecord;
+
+use crate::pdf::Pdf;
+use crate::pdf::HittablePdf;
+use crate::pdf::MixturePdf;
+use crate::pdf::SpherePdf;
+
+use crate::environment::Environment;
+
+use crate::delta_light::DeltaLight;
+
+use std::sync::Arc;
+
+// Computes the radiance carried back along a camera ray. Implementations own
+// the scene they trace, and are shared by all render threads.
+pub trait Integrator: Send + Sync {
+    fn ray_color(&self, r: &Ray, background: &Color, depth: u64) -> Color;
+}
+
+// The path tracer from Ray Tracing: The Rest of Your Life. Scattered rays are
+// drawn half of the time towards `lights` and half of the time from the
+// material's own pdf. Without lights only the material's pdf is used.
+// Scenes are rendered with MisPathIntegrator below instead.
+pub struct PathIntegrator {
+    pub world: Arc<dyn Hittable>,
+    pub lights: Option<Arc<dyn Hittable>>,
+}
+
+impl Integrator for PathIntegrator {
+    fn ray_color(&self, r: &Ray, background: &Color, depth: u64) -> Color {
+        let mut rec: HitRecord = HitRecord {
+            p: Point3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 0.0, 0.0),
+            mat_ptr: Arc::new(DefaultMaterial),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+        };
+
+        // Limits the callback length. Guards against stack overflow
+        if depth == 0 {
+            return Color(0.0, 0.0, 0.0);
+        }
+
+        if !self.world.hit(r, 0.001, f64::INFINITY, &mut rec) {
+            return *background;
+        }
+
+        let mut srec: ScatterRecord = ScatterRecord {
+            attenuation: Vec3(0.0, 0.0, 0.0),
+            pdf_ptr: Arc::new(SpherePdf()),
+            skip_pdf: false,
+            skip_pdf_ray:
+                Ray {
+                    origin: Vec3(0.0, 0.0, 0.0),
+                    direction: Vec3(0.0, 0.0, 0.0),
+                    tm: 0.0,
+                    wavelength: 0.0 }
+        };
+        let color_from_emission = rec.mat_ptr.emitted(r, &rec, rec.u, rec.v, &rec.p);
+
+        if !rec.mat_ptr.scatter(r, &rec, &mut srec) {
+            return color_from_emission;
+        }
+
+        if srec.skip_pdf {
+            return srec.attenuation * self.ray_color(&srec.skip_pdf_ray, background, depth-1)
+        }
+
+        let p: Arc<dyn Pdf> = match &self.lights {
+            Some(lights) => {
+                let light_pdf = Arc::new(HittablePdf {objects: lights.clone(), origin: rec.p});
+                Arc::new(MixturePdf(light_pdf, srec.pdf_ptr.clone()))
+            },
+            None => srec.pdf_ptr.clone(),
+        };
+
+        let scattered = Ray {origin: rec.p, direction: p.generate(), tm: r.time(), wavelength: r.wavelength()};
+        let pdf_val = p.value(&scattered.direction());
+        if pdf_val <= 0.0 {
+            return color_from_emission;
+        }
+
+        let scattering_color = rec.mat_ptr.scattering_color(r, &rec, &srec, &scattered);
+
+        let color_from_scatter = (scattering_color * self.ray_color(&scattered, background, depth-1)) / pdf_val;
+
+        color_from_emission + color_from_scatter
+    }
+}
+
+// Path tracer with next event estimation. At every diffuse bounce one
+// direction is drawn towards `lights` and one from the material's pdf, and
+// the light found by each is weighted with the power heuristic, so whichever
+// strategy is better at finding a light gets most of the weight. Small bright
+// lights are then found by light sampling, and light reflected by narrow
+// lobes by the material's pdf, which keeps the fireflies of PathIntegrator's
+// fixed 50/50 mixture away. Light reached through materials that skip the
+// pdf (metal and glass) can only be found by following them, so it is
+// counted in full. Without lights this is an ordinary path tracer.
+//
+// Rays that hit nothing see `environment`, or the background colour when
+// there is none. An environment that can be sampled is also sampled at
+// every diffuse bounce, weighted against the material's pdf the same way.
+// Every one of `delta_lights` is sampled at each diffuse bounce as well.
+pub struct MisPathIntegrator {
+    pub world: Arc<dyn Hittable>,
+    pub lights: Option<Arc<dyn Hittable>>,
+    pub environment: Option<Arc<dyn Environment>>,
+    pub delta_lights: Vec<Arc<dyn DeltaLight>>,
+}
+
+// Weight of a sample drawn with density f when the same direction could also
+// have been drawn with density g (Veach's power heuristic with exponent 2)
+pub fn power_heuristic(f: f64, g: f64) -> f64 {
+    let f2 = f * f;
+    let g2 = g * g;
+    if f2 + g2 == 0.0 {
+        return 0.0;
+    }
+    f2 / (f2 + g2)
+}
+
+fn empty_hit_record() -> HitRecord {
+    HitRecord {
+        p: Point3(0.0, 0.0, 0.0),
+        normal: Vec3(0.0, 0.0, 0.0),
+        mat_ptr: Arc::new(DefaultMaterial),
+        t: 0.0,
+        u: 0.0,
+        v: 0.0,
+        front_face: false,
+    }
+}
+
+impl MisPathIntegrator {
+    // Light reaching `rec.p` from a direction drawn towards the lights,
+    // times the material's reflectance, weighted against the material's pdf
+    fn sample_light(&self, lights: &Arc<dyn Hittable>, r: &Ray, rec: &HitRecord, srec: &ScatterRecord) -> Color {
+        let direction = lights.random(&rec.p);
+        let light_pdf = lights.pdf_value(&rec.p, &direction);
+        if light_pdf <= 0.0 {
+            return Color(0.0, 0.0, 0.0);
+        }
+
+        let to_light = Ray { origin: rec.p, direction, tm: r.time(), wavelength: r.wavelength() };
+        let scattering_color = rec.mat_ptr.scattering_color(r, rec, srec, &to_light);
+        if scattering_color.near_zero() {
+            return Color(0.0, 0.0, 0.0);
+        }
+
+        // Whatever is hit first is what is seen, so occluders give no light
+        let mut light_rec = empty_hit_record();
+        if !self.world.hit(&to_light, 0.001, f64::INFINITY, &mut light_rec) {
+            return Color(0.0, 0.0, 0.0);
+        }
+        let emitted = light_rec.mat_ptr.emitted(&to_light, &light_rec, light_rec.u, light_rec.v, &light_rec.p);
+
+        let weight = power_heuristic(light_pdf, srec.pdf_ptr.value(&direction));
+        scattering_color * emitted * weight / light_pdf
+    }
+
+    // Like sample_light, for a direction drawn from the environment. Only
+    // directions that leave the scene see it.
+    fn sample_environment(&self, environment: &Arc<dyn Environment>, r: &Ray, rec: &HitRecord, srec: &ScatterRecord) -> Color {
+        let direction = match environment.sample() {
+            Some(direction) => direction,
+            None => return Color(0.0, 0.0, 0.0),
+        };
+        let environment_pdf = environment.pdf_value(&direction);
+        if environment_pdf <= 0.0 {
+            return Color(0.0, 0.0, 0.0);
+        }
+
+        let to_environment = Ray { origin: rec.p, direction, tm: r.time(), wavelength: r.wavelength() };
+        let scattering_color = rec.mat_ptr.scattering_color(r, rec, srec, &to_environment);
+        if scattering_color.near_zero() {
+            return Color(0.0, 0.0, 0.0);
+        }
+
+        let mut blocker_rec = empty_hit_record();
+        if self.world.hit(&to_environment, 0.001, f64::INFINITY, &mut blocker_rec) {
+            return Color(0.0, 0.0, 0.0);
+        }
+
+        let weight = power_heuristic(environment_pdf, srec.pdf_ptr.value(&direction));
+        scattering_color * environment.value(&direction) * weight / environment_pdf
+    }
+
+    // Light reaching `rec.p` from the delta lights, times the material's
+    // reflectance. There is no pdf to divide by, as each light is reached
+    // along one direction only.
+    fn sample_delta_lights(&self, r: &Ray, rec: &HitRecord, srec: &ScatterRecord) -> Color {
+        let mut color = Color(0.0, 0.0, 0.0);
+        for light in &self.delta_lights {
+            let sample = match light.sample(&rec.p) {
+                Some(sample) => sample,
+                None => continue,
+            };
+
+            let to_light = Ray { origin: rec.p, direction: sample.direction, tm: r.time(), wavelength: r.wavelength() };
+            let scattering_color = rec.mat_ptr.scattering_color(r, rec, srec, &to_light);
+            if scattering_color.near_zero() {
+                continue;
+            }
+
+            let mut blocker_rec = empty_hit_record();
+            if self.world.hit(&to_light, 0.001, sample.distance - 0.001, &mut blocker_rec) {
+                continue;
+            }
+            color += scattering_color * sample.irradiance;
+        }
+        color
+    }
+}
+
+impl Integrator for MisPathIntegrator {
+    fn ray_color(&self, r: &Ray, background: &Color, depth: u64) -> Color {
+        let mut color = Color(0.0, 0.0, 0.0);
+        // Product of the reflectances divided by the pdfs along the path
+        let mut throughput = Color(1.0, 1.0, 1.0);
+        let mut ray = *r;
+        // Density of the material's pdf for the current ray, or None when
+        // light sampling could not have found the same light
+        let mut scatter_pdf: Option<f64> = None;
+
+        for _ in 0..depth {
+            let mut rec = empty_hit_record();
+            if !self.world.hit(&ray, 0.001, f64::INFINITY, &mut rec) {
+                let seen = match &self.environment {
+                    Some(environment) => {
+                        let weight = match scatter_pdf {
+                            Some(pdf) => power_heuristic(pdf, environment.pdf_value(&ray.direction())),
+                            None => 1.0,
+                        };
+                        environment.value(&ray.direction()) * weight
+                    },
+                    None => *background,
+                };
+                color += throughput * seen;
+                break;
+            }
+
+            let emitted = rec.mat_ptr.emitted(&ray, &rec, rec.u, rec.v, &rec.p);
+            let weight = match (scatter_pdf, &self.lights) {
+                (Some(pdf), Some(lights)) => power_heuristic(pdf, lights.pdf_value(&ray.origin(), &ray.direction())),
+                _ => 1.0,
+            };
+            color += throughput * emitted * weight;
+
+            let mut srec: ScatterRecord = ScatterRecord {
+                attenuation: Vec3(0.0, 0.0, 0.0),
+                pdf_ptr: Arc::new(SpherePdf()),
+                skip_pdf: false,
+                skip_pdf_ray:
+                    Ray {
+                        origin: Vec3(0.0, 0.0, 0.0),
+                        direction: Vec3(0.0, 0.0, 0.0),
+                        tm: 0.0,
+                        wavelength: 0.0 }
+            };
+            if !rec.mat_ptr.scatter(&ray, &rec, &mut srec) {
+                break;
+            }
+
+            if srec.skip_pdf {
+                throughput = throughput * srec.attenuation;
+                ray = srec.skip_pdf_ray;
+                scatter_pdf = None;
+                continue;
+            }
+
+            if let Some(lights) = &self.lights {
+                color += throughput * self.sample_light(lights, &ray, &rec, &srec);
+            }
+            if let Some(environment) = &self.environment {
+                color += throughput * self.sample_environment(environment, &ray, &rec, &srec);
+            }
+            if !self.delta_lights.is_empty() {
+                color += throughput * self.sample_delta_lights(&ray, &rec, &srec);
+            }
+
+            let scattered = Ray { origin: rec.p, direction: srec.pdf_ptr.generate(), tm: ray.time(), wavelength: ray.wavelength() };
+            let pdf_val = srec.pdf_ptr.value(&scattered.direction());
+            if pdf_val <= 0.0 {
+                break;
+            }
+            throughput = throughput * rec.mat_ptr.scattering_color(&ray, &rec, &srec, &scattered) / pdf_val;
+            ray = scattered;
+            scatter_pdf = Some(pdf_val);
+        }
+
+        color
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aarect::XZRect;
+    use crate::hittable_list::HittableList;
+    use crate::material::DiffuseLight;
+    use crate::material::Lambertian;
+    use crate::rtweekend::seed_random;
+    use crate::sphere::Sphere;
+
+    #[test]
+    fn the_power_heuristic_weights_add_up_to_one() {
+        for (f, g) in [(1.0, 1.0), (0.3, 2.0), (5.0, 0.0), (1.0e-3, 40.0)] {
+            assert!((power_heuristic(f, g) + power_heuristic(g, f) - 1.0).abs() < 1.0e-12);
+        }
+        assert_eq!(power_heuristic(0.5, 0.5), 0.5);
+        assert!((power_heuristic(1.0, 2.0) - 0.2).abs() < 1.0e-12);
+        assert_eq!(power_heuristic(0.0, 0.0), 0.0);
+    }
+
+    // A grey floor under a sphere of radiance 1, of radius 1 and 3 above the
+    // point the camera ray hits. The sphere fills a cone of half angle a,
+    // sin a = 1/3, so the floor there reflects albedo * sin^2 a.
+    fn floor_under_a_lamp() -> (Arc<dyn Hittable>, Arc<dyn Hittable>) {
+        let lamp: Arc<dyn Hittable> = Arc::new(Sphere {
+            center: Point3(0.0, 3.0, 0.0),
+            radius: 1.0,
+            mat_ptr: Arc::new(DiffuseLight::new(Color(1.0, 1.0, 1.0))),
+        });
+        let floor: Arc<dyn Hittable> = Arc::new(XZRect::new(-100.0, 100.0, -100.0, 100.0, 0.0, Arc::new(Lambertian::new(&Color(0.5, 0.5, 0.5)))));
+        (Arc::new(HittableList { objects: vec![floor, lamp.clone()] }), lamp)
+    }
+
+    fn mean_radiance(integrator: &dyn Integrator) -> f64 {
+        seed_random(7);
+        let r = Ray { origin: Point3(2.0, 0.5, 0.0), direction: Vec3(-2.0, -0.5, 0.0), tm: 0.0, wavelength: 0.0 };
+        let n = 40000;
+        let mut sum = 0.0;
+        for _ in 0..n {
+            sum += integrator.ray_color(&r, &Color(0.0, 0.0, 0.0), 5).y();
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn both_path_tracers_find_the_light_of_a_lamp() {
+        let expected = 0.5 / 9.0;
+        let (world, lamp) = floor_under_a_lamp();
+
+        let mis = MisPathIntegrator { world: world.clone(), lights: Some(lamp.clone()), environment: None, delta_lights: Vec::new() };
+        let found = mean_radiance(&mis);
+        assert!((found - expected).abs() < 0.02 * expected, "MIS: {} expected {}", found, expected);
+
+        let mixture = PathIntegrator { world, lights: Some(lamp) };
+        let found = mean_radiance(&mixture);
+        assert!((found - expected).abs() < 0.02 * expected, "mixture: {} expected {}", found, expected);
+    }
+}
@@ -28,14 +28,58 @@ pub mod aarect;
 
 pub mod abox;
 
+pub mod triangle;
+
+pub mod mesh;
+
+pub mod obj;
+
+pub mod ply;
+
+pub mod stl;
+
+pub mod mat4;
+
+pub mod transform;
+
 pub mod constant_medium;
 
+pub mod bvh_build;
+
 pub mod bvh_node;
 
+pub mod linear_bvh;
+pub mod instance;
+pub mod light_sampler;
+
 pub mod onb;
 
 pub mod pdf;
 
+pub mod microfacet;
+
+pub mod principled;
+
+pub mod coated;
+
+pub mod spectrum;
+
+pub mod environment;
+
+pub mod sky;
+
+pub mod delta_light;
+
+pub mod film;
+
+pub mod integrator;
+
+pub mod renderer;
+
+pub mod scene;
+
+pub mod cli;
+
 pub mod material_first_week;
 
 pub mod hittable_first_week;
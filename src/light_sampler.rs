@@ -0,0 +1,312 @@
+// Chooses which light to sample for next event estimation.
+//
+// A HittableList of lights picks every light with the same probability, so
+// in a scene with hundreds of lights most samples go to dim or distant ones.
+// LightSampler keeps the lights in a BVH whose nodes know the total power of
+// the lights below them (see Hittable::emitted_power), and picks a light by
+// walking down the tree, taking each child with a probability proportional
+// to its importance:
+//
+//   Uniform   every light counts the same, like HittableList
+//   Power     the power of the child, so each light is picked in proportion
+//             to its power, as with a CDF over the powers
+//   Tree      the power divided by the squared distance from the shading
+//             point to the child's box, so lights nearby are preferred
+//
+// The probability of a light is the product of the choices on the way down.
+// pdf_value only walks into the boxes hit by the direction, which is enough
+// because a light's own pdf_value is zero for directions that miss it.
+//
+// Lights that give off no power are never picked, unless none gives off
+// any, e.g. copies of the lights with DefaultMaterial. Then all count the same.
+// Objects that give off no light but are worth sampling anyway, like the
+// glass sphere of the book's Cornell box, can be passed as important ones.
+// They count as much as an average light.
+
+use crate::hittable::Hittable;
+use crate::hittable::HitRecord;
+use crate::hittable::collect_lights;
+use crate::ray::Ray;
+
+use crate::aabb::AABB;
+
+use crate::mat4::Mat4;
+
+use crate::bvh_build::build_bvh;
+use crate::bvh_build::BuildNode;
+use crate::bvh_build::BvhError;
+
+use crate::rtweekend::random_double;
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+
+use std::sync::Arc;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum LightStrategy {
+    Uniform,
+    Power,
+    Tree,
+}
+
+struct LightNode {
+    bounds: AABB,
+    power: f64,
+    // Leaves: index of the first light. Interior nodes: index of the second child.
+    offset: usize,
+    // Number of lights in a leaf, 0 for interior nodes
+    count: usize,
+}
+
+pub struct LightSampler {
+    strategy: LightStrategy,
+    nodes: Vec<LightNode>,
+    // In the order of the tree, so each leaf refers to a range
+    lights: Vec<Arc<dyn Hittable>>,
+    boxes: Vec<AABB>,
+    powers: Vec<f64>,
+}
+
+impl LightSampler {
+    // Indices in a BvhError count the important objects after the lights
+    pub fn new(lights: &[Arc<dyn Hittable>], important: &[Arc<dyn Hittable>], strategy: LightStrategy, time0: f64, time1: f64) -> Result<Self, BvhError> {
+        let mut powers: Vec<f64> = lights.iter().map(|light| light.emitted_power().max(0.0)).collect();
+        let total: f64 = powers.iter().sum();
+        let average = if total > 0.0 { total / lights.len() as f64 } else { 1.0 };
+        powers.extend(important.iter().map(|_| average));
+        if strategy == LightStrategy::Uniform || total <= 0.0 {
+            powers = vec![1.0; powers.len()];
+        }
+
+        let lights: Vec<Arc<dyn Hittable>> = lights.iter().chain(important).cloned().collect();
+        let mut boxes: Vec<AABB> = Vec::with_capacity(lights.len());
+        for (i, light) in lights.iter().enumerate() {
+            let mut output_box = AABB {
+                minimum: Point3(0.0, 0.0, 0.0),
+                maximum: Point3(0.0, 0.0, 0.0)
+            };
+            if !light.bounding_box(time0, time1, &mut output_box) {
+                return Err(BvhError::NoBoundingBox { index: i });
+            }
+            boxes.push(output_box);
+        }
+
+        let build = build_bvh(&boxes)?;
+        let mut sampler = LightSampler {
+            strategy,
+            nodes: Vec::with_capacity(build.node_count),
+            lights: build.order.iter().map(|&i| lights[i].clone()).collect(),
+            boxes: build.order.iter().map(|&i| boxes[i]).collect(),
+            powers: build.order.iter().map(|&i| powers[i]).collect(),
+        };
+        sampler.flatten(&build.root);
+        Ok(sampler)
+    }
+
+    // Samples every light found in `world` by collect_lights, and the
+    // important objects. None when there is nothing to sample.
+    pub fn from_world(world: &Arc<dyn Hittable>, important: &[Arc<dyn Hittable>], strategy: LightStrategy, time0: f64, time1: f64) -> Result<Option<Self>, BvhError> {
+        let lights = collect_lights(world);
+        if lights.is_empty() && important.is_empty() {
+            return Ok(None);
+        }
+        LightSampler::new(&lights, important, strategy, time0, time1).map(Some)
+    }
+
+    pub fn strategy(&self) -> LightStrategy {
+        self.strategy
+    }
+
+    // Appends the nodes of the subtree and returns the power below it
+    fn flatten(&mut self, node: &BuildNode) -> f64 {
+        let index = self.nodes.len();
+        match node {
+            BuildNode::Leaf { bounds, first, count } => {
+                let power = self.powers[*first..*first + *count].iter().sum();
+                self.nodes.push(LightNode { bounds: *bounds, power, offset: *first, count: *count });
+                power
+            },
+            BuildNode::Interior { bounds, left, right, .. } => {
+                self.nodes.push(LightNode { bounds: *bounds, power: 0.0, offset: 0, count: 0 });
+                let left_power = self.flatten(left);
+                self.nodes[index].offset = self.nodes.len();
+                let right_power = self.flatten(right);
+                self.nodes[index].power = left_power + right_power;
+                left_power + right_power
+            },
+        }
+    }
+
+    // How much the lights in `bounds` with total `power` are expected to
+    // light the point `o`, up to a common factor
+    fn importance(&self, o: &Point3, bounds: &AABB, power: f64) -> f64 {
+        match self.strategy {
+            LightStrategy::Uniform | LightStrategy::Power => power,
+            LightStrategy::Tree => {
+                let center = 0.5*(bounds.min() + bounds.max());
+                let half_diagonal = 0.5*(bounds.max() - bounds.min());
+                // Inside or close to the box every point of it may be next
+                // to `o`, so the distance is not allowed below its size
+                let distance_squared = (center - *o).length_square().max(half_diagonal.length_square());
+                if distance_squared == 0.0 { power } else { power / distance_squared }
+            },
+        }
+    }
+
+    // Importance of the two children of an interior node
+    fn child_importances(&self, o: &Point3, index: usize) -> (usize, f64, usize, f64) {
+        let left = index + 1;
+        let right = self.nodes[index].offset;
+        let left_importance = self.importance(o, &self.nodes[left].bounds, self.nodes[left].power);
+        let right_importance = self.importance(o, &self.nodes[right].bounds, self.nodes[right].power);
+        (left, left_importance, right, right_importance)
+    }
+
+    // Sum of the light pdfs below node `index`, each times the probability of
+    // picking that light. `probability` is the one of reaching the node.
+    fn pdf_below(&self, index: usize, probability: f64, o: &Point3, v: &Vec3, r: &Ray) -> f64 {
+        let node = &self.nodes[index];
+        if !node.bounds.hit(r, 0.001, f64::INFINITY) {
+            return 0.0;
+        }
+
+        if node.count > 0 {
+            let range = node.offset..node.offset + node.count;
+            let importances: Vec<f64> = range.clone().map(|i| self.importance(o, &self.boxes[i], self.powers[i])).collect();
+            let total: f64 = importances.iter().sum();
+            if total <= 0.0 {
+                return 0.0;
+            }
+            let mut sum = 0.0;
+            for (i, importance) in range.zip(importances) {
+                if importance > 0.0 {
+                    sum += probability * importance / total * self.lights[i].pdf_value(o, v);
+                }
+            }
+            return sum;
+        }
+
+        let (left, left_importance, right, right_importance) = self.child_importances(o, index);
+        let total = left_importance + right_importance;
+        if total <= 0.0 {
+            return 0.0;
+        }
+        let mut sum = 0.0;
+        if left_importance > 0.0 {
+            sum += self.pdf_below(left, probability * left_importance / total, o, v, r);
+        }
+        if right_importance > 0.0 {
+            sum += self.pdf_below(right, probability * right_importance / total, o, v, r);
+        }
+        sum
+    }
+}
+
+// Only for sampling directions. It is never put into the world, so it
+// cannot be hit.
+impl Hittable for LightSampler {
+    fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64, _rec: &mut HitRecord) -> bool {
+        false
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = self.nodes[0].bounds;
+        true
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let r = Ray { origin: *o, direction: *v, tm: 0.0, wavelength: 0.0 };
+        self.pdf_below(0, 1.0, o, v, &r)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            if node.count > 0 {
+                let range = node.offset..node.offset + node.count;
+                let importances: Vec<f64> = range.clone().map(|i| self.importance(o, &self.boxes[i], self.powers[i])).collect();
+                let mut target = random_double() * importances.iter().sum::<f64>();
+                // Falls back on the last light with any importance, in case
+                // rounding leaves the target past the end
+                let mut chosen = node.offset;
+                for (i, importance) in range.zip(importances) {
+                    if importance <= 0.0 {
+                        continue;
+                    }
+                    chosen = i;
+                    if target < importance {
+                        break;
+                    }
+                    target -= importance;
+                }
+                return self.lights[chosen].random(o);
+            }
+
+            let (left, left_importance, right, right_importance) = self.child_importances(o, index);
+            index = if random_double() * (left_importance + right_importance) < left_importance { left } else { right };
+        }
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.lights.iter().map(|light| light.transformed_emitted_power(matrix)).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::aarect::XZRect;
+    use crate::material::DiffuseLight;
+    use crate::material::Material;
+    use crate::pdf::checks::histogram_distance;
+    use crate::pdf::checks::sphere_integral;
+    use crate::rtweekend::seed_random;
+    use crate::sphere::Sphere;
+    use crate::vec3::Color;
+
+    fn lamp(radiance: f64) -> Arc<dyn Material> {
+        Arc::new(DiffuseLight::new(Color(radiance, radiance, radiance)))
+    }
+
+    // Lights of different sizes and powers all around the origin
+    fn lights() -> Vec<Arc<dyn Hittable>> {
+        vec![
+            Arc::new(Sphere { center: Point3(0.0, 5.0, 0.0), radius: 1.0, mat_ptr: lamp(4.0) }),
+            Arc::new(Sphere { center: Point3(6.0, 1.0, -3.0), radius: 0.5, mat_ptr: lamp(1.0) }),
+            Arc::new(Sphere { center: Point3(-1.0, -2.0, 2.0), radius: 0.7, mat_ptr: lamp(0.5) }),
+            Arc::new(XZRect::new(-2.0, 2.0, -2.0, 2.0, 8.0, lamp(2.0))),
+            Arc::new(XZRect::new(1.0, 4.0, -3.0, 1.0, -6.0, lamp(1.0))),
+        ]
+    }
+
+    #[test]
+    fn the_density_integrates_to_one_and_matches_the_samples() {
+        let o = Point3(0.0, 0.0, 0.0);
+        for strategy in [LightStrategy::Uniform, LightStrategy::Power, LightStrategy::Tree] {
+            let sampler = LightSampler::new(&lights(), &[], strategy, 0.0, 1.0).unwrap();
+
+            let total = sphere_integral(|v| sampler.pdf_value(&o, v), 400);
+            assert!((total - 1.0).abs() < 0.01, "{:?}: integral {}", strategy, total);
+
+            seed_random(1);
+            let distance = histogram_distance(|| sampler.random(&o), |v| sampler.pdf_value(&o, v), 200000);
+            assert!(distance < 0.02, "{:?}: distance {}", strategy, distance);
+        }
+    }
+
+    #[test]
+    fn lights_are_picked_in_proportion_to_their_power() {
+        let o = Point3(0.0, 0.0, 0.0);
+        let lights = lights();
+        let uniform = LightSampler::new(&lights, &[], LightStrategy::Uniform, 0.0, 1.0).unwrap();
+        let power = LightSampler::new(&lights, &[], LightStrategy::Power, 0.0, 1.0).unwrap();
+        // Towards the second sphere, which no other light is in front of
+        let towards = Vec3(6.0, 1.0, -3.0);
+        let ratio = power.pdf_value(&o, &towards) / uniform.pdf_value(&o, &towards);
+        let total_power: f64 = lights.iter().map(|light| light.emitted_power()).sum();
+        let expected = lights[1].emitted_power() / total_power * lights.len() as f64;
+        assert!((ratio - expected).abs() < 1.0e-9 * expected, "ratio {} expected {}", ratio, expected);
+    }
+}
@@ -0,0 +1,215 @@
+// A BVH stored as one array of nodes, in depth first order.
+//
+// The first child of an interior node is the node right after it, and the
+// node keeps the index of its second child. Traversal walks the array with an
+// explicit stack instead of recursing, and visits the child on the near side
+// of the split axis first, so the far one can often be skipped once something
+// closer has been hit. The tree comes from the SAH builder in bvh_build.rs.
+
+use crate::hittable::Hittable;
+use crate::hittable::HitRecord;
+use crate::ray::Ray;
+
+use crate::aabb::AABB;
+
+use crate::mat4::Mat4;
+
+use crate::bvh_build::build_bvh;
+use crate::bvh_build::BuildNode;
+use crate::bvh_build::BvhError;
+
+use crate::rtweekend::random_double_range;
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+
+use std::sync::Arc;
+
+// Stack entries kept on the stack of the thread. Deeper trees use a heap
+// allocated stack instead.
+const STACK_SIZE: usize = 64;
+
+struct LinearNode {
+    bounds: AABB,
+    // Leaves: index of the first object. Interior nodes: index of the second child.
+    offset: u32,
+    // Number of objects in a leaf, 0 for interior nodes
+    count: u16,
+    axis: u8,
+}
+
+pub struct LinearBvh {
+    tree: FlatBvh,
+    // In the order of the tree, so each leaf refers to a range
+    objects: Vec<Arc<dyn Hittable>>,
+}
+
+impl Hittable for LinearBvh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        self.tree.traverse(r, t_min, t_max, |first, count, closest_so_far| {
+            let mut hit_anything = false;
+            let mut closest_so_far = closest_so_far;
+            for object in &self.objects[first..first + count] {
+                if object.hit(r, t_min, closest_so_far, rec) {
+                    hit_anything = true;
+                    closest_so_far = rec.t;
+                }
+            }
+            if hit_anything { Some(closest_so_far) } else { None }
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = self.tree.bounds();
+        true
+    }
+
+    // Like HittableList, every object is picked with the same probability
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let weight = 1.0 / self.objects.len() as f64;
+        let mut sum = 0.0;
+
+        for object in &self.objects {
+            sum += weight * object.pdf_value(o, v);
+        }
+        sum
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        let int_size = self.objects.len();
+        self.objects[(random_double_range(0.0, int_size as f64) as usize).min(int_size-1)].random(o)
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.objects.iter().map(|object| object.transformed_emitted_power(matrix)).sum()
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.objects.iter().map(|object| object.area(matrix)).sum()
+    }
+
+    fn collect_lights(&self, _this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        for object in &self.objects {
+            object.collect_lights(object, lights);
+        }
+    }
+}
+
+impl LinearBvh {
+    pub fn new(objects: &[Arc<dyn Hittable>], time0: f64, time1: f64) -> Result<Self, BvhError> {
+        let mut boxes: Vec<AABB> = Vec::with_capacity(objects.len());
+        for (i, object) in objects.iter().enumerate() {
+            let mut output_box = AABB {
+                minimum: Point3(0.0, 0.0, 0.0),
+                maximum: Point3(0.0, 0.0, 0.0)
+            };
+            if !object.bounding_box(time0, time1, &mut output_box) {
+                return Err(BvhError::NoBoundingBox { index: i });
+            }
+            boxes.push(output_box);
+        }
+
+        let (tree, order) = FlatBvh::new(&boxes)?;
+        Ok(LinearBvh {
+            tree,
+            objects: order.iter().map(|&i| objects[i].clone()).collect(),
+        })
+    }
+}
+
+// The node array and traversal, apart from what the leaves hold
+pub(crate) struct FlatBvh {
+    nodes: Vec<LinearNode>,
+    depth: usize,
+}
+
+impl FlatBvh {
+    // Builds the tree over the boxes. Also returns the index of the box at
+    // each position of the tree order, which the leaf ranges refer to.
+    pub(crate) fn new(boxes: &[AABB]) -> Result<(FlatBvh, Vec<usize>), BvhError> {
+        let build = build_bvh(boxes)?;
+        let mut nodes: Vec<LinearNode> = Vec::with_capacity(build.node_count);
+        let depth = flatten(&build.root, &mut nodes);
+        Ok((FlatBvh { nodes, depth }, build.order))
+    }
+
+    pub(crate) fn bounds(&self) -> AABB {
+        self.nodes[0].bounds
+    }
+
+    // Walks the leaves the ray may hit. `hit_leaf` gets the range of a leaf
+    // and the closest hit so far, and returns the new closest hit if there is
+    // one. Returns whether anything was hit.
+    pub(crate) fn traverse<F>(&self, r: &Ray, t_min: f64, t_max: f64, hit_leaf: F) -> bool
+    where F: FnMut(usize, usize, f64) -> Option<f64> {
+        if self.depth <= STACK_SIZE {
+            let mut stack = [0u32; STACK_SIZE];
+            self.traverse_with(r, t_min, t_max, hit_leaf, &mut stack)
+        } else {
+            let mut stack = vec![0u32; self.depth];
+            self.traverse_with(r, t_min, t_max, hit_leaf, &mut stack)
+        }
+    }
+
+    fn traverse_with<F>(&self, r: &Ray, t_min: f64, t_max: f64, mut hit_leaf: F, stack: &mut [u32]) -> bool
+    where F: FnMut(usize, usize, f64) -> Option<f64> {
+        let origin = r.origin();
+        let direction = r.direction();
+        let inv_direction = Vec3(1.0 / direction.x(), 1.0 / direction.y(), 1.0 / direction.z());
+        let direction_is_negative = [inv_direction.x() < 0.0, inv_direction.y() < 0.0, inv_direction.z() < 0.0];
+
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+        let mut stack_size = 0;
+        let mut current: usize = 0;
+
+        loop {
+            let node = &self.nodes[current];
+            if node.bounds.hit_with_inverse(&origin, &inv_direction, t_min, closest_so_far) {
+                if node.count > 0 {
+                    if let Some(t) = hit_leaf(node.offset as usize, node.count as usize, closest_so_far) {
+                        hit_anything = true;
+                        closest_so_far = t;
+                    }
+                } else {
+                    // Visit the near child now and the far one later
+                    let (near, far) = if direction_is_negative[node.axis as usize] {
+                        (node.offset as usize, current + 1)
+                    } else {
+                        (current + 1, node.offset as usize)
+                    };
+                    stack[stack_size] = far as u32;
+                    stack_size += 1;
+                    current = near;
+                    continue;
+                }
+            }
+
+            if stack_size == 0 {
+                break;
+            }
+            stack_size -= 1;
+            current = stack[stack_size] as usize;
+        }
+
+        hit_anything
+    }
+}
+
+// Appends the nodes of the subtree to `nodes` and returns its depth, which
+// bounds the number of nodes waiting on the stack during traversal.
+fn flatten(node: &BuildNode, nodes: &mut Vec<LinearNode>) -> usize {
+    let index = nodes.len();
+    match node {
+        BuildNode::Leaf { bounds, first, count } => {
+            nodes.push(LinearNode { bounds: *bounds, offset: *first as u32, count: *count as u16, axis: 0 });
+            1
+        },
+        BuildNode::Interior { bounds, axis, left, right } => {
+            nodes.push(LinearNode { bounds: *bounds, offset: 0, count: 0, axis: *axis as u8 });
+            let left_depth = flatten(left, nodes);
+            nodes[index].offset = nodes.len() as u32;
+            let right_depth = flatten(right, nodes);
+            1 + left_depth.max(right_depth)
+        },
+    }
+}
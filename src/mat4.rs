@@ -0,0 +1,314 @@
+// 4x4 matrices for affine transforms of points, directions and normals.
+//
+// Points are column vectors, so `a * b` applies b first and then a. The
+// constructors take angles in degrees, like RotateY.
+
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+
+use std::ops::Mul;
+
+#[derive(Copy, Clone, Debug)]
+pub struct Mat4 {
+    // Rows of the matrix
+    pub m: [[f64; 4]; 4],
+}
+
+impl Mat4 {
+    pub fn identity() -> Self {
+        Mat4 {
+            m: [[1.0, 0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0, 0.0],
+                [0.0, 0.0, 1.0, 0.0],
+                [0.0, 0.0, 0.0, 1.0]],
+        }
+    }
+
+    pub fn translate(offset: Vec3) -> Self {
+        let mut t = Mat4::identity();
+        t.m[0][3] = offset.x();
+        t.m[1][3] = offset.y();
+        t.m[2][3] = offset.z();
+        t
+    }
+
+    pub fn scale(factors: Vec3) -> Self {
+        let mut t = Mat4::identity();
+        t.m[0][0] = factors.x();
+        t.m[1][1] = factors.y();
+        t.m[2][2] = factors.z();
+        t
+    }
+
+    pub fn rotate_x(angle: f64) -> Self {
+        let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+        let mut t = Mat4::identity();
+        t.m[1][1] = cos_theta;
+        t.m[1][2] = -sin_theta;
+        t.m[2][1] = sin_theta;
+        t.m[2][2] = cos_theta;
+        t
+    }
+
+    // The same rotation as RotateY
+    pub fn rotate_y(angle: f64) -> Self {
+        let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+        let mut t = Mat4::identity();
+        t.m[0][0] = cos_theta;
+        t.m[0][2] = sin_theta;
+        t.m[2][0] = -sin_theta;
+        t.m[2][2] = cos_theta;
+        t
+    }
+
+    pub fn rotate_z(angle: f64) -> Self {
+        let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+        let mut t = Mat4::identity();
+        t.m[0][0] = cos_theta;
+        t.m[0][1] = -sin_theta;
+        t.m[1][0] = sin_theta;
+        t.m[1][1] = cos_theta;
+        t
+    }
+
+    // Rotation about `axis` through the origin, counterclockwise when the
+    // axis points at the viewer (Rodrigues' formula)
+    pub fn rotate_axis(axis: Vec3, angle: f64) -> Self {
+        let a = Vec3::unit_vector(axis);
+        let (sin_theta, cos_theta) = angle.to_radians().sin_cos();
+        let c = 1.0 - cos_theta;
+        let mut t = Mat4::identity();
+
+        t.m[0][0] = a.x()*a.x()*c + cos_theta;
+        t.m[0][1] = a.x()*a.y()*c - a.z()*sin_theta;
+        t.m[0][2] = a.x()*a.z()*c + a.y()*sin_theta;
+
+        t.m[1][0] = a.y()*a.x()*c + a.z()*sin_theta;
+        t.m[1][1] = a.y()*a.y()*c + cos_theta;
+        t.m[1][2] = a.y()*a.z()*c - a.x()*sin_theta;
+
+        t.m[2][0] = a.z()*a.x()*c - a.y()*sin_theta;
+        t.m[2][1] = a.z()*a.y()*c + a.x()*sin_theta;
+        t.m[2][2] = a.z()*a.z()*c + cos_theta;
+        t
+    }
+
+    // Places an object modelled looking down -z with +y up at `eye`, looking
+    // at `target`. This is the inverse of a camera's view matrix.
+    pub fn look_at(eye: Point3, target: Point3, up: Vec3) -> Self {
+        let w = Vec3::unit_vector(eye - target);
+        let u = Vec3::unit_vector(Vec3::cross(up, w));
+        let v = Vec3::cross(w, u);
+
+        Mat4 {
+            m: [[u.x(), v.x(), w.x(), eye.x()],
+                [u.y(), v.y(), w.y(), eye.y()],
+                [u.z(), v.z(), w.z(), eye.z()],
+                [0.0, 0.0, 0.0, 1.0]],
+        }
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        transform_point(&self.m, p)
+    }
+
+    // Directions are not moved by the translation
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        transform_vector(&self.m, v)
+    }
+
+    // Multiplies by the transpose. Called on the inverse of a transform, this
+    // maps normals so they stay perpendicular to the transformed surface.
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        transform_normal(&self.m, n)
+    }
+
+    // Area of the parallelogram with sides a and b once moved by the upper
+    // 3x3 part. Areas of flat shapes are found from their sides this way, so
+    // they are exact for any scale or shear.
+    pub fn transformed_area(&self, a: &Vec3, b: &Vec3) -> f64 {
+        Vec3::cross(self.transform_vector(a), self.transform_vector(b)).length()
+    }
+
+    // Determinant of the upper 3x3 part, the factor by which volumes change
+    pub fn determinant3(&self) -> f64 {
+        determinant3(&self.m)
+    }
+
+    // Inverse by Gauss-Jordan elimination with partial pivoting. None when
+    // the matrix is singular, e.g. a scale by zero.
+    pub fn inverse(&self) -> Option<Mat4> {
+        let mut a = self.m;
+        let mut inv = Mat4::identity().m;
+
+        for col in 0..4 {
+            let mut pivot = col;
+            for row in col + 1..4 {
+                if a[row][col].abs() > a[pivot][col].abs() {
+                    pivot = row;
+                }
+            }
+            // NaN entries fail here too
+            if a[pivot][col].is_nan() || a[pivot][col].abs() <= 1e-12 {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+
+            let scale = 1.0 / a[col][col];
+            for k in 0..4 {
+                a[col][k] *= scale;
+                inv[col][k] *= scale;
+            }
+            for row in 0..4 {
+                if row == col {
+                    continue;
+                }
+                let factor = a[row][col];
+                for k in 0..4 {
+                    a[row][k] -= factor * a[col][k];
+                    inv[row][k] -= factor * inv[col][k];
+                }
+            }
+        }
+
+        Some(Mat4 { m: inv })
+    }
+}
+
+// The top three rows of a matrix whose last row is 0 0 0 1, as it is for
+// every transform made here. A quarter smaller than a Mat4, for when many of
+// them are kept.
+#[derive(Copy, Clone, Debug)]
+pub struct Affine {
+    pub m: [[f64; 4]; 3],
+}
+
+impl Affine {
+    // The last row of `matrix` is taken to be 0 0 0 1
+    pub fn from_mat4(matrix: &Mat4) -> Self {
+        Affine {
+            m: [matrix.m[0], matrix.m[1], matrix.m[2]],
+        }
+    }
+
+    pub fn to_mat4(&self) -> Mat4 {
+        Mat4 {
+            m: [self.m[0], self.m[1], self.m[2], [0.0, 0.0, 0.0, 1.0]],
+        }
+    }
+
+    pub fn transform_point(&self, p: &Point3) -> Point3 {
+        transform_point(&self.m, p)
+    }
+
+    pub fn transform_vector(&self, v: &Vec3) -> Vec3 {
+        transform_vector(&self.m, v)
+    }
+
+    // See Mat4::transform_normal
+    pub fn transform_normal(&self, n: &Vec3) -> Vec3 {
+        transform_normal(&self.m, n)
+    }
+
+    pub fn determinant3(&self) -> f64 {
+        determinant3(&self.m)
+    }
+}
+
+// The products below only read the top three rows, so Mat4 and Affine share
+// them
+
+fn transform_point(m: &[[f64; 4]], p: &Point3) -> Point3 {
+    Point3(
+        m[0][0]*p.x() + m[0][1]*p.y() + m[0][2]*p.z() + m[0][3],
+        m[1][0]*p.x() + m[1][1]*p.y() + m[1][2]*p.z() + m[1][3],
+        m[2][0]*p.x() + m[2][1]*p.y() + m[2][2]*p.z() + m[2][3],
+    )
+}
+
+fn transform_vector(m: &[[f64; 4]], v: &Vec3) -> Vec3 {
+    Vec3(
+        m[0][0]*v.x() + m[0][1]*v.y() + m[0][2]*v.z(),
+        m[1][0]*v.x() + m[1][1]*v.y() + m[1][2]*v.z(),
+        m[2][0]*v.x() + m[2][1]*v.y() + m[2][2]*v.z(),
+    )
+}
+
+fn transform_normal(m: &[[f64; 4]], n: &Vec3) -> Vec3 {
+    Vec3(
+        m[0][0]*n.x() + m[1][0]*n.y() + m[2][0]*n.z(),
+        m[0][1]*n.x() + m[1][1]*n.y() + m[2][1]*n.z(),
+        m[0][2]*n.x() + m[1][2]*n.y() + m[2][2]*n.z(),
+    )
+}
+
+fn determinant3(m: &[[f64; 4]]) -> f64 {
+    m[0][0]*(m[1][1]*m[2][2] - m[1][2]*m[2][1])
+        - m[0][1]*(m[1][0]*m[2][2] - m[1][2]*m[2][0])
+        + m[0][2]*(m[1][0]*m[2][1] - m[1][1]*m[2][0])
+}
+
+impl Mul for Mat4 {
+    type Output = Mat4;
+
+    #[allow(clippy::needless_range_loop)]
+    fn mul(self, other: Mat4) -> Mat4 {
+        let mut product = [[0.0; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                for k in 0..4 {
+                    product[i][j] += self.m[i][k] * other.m[k][j];
+                }
+            }
+        }
+        Mat4 { m: product }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A rotation, a shear, an uneven scale and a move
+    fn skewed() -> Mat4 {
+        let mut shear = Mat4::identity();
+        shear.m[0][1] = 0.7;
+        shear.m[2][0] = -0.3;
+        Mat4::translate(Vec3(1.0, -2.0, 3.0)) * Mat4::rotate_axis(Vec3(1.0, 2.0, 0.5), 35.0) * shear * Mat4::scale(Vec3(2.0, 0.5, 3.0))
+    }
+
+    #[test]
+    fn the_inverse_undoes_the_matrix() {
+        let m = skewed();
+        let inverse = m.inverse().unwrap();
+        for product in [inverse * m, m * inverse] {
+            for i in 0..4 {
+                for j in 0..4 {
+                    let expected = if i == j { 1.0 } else { 0.0 };
+                    assert!((product.m[i][j] - expected).abs() < 1.0e-12, "{:?}", product);
+                }
+            }
+        }
+        assert!((m.determinant3() * inverse.determinant3() - 1.0).abs() < 1.0e-12);
+        assert!(Mat4::scale(Vec3(1.0, 0.0, 1.0)).inverse().is_none());
+    }
+
+    #[test]
+    fn normals_stay_perpendicular_to_the_surface() {
+        let m = skewed();
+        let inverse = m.inverse().unwrap();
+        // Two directions in a surface and its normal
+        let a = Vec3(1.0, 0.2, -0.4);
+        let b = Vec3(-0.3, 1.0, 0.6);
+        let n = Vec3::cross(a, b);
+
+        let moved_n = inverse.transform_normal(&n);
+        assert!(Vec3::dot(moved_n, m.transform_vector(&a)).abs() < 1.0e-12);
+        assert!(Vec3::dot(moved_n, m.transform_vector(&b)).abs() < 1.0e-12);
+        // Moving the normal like a direction would not keep it perpendicular
+        assert!(Vec3::dot(m.transform_vector(&n), m.transform_vector(&a)).abs() > 0.1);
+        // The side of the surface is kept
+        assert!(Vec3::dot(moved_n, Vec3::cross(m.transform_vector(&a), m.transform_vector(&b))) > 0.0);
+    }
+}
@@ -5,6 +5,8 @@ use crate::vec3::Point3;
 use crate::vec3::random_in_unit_sphere;
 use crate::vec3::refract;
 
+use crate::color::luminance;
+
 use crate::pdf::Pdf;
 
 use crate::ray::Ray;
@@ -17,52 +19,92 @@ use crate::rtweekend::random_double;
 
 use crate::pdf::SpherePdf;
 
+use crate::microfacet::Ggx;
+use crate::microfacet::ShadingFrame;
+use crate::microfacet::MicrofacetReflectionPdf;
+use crate::microfacet::fresnel_conductor;
+use crate::microfacet::fresnel_dielectric;
+use crate::microfacet::MicrofacetDielectricPdf;
+use crate::microfacet::reflection;
+use crate::microfacet::dielectric_scattering;
+use crate::spectrum::Dispersion;
+use crate::spectrum::WavelengthSampler;
+
 use std::f64::consts::PI;
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
+use std::sync::OnceLock;
 
 pub struct ScatterRecord {
     pub attenuation: Color,
-    pub pdf_ptr: Rc<RefCell<dyn Pdf>>,
+    pub pdf_ptr: Arc<dyn Pdf>,
     pub skip_pdf: bool,
     pub skip_pdf_ray: Ray,
 }
 
-pub trait Material {
+pub trait Material: Send + Sync {
     fn scatter(&self, _r_in: &Ray, _rec: &HitRecord, _srec: &mut ScatterRecord) -> bool {
         false
     }
 
-    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &mut Ray) -> f64 {
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         0.0
     }
 
+    // Fraction of the light arriving along `scattered` that leaves towards
+    // the origin of `r_in`, times the cosine of `scattered` with the normal:
+    // what the light found by a ray is multiplied by before dividing by the
+    // density it was drawn with. For the materials of the book that is the
+    // attenuation times scattering_pdf, since they scatter exactly like
+    // their pdf. Materials sampled with something else override it.
+    fn scattering_color(&self, r_in: &Ray, rec: &HitRecord, srec: &ScatterRecord, scattered: &Ray) -> Color {
+        srec.attenuation * self.scattering_pdf(r_in, rec, scattered)
+    }
+
+    // Albedo of the part of the material that scatters light evenly in all
+    // directions, like a Lambertian surface. Coated uses it to add up the
+    // light bouncing between a base and its coat. Black for materials that
+    // scatter only into a few directions.
+    fn diffuse_albedo(&self, _rec: &HitRecord) -> Color {
+        Color(0.0, 0.0, 0.0)
+    }
+
     fn emitted(&self, _r_in: &Ray, _rec: &HitRecord, _u: f64, _v: f64, _p: &Point3) -> Color {
         Color(0.0, 0.0, 0.0)
     }
+
+    // Rough luminance of the radiance given off, averaged over the surface
+    // and summed over the sides that give it off. Light sampling uses it to
+    // tell bright lights from dim ones.
+    fn average_emission(&self) -> f64 {
+        0.0
+    }
 }
 
 pub struct Lambertian {
-    pub albedo: Rc<RefCell<dyn Texture>>,
+    pub albedo: Arc<dyn Texture>,
 }
 
 impl Material for Lambertian {
     fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
-        srec.attenuation = self.albedo.borrow().value(rec.u, rec.v, &rec.p);
-        srec.pdf_ptr = Rc::new(RefCell::new(CosinePdf::new(&rec.normal)));
+        srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        srec.pdf_ptr = Arc::new(CosinePdf::new(&rec.normal));
         srec.skip_pdf = false;
         true
     }
 
-    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &mut Ray) -> f64 {
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
         let cos_theta = Vec3::dot(_rec.normal, Vec3::unit_vector(_scattered.direction()));
         if cos_theta < 0.0 { 0.0 } else { cos_theta/PI }
     }
+
+    fn diffuse_albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.p)
+    }
 }
 
 impl Lambertian {
     pub fn new(a: &Color) -> Self {
-        Lambertian {albedo: Rc::new(RefCell::new(SolidColor::new(a.0, a.1, a.2)))}
+        Lambertian {albedo: Arc::new(SolidColor::new(a.0, a.1, a.2))}
     }
 }
 
@@ -86,10 +128,10 @@ pub struct Metal {
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
         srec.attenuation = self.albedo;
-        // srec.pdf_ptr = std::ptr::null::<Rc<RefCell<dyn Pdf>>>();
+        // srec.pdf_ptr = std::ptr::null::<Arc<dyn Pdf>>();
         srec.skip_pdf = true;
         let reflected: Vec3 = reflect(&Vec3::unit_vector(r_in.direction()), &rec.normal);
-        srec.skip_pdf_ray = Ray { origin: rec.p, direction: reflected + self.fuzz*random_in_unit_sphere(), tm: r_in.time()};
+        srec.skip_pdf_ray = Ray { origin: rec.p, direction: reflected + self.fuzz*random_in_unit_sphere(), tm: r_in.time(), wavelength: r_in.wavelength()};
         true
     }
 }
@@ -98,6 +140,96 @@ pub fn reflect(v: &Vec3, n: &Vec3) -> Vec3 {
     *v - 2.0*Vec3::dot(*v, *n)**n
 }
 
+// A metal with a rough surface of GGX microfacets, reflecting by the
+// Fresnel equations for its complex index of refraction eta + i k, given for
+// red, green and blue. Unlike Metal it takes part in light sampling, since
+// scattering_pdf gives the density of the directions it draws.
+//
+// The roughness goes from 0, a mirror, to 1, and may differ along `tangent`
+// (roughness_u) and across it (roughness_v) for brushed metals. The tangent
+// is made perpendicular to the normal wherever the surface is hit.
+pub struct Conductor {
+    pub eta: Color,
+    pub k: Color,
+    distribution: Ggx,
+    tangent: Vec3,
+}
+
+impl Material for Conductor {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let unit_direction = Vec3::unit_vector(r_in.direction());
+        if self.distribution.is_smooth() {
+            let cos_theta = Vec3::dot(-unit_direction, rec.normal);
+            srec.attenuation = fresnel_conductor(cos_theta, &self.eta, &self.k);
+            srec.skip_pdf = true;
+            srec.skip_pdf_ray = Ray { origin: rec.p, direction: reflect(&unit_direction, &rec.normal), tm: r_in.time(), wavelength: r_in.wavelength()};
+            return true;
+        }
+
+        let frame = ShadingFrame::new(&rec.normal, &self.tangent);
+        let wo = frame.to_local(&-unit_direction);
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        srec.attenuation = Color(1.0, 1.0, 1.0);
+        srec.pdf_ptr = Arc::new(MicrofacetReflectionPdf {
+            frame,
+            wo,
+            distribution: self.distribution,
+        });
+        srec.skip_pdf = false;
+        true
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = ShadingFrame::new(&rec.normal, &self.tangent);
+        let wo = frame.to_local(&-Vec3::unit_vector(r_in.direction()));
+        let wi = frame.to_local(&Vec3::unit_vector(scattered.direction()));
+        MicrofacetReflectionPdf::local_value(&self.distribution, &wo, &wi)
+    }
+
+    // F D G / (4 cos(wo) cos(wi)), times cos(wi)
+    fn scattering_color(&self, r_in: &Ray, rec: &HitRecord, _srec: &ScatterRecord, scattered: &Ray) -> Color {
+        let frame = ShadingFrame::new(&rec.normal, &self.tangent);
+        let wo = frame.to_local(&-Vec3::unit_vector(r_in.direction()));
+        let wi = frame.to_local(&Vec3::unit_vector(scattered.direction()));
+        match reflection(&self.distribution, &wo, &wi) {
+            Some((m, value)) => fresnel_conductor(Vec3::dot(wo, m), &self.eta, &self.k) * value,
+            None => Color(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+impl Conductor {
+    pub fn new(eta: Color, k: Color, roughness: f64) -> Self {
+        Conductor::anisotropic(eta, k, roughness, roughness, Vec3(1.0, 0.0, 0.0))
+    }
+
+    pub fn anisotropic(eta: Color, k: Color, roughness_u: f64, roughness_v: f64, tangent: Vec3) -> Self {
+        Conductor {
+            eta,
+            k,
+            distribution: Ggx::from_roughness(roughness_u, roughness_v),
+            tangent,
+        }
+    }
+
+    // eta and k of a metal by name, for red, green and blue light (650, 550
+    // and 450 nm): "gold", "silver", "copper", "aluminium" (or "aluminum")
+    // or "iron"
+    pub fn preset(name: &str) -> Option<(Color, Color)> {
+        let (eta, k) = match name {
+            "gold" => (Color(0.143, 0.374, 1.442), Color(3.983, 2.385, 1.603)),
+            "silver" => (Color(0.155, 0.117, 0.138), Color(4.828, 3.122, 2.147)),
+            "copper" => (Color(0.200, 0.924, 1.102), Color(3.912, 2.452, 2.142)),
+            "aluminium" | "aluminum" => (Color(1.657, 0.880, 0.521), Color(9.224, 6.270, 4.837)),
+            "iron" => (Color(2.911, 2.950, 2.585), Color(3.089, 2.932, 2.767)),
+            _ => return None,
+        };
+        Some((eta, k))
+    }
+}
+
 pub struct Dialectric {
     pub ir: f64
 }
@@ -114,15 +246,13 @@ impl Material for Dialectric {
         let sin_theta: f64 = (1.0 - cos_theta*cos_theta).sqrt();
 
         let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
-        let direction: Vec3;
-
-        if cannot_refract || Dialectric::reflectance(cos_theta, refraction_ratio) > random_double() {
-            direction = reflect(&unit_direction, &rec.normal);
+        let direction: Vec3 = if cannot_refract || Dialectric::reflectance(cos_theta, refraction_ratio) > random_double() {
+            reflect(&unit_direction, &rec.normal)
         } else {
-            direction = refract(&unit_direction, &rec.normal, refraction_ratio)
-        }
+            refract(&unit_direction, &rec.normal, refraction_ratio)
+        };
 
-        srec.skip_pdf_ray = Ray { origin: rec.p, direction: direction, tm: r_in.time()};
+        srec.skip_pdf_ray = Ray { origin: rec.p, direction, tm: r_in.time(), wavelength: r_in.wavelength()};
         true
     }
 }
@@ -135,52 +265,261 @@ impl Dialectric {
     }
 }
 
+// Glass with a rough surface of GGX microfacets, like frosted glass or
+// rough water. Each facet reflects and refracts by the exact Fresnel
+// equations for index of refraction `ir`. A roughness of 0 gives smooth
+// glass like Dialectric, without Schlick's approximation.
+//
+// Light going into a denser medium is squeezed into a narrower cone of
+// directions, so its radiance grows by the square of the ratio of the
+// indices, and it shrinks again on the way out. Following rays from the
+// camera the ratio is that of the side the ray arrives from over the other
+// side, squared.
+pub struct RoughDielectric {
+    pub ir: f64,
+    distribution: Ggx,
+}
+
+impl Material for RoughDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let unit_direction = Vec3::unit_vector(r_in.direction());
+        let eta = self.eta(rec);
+        if self.distribution.is_smooth() {
+            let cos_theta = Vec3::dot(-unit_direction, rec.normal).min(1.0);
+            srec.skip_pdf = true;
+            let direction = if fresnel_dielectric(cos_theta, eta) > random_double() {
+                srec.attenuation = Color(1.0, 1.0, 1.0);
+                reflect(&unit_direction, &rec.normal)
+            } else {
+                srec.attenuation = Color(1.0, 1.0, 1.0) / (eta*eta);
+                refract(&unit_direction, &rec.normal, 1.0 / eta)
+            };
+            srec.skip_pdf_ray = Ray { origin: rec.p, direction, tm: r_in.time(), wavelength: r_in.wavelength()};
+            return true;
+        }
+
+        let frame = ShadingFrame::new(&rec.normal, &Vec3(1.0, 0.0, 0.0));
+        let wo = frame.to_local(&-unit_direction);
+        if wo.z() <= 0.0 {
+            return false;
+        }
+        srec.attenuation = Color(1.0, 1.0, 1.0);
+        srec.pdf_ptr = Arc::new(MicrofacetDielectricPdf {
+            frame,
+            wo,
+            distribution: self.distribution,
+            eta,
+        });
+        srec.skip_pdf = false;
+        true
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        let frame = ShadingFrame::new(&rec.normal, &Vec3(1.0, 0.0, 0.0));
+        let wo = frame.to_local(&-Vec3::unit_vector(r_in.direction()));
+        let wi = frame.to_local(&Vec3::unit_vector(scattered.direction()));
+        MicrofacetDielectricPdf::local_value(&self.distribution, self.eta(rec), &wo, &wi)
+    }
+
+    fn scattering_color(&self, r_in: &Ray, rec: &HitRecord, _srec: &ScatterRecord, scattered: &Ray) -> Color {
+        let frame = ShadingFrame::new(&rec.normal, &Vec3(1.0, 0.0, 0.0));
+        let wo = frame.to_local(&-Vec3::unit_vector(r_in.direction()));
+        let wi = frame.to_local(&Vec3::unit_vector(scattered.direction()));
+        let value = dielectric_scattering(&self.distribution, self.eta(rec), &wo, &wi);
+        Color(value, value, value)
+    }
+}
+
+impl RoughDielectric {
+    pub fn new(ir: f64, roughness: f64) -> Self {
+        RoughDielectric {
+            ir,
+            distribution: Ggx::from_roughness(roughness, roughness),
+        }
+    }
+
+    // Index of refraction of the far side over that of the side the ray
+    // arrives from
+    fn eta(&self, rec: &HitRecord) -> f64 {
+        if rec.front_face { self.ir } else { 1.0 / self.ir }
+    }
+}
+
+// Smooth glass whose index of refraction depends on the wavelength, so it
+// splits white light into a rainbow like a prism or a diamond. A ray of
+// white light (see spectrum.rs) that is refracted carries a single hero
+// wavelength from then on, weighted by its colour. Reflection sends every
+// wavelength the same way, so a reflected ray stays as it was, with the
+// chance to reflect taken at the hero wavelength. Refraction scales the
+// radiance like RoughDielectric.
+pub struct DispersiveDielectric {
+    pub dispersion: Dispersion,
+    wavelengths: WavelengthSampler,
+}
+
+impl Material for DispersiveDielectric {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let (wavelength, weight) = if r_in.wavelength() > 0.0 {
+            (r_in.wavelength(), Color(1.0, 1.0, 1.0))
+        } else {
+            self.wavelengths.sample()
+        };
+        let ir = self.dispersion.ior(wavelength);
+        let eta = if rec.front_face { ir } else { 1.0 / ir };
+
+        let unit_direction = Vec3::unit_vector(r_in.direction());
+        let cos_theta = Vec3::dot(-unit_direction, rec.normal).min(1.0);
+        srec.skip_pdf = true;
+        if fresnel_dielectric(cos_theta, eta) > random_double() {
+            srec.attenuation = Color(1.0, 1.0, 1.0);
+            srec.skip_pdf_ray = Ray { origin: rec.p, direction: reflect(&unit_direction, &rec.normal), tm: r_in.time(), wavelength: r_in.wavelength()};
+        } else {
+            srec.attenuation = weight / (eta*eta);
+            srec.skip_pdf_ray = Ray { origin: rec.p, direction: refract(&unit_direction, &rec.normal, 1.0 / eta), tm: r_in.time(), wavelength};
+        }
+        true
+    }
+}
+
+impl DispersiveDielectric {
+    pub fn new(dispersion: Dispersion) -> Self {
+        DispersiveDielectric {
+            dispersion,
+            wavelengths: WavelengthSampler::new(),
+        }
+    }
+}
+
+// Gives off the texture times `scale` as radiance, the same in every
+// direction. Like in the book, only the front of the surface, the side its
+// normal points to, gives off light, unless the light is two-sided. Turn a
+// surface around with FlipFace to light the other side.
+//
+// The texture can be read either as radiance, or as the power given off by
+// the whole surface, for which the area of the surface has to be known. See
+// with_power.
 pub struct DiffuseLight {
-    emit: Rc<RefCell<dyn Texture>>
+    emit: Arc<dyn Texture>,
+    // Set when the light is made, or by set_area for a light given by its power
+    scale: OnceLock<f64>,
+    // The power to spread over the area given to set_area
+    power: f64,
+    two_sided: bool,
 }
 
 impl Material for DiffuseLight {
     fn emitted(&self, _r_in: &Ray, rec: &HitRecord, u: f64, v: f64, p: &Point3) -> Color {
-        if rec.front_face {
+        if !rec.front_face && !self.two_sided {
             return Color(0.0, 0.0, 0.0);
         }
-        return self.emit.borrow().value(u, v, p);
+        self.scale() * self.emit.value(u, v, p)
+    }
+
+    // Mean of the texture over a grid of texture coordinates
+    fn average_emission(&self) -> f64 {
+        let n = 4;
+        let mut sum = 0.0;
+        for i in 0..n {
+            for j in 0..n {
+                let u = (i as f64 + 0.5) / n as f64;
+                let v = (j as f64 + 0.5) / n as f64;
+                sum += luminance(self.emit.value(u, v, &Point3(0.0, 0.0, 0.0)));
+            }
+        }
+        self.sides() * self.scale() * sum / (n*n) as f64
     }
 }
 
 impl DiffuseLight {
     pub fn new(c: Color) -> Self {
+        DiffuseLight::new_texture(Arc::new(SolidColor { color_value: c }))
+    }
+
+    pub fn new_texture(a: Arc<dyn Texture>) -> Self {
         DiffuseLight {
-            emit: Rc::new(RefCell::new(SolidColor { color_value: c }))
+            emit: a,
+            scale: OnceLock::from(1.0),
+            power: 0.0,
+            two_sided: false,
         }
     }
+
+    // Multiplies the radiance by `intensity`
+    pub fn with_intensity(mut self, intensity: f64) -> Self {
+        self.scale = OnceLock::from(intensity);
+        self
+    }
+
+    // Lets the back of the surface give off light as well
+    pub fn with_two_sides(mut self, two_sided: bool) -> Self {
+        self.two_sided = two_sided;
+        self
+    }
+
+    // Reads the texture times `power` as the power given off by a surface of
+    // `area`, in all directions and on every side that gives off light. Each
+    // side gives off radiance L over half of the directions, a power of
+    // pi * L per unit area, so the radiance is the power divided by
+    // pi * area * sides. The material is then only right for surfaces of
+    // that area, and each of them needs its own.
+    pub fn with_power(self, power: f64, area: f64) -> Self {
+        let light = self.with_power_for_later_area(power);
+        light.set_area(area);
+        light
+    }
+
+    // Like with_power, for a surface made with this material, whose area is
+    // only known once it exists. Gives off nothing until set_area is called.
+    pub fn with_power_for_later_area(mut self, power: f64) -> Self {
+        self.power = power;
+        self.scale = OnceLock::new();
+        self
+    }
+
+    // Spreads the power of with_power_for_later_area over `area`. Only the
+    // first call counts.
+    pub fn set_area(&self, area: f64) {
+        let _ = self.scale.set(if area > 0.0 { self.power / (PI * area * self.sides()) } else { 0.0 });
+    }
+
+    pub fn is_two_sided(&self) -> bool {
+        self.two_sided
+    }
+
+    fn scale(&self) -> f64 {
+        self.scale.get().copied().unwrap_or(0.0)
+    }
+
+    fn sides(&self) -> f64 {
+        if self.two_sided { 2.0 } else { 1.0 }
+    }
 }
 
 pub struct Isotropic {
-    albedo: Rc<RefCell<dyn Texture>>,
+    albedo: Arc<dyn Texture>,
 }
 
 impl Material for Isotropic {
     fn scatter(&self, _r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
-        srec.attenuation = self.albedo.borrow().value(rec.u, rec.v, &rec.p);
-        srec.pdf_ptr = Rc::new(RefCell::new(SpherePdf()));
+        srec.attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        srec.pdf_ptr = Arc::new(SpherePdf());
         srec.skip_pdf = false;
-        return true;
+        true
     }
 
-    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &mut Ray) -> f64 {
-        1.0 * (4.0 * PI)
+    fn scattering_pdf(&self, _r_in: &Ray, _rec: &HitRecord, _scattered: &Ray) -> f64 {
+        1.0 / (4.0 * PI)
     }
 }
 
 impl Isotropic {
     pub fn new(c: Color) -> Self {
         Isotropic {
-            albedo: Rc::new(RefCell::new(SolidColor::new(c[0], c[1], c[2])))
+            albedo: Arc::new(SolidColor::new(c[0], c[1], c[2]))
         }
     }
 
-    pub fn new_texture(a: Rc<RefCell<dyn Texture>>) -> Self {
+    pub fn new_texture(a: Arc<dyn Texture>) -> Self {
         Isotropic { albedo: a }
     }
 }
\ No newline at end of file
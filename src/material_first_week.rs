@@ -12,16 +12,15 @@ use crate::hittable_first_week::HitRecord;
 use crate::texture::Texture;
 use crate::texture::SolidColor;
 
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
-pub trait Material {
+pub trait Material: Send + Sync {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool;
     fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color;
 }
 
 pub struct Lambertian {
-    pub albedo: Rc<RefCell<dyn Texture>>,
+    pub albedo: Arc<dyn Texture>,
 }
 
 impl Material for Lambertian {
@@ -32,8 +31,8 @@ impl Material for Lambertian {
             scatter_direction = rec.normal;
         }
 
-        *scattered = Ray { origin: rec.p, direction: scatter_direction, tm: _r_in.time()};
-        *attenuation = self.albedo.borrow().value(rec.u, rec.v, &rec.p);
+        *scattered = Ray { origin: rec.p, direction: scatter_direction, tm: _r_in.time(), wavelength: _r_in.wavelength()};
+        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
         true
     }
 
@@ -44,7 +43,7 @@ impl Material for Lambertian {
 
 impl Lambertian {
     pub fn new(a: &Color) -> Self {
-        Lambertian {albedo: Rc::new(RefCell::new(SolidColor::new(a.0, a.1, a.2)))}
+        Lambertian {albedo: Arc::new(SolidColor::new(a.0, a.1, a.2))}
     }
 }
 
@@ -68,7 +67,7 @@ pub struct Metal {
 impl Material for Metal {
     fn scatter(&self, r_in: &Ray, rec: &HitRecord, attenuation: &mut Color, scattered: &mut Ray) -> bool {
         let reflected = reflect(&Vec3::unit_vector(r_in.direction()), &rec.normal);
-        *scattered = Ray {origin: rec.p, direction: reflected + self.fuzz*random_in_unit_sphere(), tm: r_in.time()};
+        *scattered = Ray {origin: rec.p, direction: reflected + self.fuzz*random_in_unit_sphere(), tm: r_in.time(), wavelength: r_in.wavelength()};
         *attenuation = self.albedo;
 
         Vec3::dot(scattered.direction(), rec.normal) > 0.0
@@ -97,15 +96,13 @@ impl Material for Dialectric {
         let sin_theta: f64 = (1.0 - cos_theta*cos_theta).sqrt();
 
         let cannot_refract: bool = refraction_ratio * sin_theta > 1.0;
-        let direction: Vec3;
-
-        if cannot_refract || Dialectric::reflectance(cos_theta, refraction_ratio) > random_double_range(0.0, 1.0) {
-            direction = reflect(&unit_direction, &rec.normal);
+        let direction: Vec3 = if cannot_refract || Dialectric::reflectance(cos_theta, refraction_ratio) > random_double_range(0.0, 1.0) {
+            reflect(&unit_direction, &rec.normal)
         } else {
-            direction = refract(&unit_direction, &rec.normal, refraction_ratio)
-        }
+            refract(&unit_direction, &rec.normal, refraction_ratio)
+        };
 
-        *scattered = Ray { origin: rec.p, direction: direction, tm: r_in.time()};
+        *scattered = Ray { origin: rec.p, direction, tm: r_in.time(), wavelength: r_in.wavelength()};
         true
     }
 
@@ -123,7 +120,7 @@ impl Dialectric {
 }
 
 pub struct DiffuseLight {
-    emit: Rc<RefCell<dyn Texture>>
+    emit: Arc<dyn Texture>
 }
 
 impl Material for DiffuseLight {
@@ -132,20 +129,20 @@ impl Material for DiffuseLight {
     }
 
     fn emitted(&self, u: f64, v: f64, p: &Point3) -> Color {
-        self.emit.borrow().value(u, v, p)
+        self.emit.value(u, v, p)
     }
 }
 
 impl DiffuseLight {
     pub fn new(c: Color) -> Self {
         DiffuseLight {
-            emit: Rc::new(RefCell::new(SolidColor { color_value: c }))
+            emit: Arc::new(SolidColor { color_value: c })
         }
     }
 }
 
 pub struct Isotropic {
-    albedo: Rc<RefCell<dyn Texture>>
+    albedo: Arc<dyn Texture>
 }
 
 impl Material for Isotropic {
@@ -154,9 +151,10 @@ impl Material for Isotropic {
             origin: rec.p,
             direction: random_in_unit_sphere(),
             tm: r_in.time(), 
+            wavelength: r_in.wavelength(),
         };
-        *attenuation = self.albedo.borrow().value(rec.u, rec.v, &rec.p);
-        return true;
+        *attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
+        true
     }
 
     fn emitted(&self, _u: f64, _v: f64, _p: &Point3) -> Color {
@@ -167,7 +165,7 @@ impl Material for Isotropic {
 impl Isotropic {
     pub fn new(c: Color) -> Self {
         Isotropic {
-            albedo: Rc::new(RefCell::new(SolidColor::new(c[0], c[1], c[2])))
+            albedo: Arc::new(SolidColor::new(c[0], c[1], c[2]))
         }
     }
 }
\ No newline at end of file
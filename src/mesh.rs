@@ -0,0 +1,399 @@
+use crate::hittable::Hittable;
+use crate::hittable::HitRecord;
+use crate::hittable::area_light_power;
+use crate::aabb::AABB;
+
+use crate::mat4::Mat4;
+
+use crate::ray::Ray;
+
+use crate::material::Material;
+use crate::material::Lambertian;
+
+use crate::linear_bvh::FlatBvh;
+
+use crate::triangle::intersect_triangle;
+use crate::triangle::set_triangle_normal;
+use crate::triangle::triangle_box;
+use crate::triangle::random_point_on_triangle;
+
+use crate::obj::load_obj;
+use crate::ply::load_ply;
+use crate::stl::load_stl;
+
+use crate::rtweekend::random_double;
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+use crate::vec3::Color;
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+// Corners of one triangle, as indices into the buffers of its mesh.
+#[derive(Copy, Clone)]
+pub struct MeshFace {
+    pub positions: [usize; 3],
+    pub normals: Option<[usize; 3]>,
+    pub uvs: Option<[usize; 3]>,
+    pub material: usize,
+}
+
+// Vertex buffers and faces shared by all triangles of a mesh. Index checks
+// are up to the loaders: TriangleMesh::new expects every index to be valid.
+pub struct MeshData {
+    pub positions: Vec<Point3>,
+    pub normals: Vec<Vec3>,
+    pub uvs: Vec<(f64, f64)>,
+    pub faces: Vec<MeshFace>,
+    pub materials: Vec<Arc<dyn Material>>,
+}
+
+impl Default for MeshData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MeshData {
+    pub fn new() -> Self {
+        MeshData {
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            faces: Vec::new(),
+            materials: Vec::new(),
+        }
+    }
+
+    pub fn corners(&self, face: usize) -> [Point3; 3] {
+        let f = &self.faces[face];
+        [self.positions[f.positions[0]], self.positions[f.positions[1]], self.positions[f.positions[2]]]
+    }
+
+    pub fn area(&self, face: usize) -> f64 {
+        self.transformed_area(face, &Mat4::identity())
+    }
+
+    // See Hittable::area
+    pub fn transformed_area(&self, face: usize, matrix: &Mat4) -> f64 {
+        let [p0, p1, p2] = self.corners(face);
+        0.5*matrix.transformed_area(&(p1 - p0), &(p2 - p0))
+    }
+
+    // See Hittable::transformed_emitted_power
+    pub fn face_power(&self, face: usize, matrix: &Mat4) -> f64 {
+        area_light_power(self.transformed_area(face, matrix), self.materials[self.faces[face].material].as_ref())
+    }
+
+    pub fn hit_face(&self, face: usize, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let [p0, p1, p2] = self.corners(face);
+        let (t, b1, b2) = match intersect_triangle(r, &p0, &p1, &p2, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let b0 = 1.0 - b1 - b2;
+        let f = &self.faces[face];
+
+        rec.t = t;
+        rec.p = r.at(t);
+        match f.uvs {
+            Some([i0, i1, i2]) => {
+                let uvs = &self.uvs;
+                rec.u = b0*uvs[i0].0 + b1*uvs[i1].0 + b2*uvs[i2].0;
+                rec.v = b0*uvs[i0].1 + b1*uvs[i1].1 + b2*uvs[i2].1;
+            },
+            None => {
+                rec.u = b1;
+                rec.v = b2;
+            },
+        }
+        let geometric_normal = Vec3::cross(p1 - p0, p2 - p0);
+        let shading_normal = match f.normals {
+            Some([i0, i1, i2]) => b0*self.normals[i0] + b1*self.normals[i1] + b2*self.normals[i2],
+            None => geometric_normal,
+        };
+        set_triangle_normal(rec, r, geometric_normal, shading_normal);
+        rec.mat_ptr = self.materials[f.material].clone();
+        true
+    }
+
+    // The density over directions of points picked uniformly on `area`, for
+    // the direction of `r` through the face. 0 if the ray misses the face.
+    pub fn face_pdf(&self, face: usize, r: &Ray, area: f64) -> f64 {
+        let [p0, p1, p2] = self.corners(face);
+        let t = match intersect_triangle(r, &p0, &p1, &p2, 0.001, f64::INFINITY) {
+            Some((t, _, _)) => t,
+            None => return 0.0,
+        };
+
+        let v = r.direction();
+        let n = Vec3::cross(p1 - p0, p2 - p0);
+        let distance_squared = t * t * v.length_square();
+        let cosine = Vec3::dot(v, n).abs() / (v.length() * n.length());
+        distance_squared / (cosine * area)
+    }
+}
+
+// One face of a mesh, so the faces that give off light can be lights of
+// their own. It only holds a reference to the shared buffers.
+pub struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    face: usize,
+}
+
+impl Hittable for MeshTriangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        self.mesh.hit_face(self.face, r, t_min, t_max, rec)
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        let [p0, p1, p2] = self.mesh.corners(self.face);
+        *output_box = triangle_box(&p0, &p1, &p2);
+        true
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.mesh.face_power(self.face, matrix)
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.mesh.transformed_area(self.face, matrix)
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let r = Ray { origin: *o, direction: *v, tm: 0.0, wavelength: 0.0 };
+        self.mesh.face_pdf(self.face, &r, self.mesh.area(self.face))
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        let [p0, p1, p2] = self.mesh.corners(self.face);
+        random_point_on_triangle(&p0, &p1, &p2) - *o
+    }
+}
+
+// A triangle mesh with its own BVH over the faces. Used as a light, points
+// are picked uniformly over the whole surface.
+pub struct TriangleMesh {
+    pub data: Arc<MeshData>,
+    // None for a mesh without faces
+    tree: Option<FlatBvh>,
+    // Face at each position of the tree order, which the leaf ranges refer to
+    order: Vec<usize>,
+    // Running sum of the face areas, for picking faces in proportion to their area
+    area_cdf: Vec<f64>,
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let tree = match &self.tree {
+            Some(tree) => tree,
+            None => return false,
+        };
+        tree.traverse(r, t_min, t_max, |first, count, closest_so_far| {
+            let mut hit_anything = false;
+            let mut closest_so_far = closest_so_far;
+            for &face in &self.order[first..first + count] {
+                if self.data.hit_face(face, r, t_min, closest_so_far, rec) {
+                    hit_anything = true;
+                    closest_so_far = rec.t;
+                }
+            }
+            if hit_anything { Some(closest_so_far) } else { None }
+        })
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        match &self.tree {
+            Some(tree) => {
+                *output_box = tree.bounds();
+                true
+            },
+            None => false,
+        }
+    }
+
+    // Every face crossed by the direction adds to the density, hidden or not,
+    // since random() can pick points on any of them.
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let total_area = self.total_area();
+        let tree = match &self.tree {
+            Some(tree) if total_area > 0.0 => tree,
+            _ => return 0.0,
+        };
+
+        // The leaves never report a hit, so the ray is not cut short and
+        // every leaf whose box it crosses is visited
+        let r = Ray { origin: *o, direction: *v, tm: 0.0, wavelength: 0.0 };
+        let mut sum = 0.0;
+        tree.traverse(&r, 0.001, f64::INFINITY, |first, count, _| {
+            for &face in &self.order[first..first + count] {
+                sum += self.data.face_pdf(face, &r, total_area);
+            }
+            None
+        });
+        sum
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        if self.data.faces.is_empty() {
+            return Vec3(1.0, 0.0, 0.0);
+        }
+        let target = random_double() * self.total_area();
+        let face = self.area_cdf.partition_point(|&a| a <= target).min(self.data.faces.len() - 1);
+        let [p0, p1, p2] = self.data.corners(face);
+        random_point_on_triangle(&p0, &p1, &p2) - *o
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        (0..self.data.faces.len()).map(|face| self.data.face_power(face, matrix)).sum()
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        (0..self.data.faces.len()).map(|face| self.data.transformed_area(face, matrix)).sum()
+    }
+
+    // The whole mesh if every face gives off light, else the faces that do
+    fn collect_lights(&self, this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        let emitting: Vec<usize> = (0..self.data.faces.len()).filter(|&face| self.data.face_power(face, &Mat4::identity()) > 0.0).collect();
+        if emitting.len() == self.data.faces.len() {
+            if !emitting.is_empty() {
+                lights.push(this.clone());
+            }
+            return;
+        }
+        for face in emitting {
+            lights.push(Arc::new(MeshTriangle { mesh: self.data.clone(), face }));
+        }
+    }
+}
+
+impl TriangleMesh {
+    pub fn new(data: MeshData) -> Self {
+        let data = Arc::new(data);
+
+        let mut boxes: Vec<AABB> = Vec::with_capacity(data.faces.len());
+        let mut area_cdf = Vec::with_capacity(data.faces.len());
+        let mut total_area = 0.0;
+        for face in 0..data.faces.len() {
+            let [p0, p1, p2] = data.corners(face);
+            boxes.push(triangle_box(&p0, &p1, &p2));
+            total_area += data.area(face);
+            area_cdf.push(total_area);
+        }
+
+        let (tree, order) = if boxes.is_empty() {
+            (None, Vec::new())
+        } else {
+            let (tree, order) = FlatBvh::new(&boxes).expect("triangles always have a bounding box");
+            (Some(tree), order)
+        };
+
+        TriangleMesh {
+            data,
+            tree,
+            order,
+            area_cdf,
+        }
+    }
+
+    pub fn total_area(&self) -> f64 {
+        *self.area_cdf.last().unwrap_or(&0.0)
+    }
+}
+
+// Loads a mesh file in the format given by its extension: .obj, .ply or .stl.
+// `material` is used for the whole mesh where the file has no materials of its
+// own (OBJ faces without usemtl, PLY and STL files); without it, PLY vertex
+// colours are used, or else a grey Lambertian.
+pub fn load_mesh(path: &str, material: Option<Arc<dyn Material>>) -> Result<TriangleMesh, MeshError> {
+    let extension = Path::new(path).extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    let default_material = || -> Arc<dyn Material> { Arc::new(Lambertian::new(&Color(0.8, 0.8, 0.8))) };
+
+    match extension.as_str() {
+        "obj" => load_obj(path, material.unwrap_or_else(default_material)),
+        "ply" => load_ply(path, material),
+        "stl" => load_stl(path, material.unwrap_or_else(default_material)),
+        _ => Err(MeshError::Io(io::Error::new(io::ErrorKind::Unsupported, format!("unknown mesh format '{}'", extension)))),
+    }
+}
+
+// Errors of the mesh file loaders.
+#[derive(Debug)]
+pub enum MeshError {
+    Io(io::Error),
+    // A problem in `file` at `line` (counted from 1, or 0 in binary data)
+    Parse { file: String, line: usize, message: String },
+}
+
+impl fmt::Display for MeshError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MeshError::Io(e) => write!(f, "{}", e),
+            MeshError::Parse { file, line: 0, message } => write!(f, "{}: {}", file, message),
+            MeshError::Parse { file, line, message } => write!(f, "{}:{}: {}", file, line, message),
+        }
+    }
+}
+
+impl std::error::Error for MeshError {}
+
+impl From<io::Error> for MeshError {
+    fn from(e: io::Error) -> Self {
+        MeshError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::material::DefaultMaterial;
+
+    // Two unit squares facing the origin, at z = 1 and z = 2
+    fn two_squares() -> TriangleMesh {
+        let mut data = MeshData::new();
+        for z in [1.0, 2.0] {
+            let first = data.positions.len();
+            data.positions.extend_from_slice(&[Point3(0.0, 0.0, z), Point3(1.0, 0.0, z), Point3(1.0, 1.0, z), Point3(0.0, 1.0, z)]);
+            for positions in [[first, first + 1, first + 2], [first, first + 2, first + 3]] {
+                data.faces.push(MeshFace { positions, normals: None, uvs: None, material: 0 });
+            }
+        }
+        data.materials.push(Arc::new(DefaultMaterial));
+        TriangleMesh::new(data)
+    }
+
+    #[test]
+    fn rays_hit_the_nearest_face() {
+        let mesh = two_squares();
+        let r = Ray { origin: Point3(0.0, 0.0, 0.0), direction: Vec3(0.25, 0.5, 1.0), tm: 0.0, wavelength: 0.0 };
+        let mut rec = HitRecord {
+            p: Point3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 0.0, 0.0),
+            mat_ptr: Arc::new(DefaultMaterial),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+        };
+        assert!(mesh.hit(&r, 0.001, f64::INFINITY, &mut rec));
+        assert!((rec.t - 1.0).abs() < 1.0e-12);
+        assert!(!mesh.hit(&r, 0.001, 0.5, &mut rec));
+    }
+
+    #[test]
+    fn the_density_counts_every_face_crossed() {
+        let mesh = two_squares();
+        let v = Vec3(0.25, 0.5, 1.0);
+        // Each square adds distance^2 / (cosine * total area), with a
+        // cosine of 1/|v| and distances |v| and 2|v|
+        let expected = (1.0 + 4.0) * v.length().powi(3) / 2.0;
+        let pdf = mesh.pdf_value(&Point3(0.0, 0.0, 0.0), &v);
+        assert!((pdf - expected).abs() < 1.0e-9, "pdf {} expected {}", pdf, expected);
+        assert_eq!(mesh.pdf_value(&Point3(0.0, 0.0, 0.0), &Vec3(-1.0, 0.5, 1.0)), 0.0);
+    }
+}
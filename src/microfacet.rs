@@ -0,0 +1,498 @@
+// Microfacet models for rough surfaces.
+//
+// A rough surface is seen as many tiny mirror facets, whose normals m are
+// spread around the shading normal by the GGX (Trowbridge-Reitz)
+// distribution D. Facets hide each other, which the Smith masking function
+// G accounts for. A rough mirror then reflects
+//
+//   f(wo, wi) = F(wo.m) D(m) G(wo, wi) / (4 cos(wo) cos(wi))
+//
+// with m halfway between wo and wi and F the Fresnel reflectance of a facet.
+// A rough dielectric also refracts through its facets, with m found from wo
+// and wi by the law of refraction instead (Walter et al., "Microfacet Models
+// for Refraction through Rough Surfaces", EGSR 2007).
+//
+// The roughness may differ along the two tangent directions of the surface
+// (anisotropy), which stretches highlights like on brushed metal. alpha_x and
+// alpha_y are the widths of the distribution along them, the squares of the
+// perceptual roughness. Directions are sampled from the normals visible from
+// wo (Heitz, "Sampling the GGX Distribution of Visible Normals", JCGT 2018),
+// which wastes no samples on facets that face away.
+//
+// All vectors here are in the local frame of ShadingFrame, with the normal
+// along z.
+
+use crate::vec3::Vec3;
+use crate::vec3::Color;
+
+use crate::pdf::Pdf;
+use crate::onb::Onb;
+use crate::rtweekend::random_double;
+
+use std::f64::consts::PI;
+
+// Below this alpha a surface is treated as a perfect mirror, since the
+// distribution becomes too narrow to evaluate reliably
+const MIN_ALPHA: f64 = 1.0e-3;
+
+// Tangent, bitangent and normal at a hit. The tangent is `tangent` made
+// perpendicular to the normal, so anisotropic roughness follows one
+// direction in the world, like the brushing of a metal plate.
+#[derive(Copy, Clone)]
+pub struct ShadingFrame {
+    t: Vec3,
+    b: Vec3,
+    n: Vec3,
+}
+
+impl ShadingFrame {
+    pub fn new(normal: &Vec3, tangent: &Vec3) -> Self {
+        let n = Vec3::unit_vector(*normal);
+        let projected = *tangent - Vec3::dot(*tangent, n) * n;
+        let t = if projected.length_square() > 1.0e-12 {
+            Vec3::unit_vector(projected)
+        } else {
+            // The tangent is along the normal, any direction will do
+            let mut uvw = Onb(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 0.0));
+            uvw.build_from_w(&n);
+            uvw.u()
+        };
+        ShadingFrame {
+            t,
+            b: Vec3::cross(n, t),
+            n,
+        }
+    }
+
+    pub fn to_local(&self, v: &Vec3) -> Vec3 {
+        Vec3(Vec3::dot(*v, self.t), Vec3::dot(*v, self.b), Vec3::dot(*v, self.n))
+    }
+
+    pub fn to_world(&self, v: &Vec3) -> Vec3 {
+        v.x()*self.t + v.y()*self.b + v.z()*self.n
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    // From the perceptual roughness along the tangent and the bitangent,
+    // each from 0 (a mirror) to 1
+    pub fn from_roughness(roughness_u: f64, roughness_v: f64) -> Self {
+        let roughness_u = roughness_u.clamp(0.0, 1.0);
+        let roughness_v = roughness_v.clamp(0.0, 1.0);
+        Ggx {
+            alpha_x: roughness_u * roughness_u,
+            alpha_y: roughness_v * roughness_v,
+        }
+    }
+
+    // Too smooth to be anything but a perfect mirror
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < MIN_ALPHA
+    }
+
+    // The distribution is evaluated with the alphas kept above MIN_ALPHA,
+    // so a surface that is smooth in one direction only stays finite
+    fn alphas(&self) -> (f64, f64) {
+        (self.alpha_x.max(MIN_ALPHA), self.alpha_y.max(MIN_ALPHA))
+    }
+
+    // Density of facet normals m per unit projected area
+    pub fn d(&self, m: &Vec3) -> f64 {
+        if m.z() <= 0.0 {
+            return 0.0;
+        }
+        let (ax, ay) = self.alphas();
+        let x = m.x() / ax;
+        let y = m.y() / ay;
+        let s = x*x + y*y + m.z()*m.z();
+        1.0 / (PI * ax * ay * s * s)
+    }
+
+    fn lambda(&self, w: &Vec3) -> f64 {
+        if w.z() == 0.0 {
+            return f64::INFINITY;
+        }
+        let (ax, ay) = self.alphas();
+        let tan2 = (ax*ax*w.x()*w.x() + ay*ay*w.y()*w.y()) / (w.z()*w.z());
+        0.5 * (-1.0 + (1.0 + tan2).sqrt())
+    }
+
+    // Fraction of the facets seen from w that are not hidden
+    pub fn g1(&self, w: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    // Fraction seen from both wo and wi, with the heights of the facets
+    // taken into account
+    pub fn g2(&self, wo: &Vec3, wi: &Vec3) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    // A facet normal drawn from those visible from wo, which must be above
+    // the surface
+    pub fn sample_visible_normal(&self, wo: &Vec3) -> Vec3 {
+        let (ax, ay) = self.alphas();
+        // Stretch the view so the distribution becomes a hemisphere
+        let vh = Vec3::unit_vector(Vec3(ax * wo.x(), ay * wo.y(), wo.z()));
+        let lensq = vh.x()*vh.x() + vh.y()*vh.y();
+        let t1 = if lensq > 0.0 { Vec3(-vh.y(), vh.x(), 0.0) / lensq.sqrt() } else { Vec3(1.0, 0.0, 0.0) };
+        let t2 = Vec3::cross(vh, t1);
+
+        // A point on the part of the disc that is seen from vh
+        let r = random_double().sqrt();
+        let phi = 2.0*PI*random_double();
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + vh.z());
+        let p2 = (1.0 - s) * (1.0 - p1*p1).max(0.0).sqrt() + s * r * phi.sin();
+        let nh = p1*t1 + p2*t2 + (1.0 - p1*p1 - p2*p2).max(0.0).sqrt()*vh;
+
+        // And back to the stretched distribution
+        Vec3::unit_vector(Vec3(ax * nh.x(), ay * nh.y(), nh.z().max(1.0e-6)))
+    }
+
+    // Density of sample_visible_normal over the facet normals
+    pub fn visible_normal_pdf(&self, wo: &Vec3, m: &Vec3) -> f64 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        self.g1(wo) * Vec3::dot(*wo, *m).max(0.0) * self.d(m) / wo.z()
+    }
+}
+
+// What a rough mirror reflects from wi towards wo, times cos(wi), before
+// the Fresnel reflectance of the facet: D G / (4 cos(wo)). Also gives the
+// facet normal, to find the reflectance with.
+pub fn reflection(distribution: &Ggx, wo: &Vec3, wi: &Vec3) -> Option<(Vec3, f64)> {
+    if wo.z() <= 0.0 || wi.z() <= 0.0 {
+        return None;
+    }
+    let m = Vec3::unit_vector(*wo + *wi);
+    Some((m, distribution.d(&m) * distribution.g2(wo, wi) / (4.0 * wo.z())))
+}
+
+// What a rough dielectric scatters from wi towards wo, times cos(wi), for
+// light followed from the camera, with the other side eta times as dense.
+// wi above the surface is reflected and wi below it refracted.
+pub fn dielectric_scattering(distribution: &Ggx, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+    if wo.z() <= 0.0 || wi.z() == 0.0 {
+        return 0.0;
+    }
+    let half_vector = if wi.z() > 0.0 {
+        MicrofacetDielectricPdf::reflection_half_vector(wo, wi)
+    } else {
+        MicrofacetDielectricPdf::refraction_half_vector(wo, wi, eta)
+    };
+    let m = match half_vector {
+        Some(m) => m,
+        None => return 0.0,
+    };
+    let wo_m = Vec3::dot(*wo, m);
+    let wi_m = Vec3::dot(*wi, m);
+    let reflectance = fresnel_dielectric(wo_m, eta);
+    let dg = distribution.d(&m) * distribution.g2(wo, wi);
+    if wi.z() > 0.0 {
+        reflectance * dg / (4.0 * wo.z())
+    } else {
+        // Walter et al.'s transmission term times cos(wi) has a factor of
+        // eta^2, which the change in radiance divides out again
+        let denom = wo_m + eta * wi_m;
+        (1.0 - reflectance) * dg * wo_m * wi_m.abs() / (denom * denom * wo.z())
+    }
+}
+
+// Mirror image of w about the facet normal m
+pub fn reflect_about(w: &Vec3, m: &Vec3) -> Vec3 {
+    2.0 * Vec3::dot(*w, *m) * *m - *w
+}
+
+// Direction of w refracted through a facet with normal m, on the side of m
+// w is on, going into a medium eta times as dense. None when all of it is
+// reflected.
+pub fn refract_about(w: &Vec3, m: &Vec3, eta: f64) -> Option<Vec3> {
+    let cos_i = Vec3::dot(*w, *m);
+    let sin2_t = (1.0 - cos_i*cos_i).max(0.0) / (eta*eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-*w / eta + (cos_i / eta - cos_t) * *m)
+}
+
+// Fresnel reflectance of a dielectric for light at cos_i to the normal,
+// going into a medium eta times as dense (exact, unpolarised). A negative
+// cos_i means the light comes from the other side.
+pub fn fresnel_dielectric(cos_i: f64, eta: f64) -> f64 {
+    let (cos_i, eta) = if cos_i < 0.0 { (-cos_i, 1.0 / eta) } else { (cos_i, eta) };
+    let cos_i = cos_i.min(1.0);
+    let sin2_t = (1.0 - cos_i*cos_i) / (eta*eta);
+    if sin2_t >= 1.0 {
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let r_parallel = (eta*cos_i - cos_t) / (eta*cos_i + cos_t);
+    let r_perpendicular = (cos_i - eta*cos_t) / (cos_i + eta*cos_t);
+    0.5 * (r_parallel*r_parallel + r_perpendicular*r_perpendicular)
+}
+
+// Fresnel reflectance of a metal for light at cos_i to the normal, with the
+// complex index of refraction eta + i k given per channel (exact,
+// unpolarised)
+pub fn fresnel_conductor(cos_i: f64, eta: &Color, k: &Color) -> Color {
+    let mut result = Color(0.0, 0.0, 0.0);
+    for c in 0..3 {
+        result[c] = fresnel_conductor_channel(cos_i, eta[c], k[c]);
+    }
+    result
+}
+
+fn fresnel_conductor_channel(cos_i: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = (cos_i * cos_i).min(1.0);
+    let sin2 = 1.0 - cos2;
+    let eta2 = eta * eta;
+    let k2 = k * k;
+
+    let t0 = eta2 - k2 - sin2;
+    let a2b2 = (t0*t0 + 4.0*eta2*k2).sqrt();
+    let t1 = a2b2 + cos2;
+    let a = (0.5 * (a2b2 + t0)).max(0.0).sqrt();
+    let t2 = 2.0 * cos_i.abs() * a;
+    let rs = (t1 - t2) / (t1 + t2);
+
+    let t3 = cos2 * a2b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let rp = rs * (t3 - t4) / (t3 + t4);
+
+    0.5 * (rp + rs)
+}
+
+// Draws reflected directions from the visible normals of a GGX surface
+pub struct MicrofacetReflectionPdf {
+    pub frame: ShadingFrame,
+    // Towards where the light leaves, in the local frame
+    pub wo: Vec3,
+    pub distribution: Ggx,
+}
+
+impl MicrofacetReflectionPdf {
+    // Density over the directions wi, in the local frame
+    pub fn local_value(distribution: &Ggx, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let m = Vec3::unit_vector(*wo + *wi);
+        let wo_m = Vec3::dot(*wo, m);
+        if wo_m <= 0.0 {
+            return 0.0;
+        }
+        // The reflection about m halves the angles, hence the 1 / (4 wo.m)
+        distribution.visible_normal_pdf(wo, &m) / (4.0 * wo_m)
+    }
+}
+
+impl Pdf for MicrofacetReflectionPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = self.frame.to_local(&Vec3::unit_vector(*direction));
+        MicrofacetReflectionPdf::local_value(&self.distribution, &self.wo, &wi)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let m = self.distribution.sample_visible_normal(&self.wo);
+        self.frame.to_world(&reflect_about(&self.wo, &m))
+    }
+}
+
+// Draws directions reflected or refracted by the visible normals of a rough
+// dielectric, choosing between the two by the Fresnel reflectance of the
+// facet. wo is above the surface and the other side is eta times as dense.
+pub struct MicrofacetDielectricPdf {
+    pub frame: ShadingFrame,
+    pub wo: Vec3,
+    pub distribution: Ggx,
+    pub eta: f64,
+}
+
+impl MicrofacetDielectricPdf {
+    // The facet normal that reflects wo into wi, if a facet facing both of
+    // them does
+    pub fn reflection_half_vector(wo: &Vec3, wi: &Vec3) -> Option<Vec3> {
+        let h = *wo + *wi;
+        if h.length_square() == 0.0 {
+            return None;
+        }
+        let m = Vec3::unit_vector(h);
+        if m.z() <= 0.0 || Vec3::dot(*wo, m) <= 0.0 {
+            return None;
+        }
+        Some(m)
+    }
+
+    // The facet normal that refracts wo into wi, if a facet facing wo with
+    // wi behind it does
+    pub fn refraction_half_vector(wo: &Vec3, wi: &Vec3, eta: f64) -> Option<Vec3> {
+        let h = *wo + eta * *wi;
+        if h.length_square() == 0.0 {
+            return None;
+        }
+        let mut m = Vec3::unit_vector(h);
+        if m.z() < 0.0 {
+            m = -m;
+        }
+        if m.z() == 0.0 || Vec3::dot(*wo, m) <= 0.0 || Vec3::dot(*wi, m) >= 0.0 {
+            return None;
+        }
+        Some(m)
+    }
+
+    // Density over the directions wi, in the local frame. A facet may send
+    // a reflected direction below the surface or a refracted one above it,
+    // where the material scatters nothing, so both are counted wherever wi
+    // is to match what generate draws.
+    pub fn local_value(distribution: &Ggx, eta: f64, wo: &Vec3, wi: &Vec3) -> f64 {
+        if wo.z() <= 0.0 {
+            return 0.0;
+        }
+        let mut pdf = 0.0;
+        if let Some(m) = MicrofacetDielectricPdf::reflection_half_vector(wo, wi) {
+            let wo_m = Vec3::dot(*wo, m);
+            let reflectance = fresnel_dielectric(wo_m, eta);
+            pdf += distribution.visible_normal_pdf(wo, &m) * reflectance / (4.0 * wo_m);
+        }
+        if let Some(m) = MicrofacetDielectricPdf::refraction_half_vector(wo, wi, eta) {
+            let wo_m = Vec3::dot(*wo, m);
+            let wi_m = Vec3::dot(*wi, m);
+            let reflectance = fresnel_dielectric(wo_m, eta);
+            // How much the refraction spreads the directions around m
+            let denom = wo_m + eta * wi_m;
+            pdf += distribution.visible_normal_pdf(wo, &m) * (1.0 - reflectance) * eta * eta * wi_m.abs() / (denom * denom);
+        }
+        pdf
+    }
+}
+
+impl Pdf for MicrofacetDielectricPdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        let wi = self.frame.to_local(&Vec3::unit_vector(*direction));
+        MicrofacetDielectricPdf::local_value(&self.distribution, self.eta, &self.wo, &wi)
+    }
+
+    fn generate(&self) -> Vec3 {
+        let m = self.distribution.sample_visible_normal(&self.wo);
+        let reflectance = fresnel_dielectric(Vec3::dot(self.wo, m), self.eta);
+        if random_double() >= reflectance {
+            if let Some(wi) = refract_about(&self.wo, &m, self.eta) {
+                return self.frame.to_world(&wi);
+            }
+        }
+        self.frame.to_world(&reflect_about(&self.wo, &m))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::pdf::checks::histogram_distance;
+    use crate::pdf::checks::sphere_integral;
+    use crate::rtweekend::seed_random;
+
+    fn near(a: f64, b: f64, tolerance: f64) -> bool {
+        (a - b).abs() < tolerance
+    }
+
+    // A tilted frame, so to_local and to_world are part of what is checked
+    fn frame() -> ShadingFrame {
+        ShadingFrame::new(&Vec3(0.3, 0.2, 1.0), &Vec3(1.0, 0.0, 0.0))
+    }
+
+    #[test]
+    fn reflection_density_matches_the_samples() {
+        let frame = frame();
+        let normal = frame.to_world(&Vec3(0.0, 0.0, 1.0));
+        for wo in [Vec3::unit_vector(Vec3(0.5, 0.3, 0.8)), Vec3::unit_vector(Vec3(-0.9, 0.1, 0.2))] {
+            let pdf = MicrofacetReflectionPdf {
+                frame,
+                wo,
+                distribution: Ggx::from_roughness(0.6, 0.8),
+            };
+
+            // Directions reflected below the surface are drawn but have no
+            // density, so they are left out of the histogram and the density
+            // integrates to the share of those above
+            seed_random(1);
+            let samples = 200000;
+            let mut above = 0;
+            let distance = histogram_distance(|| {
+                let direction = pdf.generate();
+                if Vec3::dot(direction, normal) > 0.0 {
+                    above += 1;
+                    direction
+                } else {
+                    Vec3(0.0, 0.0, 0.0)
+                }
+            }, |v| pdf.value(v), samples);
+            assert!(distance < 0.02, "distance {}", distance);
+
+            let total = sphere_integral(|v| pdf.value(v), 400);
+            let share = above as f64 / samples as f64;
+            assert!(near(total, share, 0.01), "integral {} share above {}", total, share);
+        }
+    }
+
+    #[test]
+    fn conductor_reflectance_at_normal_and_grazing_incidence() {
+        let eta = Color(0.2, 1.1, 2.5);
+        let k = Color(3.9, 2.6, 0.0);
+        let normal = fresnel_conductor(1.0, &eta, &k);
+        for c in 0..3 {
+            let expected = ((eta[c] - 1.0).powi(2) + k[c]*k[c]) / ((eta[c] + 1.0).powi(2) + k[c]*k[c]);
+            assert!(near(normal[c], expected, 1.0e-9), "channel {}: {} expected {}", c, normal[c], expected);
+        }
+
+        let grazing = fresnel_conductor(0.0, &eta, &k);
+        for c in 0..3 {
+            assert!(near(grazing[c], 1.0, 1.0e-9), "channel {}: {}", c, grazing[c]);
+        }
+
+        // Without absorption a conductor is a dielectric
+        let glass = fresnel_conductor(0.6, &Color(1.5, 1.5, 1.5), &Color(0.0, 0.0, 0.0));
+        assert!(near(glass[0], fresnel_dielectric(0.6, 1.5), 1.0e-9));
+    }
+
+    #[test]
+    fn dielectric_density_integrates_to_one_and_matches_the_samples() {
+        for (eta, wo) in [(1.5, Vec3::unit_vector(Vec3(0.5, 0.3, 0.8))), (1.0 / 1.5, Vec3::unit_vector(Vec3(-0.9, 0.1, 0.3)))] {
+            let pdf = MicrofacetDielectricPdf {
+                frame: frame(),
+                wo,
+                distribution: Ggx::from_roughness(0.5, 0.7),
+                eta,
+            };
+
+            let total = sphere_integral(|v| pdf.value(v), 400);
+            assert!(near(total, 1.0, 0.01), "eta {}: integral {}", eta, total);
+
+            seed_random(1);
+            let distance = histogram_distance(|| pdf.generate(), |v| pdf.value(v), 200000);
+            assert!(distance < 0.02, "eta {}: distance {}", eta, distance);
+        }
+    }
+
+    #[test]
+    fn dielectric_reflectance_at_normal_and_grazing_incidence() {
+        assert!(near(fresnel_dielectric(1.0, 1.5), 0.04, 1.0e-12));
+        // The same from inside the glass
+        assert!(near(fresnel_dielectric(-1.0, 1.5), 0.04, 1.0e-12));
+        assert!(near(fresnel_dielectric(1.0e-9, 1.5), 1.0, 1.0e-6));
+        // Past the critical angle of about 41.8 degrees all of it is reflected
+        assert_eq!(fresnel_dielectric(-0.7, 1.5), 1.0);
+        assert!(fresnel_dielectric(-0.8, 1.5) < 1.0);
+        // Nothing is reflected where nothing changes
+        assert!(near(fresnel_dielectric(0.3, 1.0), 0.0, 1.0e-12));
+    }
+}
@@ -1,11 +1,11 @@
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
 use crate::vec3::Vec3;
 use crate::vec3::Point3;
 
 use crate::hittable::Hittable;
 use crate::hittable::HitRecord;
+use crate::hittable::area_light_power;
 use crate::ray::Ray;
 
 use crate::material::Material;
@@ -13,6 +13,9 @@ use crate::material::Material;
 use crate::aabb::AABB;
 use crate::aabb::surrounding_box;
 
+use crate::mat4::Mat4;
+use crate::sphere::ellipsoid_area;
+
 #[derive(Clone)]
 pub struct MovingSphere {
     pub center0: Point3,
@@ -20,7 +23,7 @@ pub struct MovingSphere {
     pub time0: f64,
     pub time1: f64,
     pub radius: f64,
-    pub mat_ptr: Rc<RefCell<dyn Material>>,
+    pub mat_ptr: Arc<dyn Material>,
 }
 
 impl MovingSphere {
@@ -30,7 +33,7 @@ impl MovingSphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let oc: Vec3 = r.origin() - self.center(r.time());
         let a: f64 = r.direction().length_square();
         let half_b: f64 = Vec3::dot(oc, r.direction());
@@ -72,4 +75,15 @@ impl Hittable for MovingSphere {
         *output_box = surrounding_box(&box0, &box1);
         true
     }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        if self.mat_ptr.average_emission() <= 0.0 {
+            return 0.0;
+        }
+        area_light_power(self.area(matrix), self.mat_ptr.as_ref())
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        ellipsoid_area(self.radius, matrix)
+    }
 }
@@ -0,0 +1,455 @@
+// Wavefront OBJ and MTL import.
+//
+// Supported are vertex positions, normals and texture coordinates, polygonal
+// faces (split into triangle fans), and materials from the MTL files named by
+// `mtllib`. MTL materials are mapped onto the materials of the renderer:
+//
+//   Ke not black                          DiffuseLight with colour Ke
+//   any of Pr, Pm, Ps, Pc, Pcr, map_Pr    Principled with base colour Kd or map_Kd,
+//     or map_Pm (the PBR extension)         roughness Pr, metallic Pm, sheen Ps, clearcoat Pc
+//                                           and Pcr, transmission 1 - d and index Ni
+//   d < 1, Tr > 0 or illum 4, 6, 7 or 9   Dialectric with index Ni
+//   Ks brighter than Kd, or illum 3       Metal with albedo Ks, fuzz from Ns
+//   otherwise                             Lambertian with Kd, or map_Kd as an ImageTexture
+//
+// Faces before any `usemtl` get the default material passed to the loader.
+// A material library that cannot be read, or a `usemtl` naming a material
+// that no library defines, is an error.
+
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+use crate::vec3::Color;
+
+use crate::material::Material;
+use crate::material::Lambertian;
+use crate::material::Metal;
+use crate::material::Dialectric;
+use crate::material::DiffuseLight;
+use crate::principled::Principled;
+
+use crate::texture::Texture;
+use crate::texture::SolidColor;
+use crate::texture::ImageTexture;
+
+use crate::mesh::MeshData;
+use crate::mesh::MeshFace;
+use crate::mesh::MeshError;
+use crate::mesh::TriangleMesh;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+pub fn load_obj(path: &str, default_material: Arc<dyn Material>) -> Result<TriangleMesh, MeshError> {
+    let source = fs::read_to_string(path)?;
+    let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+    let data = parse_obj(&source, path, base_dir, default_material)?;
+    Ok(TriangleMesh::new(data))
+}
+
+// Parses the text of an OBJ file. `file` is only used in error messages, and
+// MTL files are looked up in `base_dir`.
+pub fn parse_obj(source: &str, file: &str, base_dir: &Path, default_material: Arc<dyn Material>) -> Result<MeshData, MeshError> {
+    let mut data = MeshData::new();
+    data.materials.push(default_material);
+
+    let mut library: HashMap<String, Arc<dyn Material>> = HashMap::new();
+    // Index into data.materials of each material used so far
+    let mut used: HashMap<String, usize> = HashMap::new();
+    let mut current_material: usize = 0;
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let error = |message: String| MeshError::Parse { file: file.to_string(), line, message };
+
+        let text = text.split('#').next().unwrap_or("");
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        match keyword {
+            "v" => {
+                let p = parse_floats(&args, 3).map_err(error)?;
+                data.positions.push(Point3(p[0], p[1], p[2]));
+            },
+            "vn" => {
+                let n = parse_floats(&args, 3).map_err(error)?;
+                data.normals.push(Vec3(n[0], n[1], n[2]));
+            },
+            "vt" => {
+                // The optional third coordinate is ignored
+                let uv = parse_floats(&args, 1).map_err(error)?;
+                data.uvs.push((uv[0], if uv.len() > 1 { uv[1] } else { 0.0 }));
+            },
+            "f" => {
+                if args.len() < 3 {
+                    return Err(error(String::from("a face needs at least three corners")));
+                }
+                let mut corners = Vec::with_capacity(args.len());
+                for arg in &args {
+                    corners.push(parse_corner(arg, &data).map_err(error)?);
+                }
+                let has_uvs = corners.iter().all(|c| c.1.is_some());
+                let has_normals = corners.iter().all(|c| c.2.is_some());
+
+                // Polygons are split into a fan of triangles around the first corner
+                for k in 1..corners.len() - 1 {
+                    let [a, b, c] = [corners[0], corners[k], corners[k + 1]];
+                    data.faces.push(MeshFace {
+                        positions: [a.0, b.0, c.0],
+                        uvs: if has_uvs { Some([a.1.unwrap(), b.1.unwrap(), c.1.unwrap()]) } else { None },
+                        normals: if has_normals { Some([a.2.unwrap(), b.2.unwrap(), c.2.unwrap()]) } else { None },
+                        material: current_material,
+                    });
+                }
+            },
+            "mtllib" => {
+                for name in &args {
+                    let path = base_dir.join(name);
+                    match fs::read_to_string(&path) {
+                        Ok(mtl) => {
+                            let mtl_dir = path.parent().unwrap_or(Path::new(""));
+                            parse_mtl(&mtl, &path.to_string_lossy(), mtl_dir, &mut library)?;
+                        },
+                        Err(e) => return Err(error(format!("cannot read material library '{}': {}", path.display(), e))),
+                    }
+                }
+            },
+            "usemtl" => {
+                let name = args.join(" ");
+                current_material = match used.get(&name) {
+                    Some(&index) => index,
+                    None => match library.get(&name) {
+                        Some(material) => {
+                            data.materials.push(material.clone());
+                            used.insert(name, data.materials.len() - 1);
+                            data.materials.len() - 1
+                        },
+                        None => return Err(error(format!("material '{}' is not defined", name))),
+                    },
+                };
+            },
+            // Objects, groups, smoothing groups, lines, points and free-form
+            // geometry are skipped
+            _ => {},
+        }
+    }
+
+    Ok(data)
+}
+
+// Parses one face corner, v, v/vt, v//vn or v/vt/vn, into 0-based indices.
+// Negative indices count back from the last vertex read so far.
+fn parse_corner(corner: &str, data: &MeshData) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = corner.split('/');
+    let position = resolve_index(parts.next().unwrap_or(""), data.positions.len(), "vertex")?;
+    let uv = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(resolve_index(part, data.uvs.len(), "texture coordinate")?),
+    };
+    let normal = match parts.next() {
+        Some("") | None => None,
+        Some(part) => Some(resolve_index(part, data.normals.len(), "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(format!("'{}' is not a face corner", corner));
+    }
+    Ok((position, uv, normal))
+}
+
+fn resolve_index(text: &str, count: usize, what: &str) -> Result<usize, String> {
+    let index: i64 = match text.parse() {
+        Ok(index) => index,
+        Err(_) => return Err(format!("'{}' is not a {} index", text, what)),
+    };
+    let resolved = if index < 0 { count as i64 + index } else { index - 1 };
+    if index == 0 || resolved < 0 || resolved >= count as i64 {
+        return Err(format!("{} index {} is out of range (there are {})", what, index, count));
+    }
+    Ok(resolved as usize)
+}
+
+// Parses all arguments as numbers, of which there must be at least `min_count`.
+fn parse_floats(args: &[&str], min_count: usize) -> Result<Vec<f64>, String> {
+    if args.len() < min_count {
+        return Err(format!("expected at least {} numbers", min_count));
+    }
+    let mut values = Vec::with_capacity(args.len());
+    for arg in args {
+        match arg.parse::<f64>() {
+            Ok(x) if x.is_finite() => values.push(x),
+            _ => return Err(format!("'{}' is not a number", arg)),
+        }
+    }
+    Ok(values)
+}
+
+// The MTL statements the mapping looks at. Defaults are those of the format.
+struct MtlSpec {
+    kd: Color,
+    ks: Color,
+    ke: Color,
+    ni: f64,
+    ns: f64,
+    dissolve: f64,
+    illum: u32,
+    map_kd: Option<Arc<dyn Texture>>,
+    // PBR extension, each a number or a texture map
+    roughness: Option<Arc<dyn Texture>>,
+    metallic: Option<Arc<dyn Texture>>,
+    sheen: Option<Arc<dyn Texture>>,
+    clearcoat: Option<Arc<dyn Texture>>,
+    clearcoat_roughness: Option<Arc<dyn Texture>>,
+}
+
+impl MtlSpec {
+    fn new() -> Self {
+        MtlSpec {
+            kd: Color(0.8, 0.8, 0.8),
+            ks: Color(0.0, 0.0, 0.0),
+            ke: Color(0.0, 0.0, 0.0),
+            ni: 1.0,
+            ns: 0.0,
+            dissolve: 1.0,
+            illum: 2,
+            map_kd: None,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            clearcoat_roughness: None,
+        }
+    }
+
+    fn material(&self) -> Arc<dyn Material> {
+        let max = |c: &Color| c.0.max(c.1).max(c.2);
+
+        if max(&self.ke) > 0.0 {
+            return Arc::new(DiffuseLight::new(self.ke));
+        }
+        if self.is_pbr() {
+            return Arc::new(self.principled());
+        }
+        if self.dissolve < 1.0 || matches!(self.illum, 4 | 6 | 7 | 9) {
+            // An index of 1 would make the surface invisible, so it is taken as unset
+            let ir = if self.ni > 1.0 { self.ni } else { 1.5 };
+            return Arc::new(Dialectric { ir });
+        }
+        if self.map_kd.is_none() && (max(&self.ks) > max(&self.kd) || self.illum == 3) {
+            // Phong exponent to roughness, as in the usual Blinn-Phong to microfacet fit
+            let fuzz = (2.0 / (self.ns + 2.0)).sqrt().min(1.0);
+            return Arc::new(Metal { albedo: self.ks, fuzz });
+        }
+        match &self.map_kd {
+            Some(texture) => Arc::new(Lambertian { albedo: texture.clone() }),
+            None => Arc::new(Lambertian { albedo: Arc::new(SolidColor { color_value: self.kd }) }),
+        }
+    }
+
+    fn is_pbr(&self) -> bool {
+        self.roughness.is_some() || self.metallic.is_some() || self.sheen.is_some()
+            || self.clearcoat.is_some() || self.clearcoat_roughness.is_some()
+    }
+
+    fn principled(&self) -> Principled {
+        let base_color: Arc<dyn Texture> = match &self.map_kd {
+            Some(texture) => texture.clone(),
+            None => Arc::new(SolidColor { color_value: self.kd }),
+        };
+        let mut material = Principled::new(base_color);
+        let parameters = [
+            (&self.roughness, &mut material.roughness),
+            (&self.metallic, &mut material.metallic),
+            (&self.sheen, &mut material.sheen),
+            (&self.clearcoat, &mut material.clearcoat),
+            (&self.clearcoat_roughness, &mut material.clearcoat_roughness),
+        ];
+        for (value, texture) in parameters {
+            if let Some(value) = value {
+                *texture = value.clone();
+            }
+        }
+        let transmission = 1.0 - self.dissolve.clamp(0.0, 1.0);
+        material.transmission = Arc::new(SolidColor::new(transmission, transmission, transmission));
+        // As for Dialectric, an index of 1 is taken as unset
+        let ir = if self.ni > 1.0 { self.ni } else { 1.5 };
+        material.ior = Arc::new(SolidColor::new(ir, ir, ir));
+        material
+    }
+}
+
+// Adds the materials of an MTL file to `library`. Texture maps are looked up in `base_dir`.
+fn parse_mtl(source: &str, file: &str, base_dir: &Path, library: &mut HashMap<String, Arc<dyn Material>>) -> Result<(), MeshError> {
+    let mut current: Option<(String, MtlSpec)> = None;
+
+    for (i, text) in source.lines().enumerate() {
+        let line = i + 1;
+        let error = |message: String| MeshError::Parse { file: file.to_string(), line, message };
+
+        let text = text.split('#').next().unwrap_or("");
+        let mut tokens = text.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = tokens.collect();
+
+        if keyword == "newmtl" {
+            if let Some((name, spec)) = current.take() {
+                library.insert(name, spec.material());
+            }
+            current = Some((args.join(" "), MtlSpec::new()));
+            continue;
+        }
+
+        let spec = match current.as_mut() {
+            Some((_, spec)) => spec,
+            None => return Err(error(format!("'{}' before any newmtl", keyword))),
+        };
+        let color = |args: &[&str]| -> Result<Color, MeshError> {
+            let c = parse_floats(args, 1).map_err(error)?;
+            Ok(if c.len() >= 3 { Color(c[0], c[1], c[2]) } else { Color(c[0], c[0], c[0]) })
+        };
+        let number = |args: &[&str]| -> Result<f64, MeshError> {
+            Ok(parse_floats(args, 1).map_err(error)?[0])
+        };
+        let scalar = |args: &[&str]| -> Result<Option<Arc<dyn Texture>>, MeshError> {
+            let x = number(args)?;
+            Ok(Some(Arc::new(SolidColor::new(x, x, x))))
+        };
+        let map = |args: &[&str]| -> Result<Arc<dyn Texture>, MeshError> {
+            // Options such as -s come first, the file name is last
+            let name = match args.last() {
+                Some(name) => name,
+                None => return Err(error(format!("{} needs a file name", keyword))),
+            };
+            let path = base_dir.join(name);
+            match ImageTexture::open(&path.to_string_lossy()) {
+                Ok(texture) => Ok(Arc::new(texture)),
+                Err(e) => Err(error(format!("cannot load texture '{}': {}", path.display(), e))),
+            }
+        };
+
+        match keyword {
+            "Kd" => spec.kd = color(&args)?,
+            "Ks" => spec.ks = color(&args)?,
+            "Ke" => spec.ke = color(&args)?,
+            "Ni" => spec.ni = number(&args)?,
+            "Ns" => spec.ns = number(&args)?,
+            "d" => spec.dissolve = number(&args)?,
+            "Tr" => spec.dissolve = 1.0 - number(&args)?,
+            "illum" => spec.illum = number(&args)? as u32,
+            "map_Kd" => spec.map_kd = Some(map(&args)?),
+            "Pr" => spec.roughness = scalar(&args)?,
+            "Pm" => spec.metallic = scalar(&args)?,
+            "Ps" => spec.sheen = scalar(&args)?,
+            "Pc" => spec.clearcoat = scalar(&args)?,
+            "Pcr" => spec.clearcoat_roughness = scalar(&args)?,
+            "map_Pr" => spec.roughness = Some(map(&args)?),
+            "map_Pm" => spec.metallic = Some(map(&args)?),
+            // Everything else (Ka, Tf, bump maps, ...) has no counterpart here
+            _ => {},
+        }
+    }
+
+    if let Some((name, spec)) = current.take() {
+        library.insert(name, spec.material());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn grey() -> Arc<dyn Material> {
+        Arc::new(Lambertian::new(&Color(0.5, 0.5, 0.5)))
+    }
+
+    fn parse(source: &str) -> Result<MeshData, MeshError> {
+        parse_obj(source, "test.obj", Path::new(""), grey())
+    }
+
+    fn error_of(source: &str) -> (usize, String) {
+        match parse(source) {
+            Err(MeshError::Parse { line, message, .. }) => (line, message),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("the file was read"),
+        }
+    }
+
+    #[test]
+    fn polygons_become_fans() {
+        let source = "# a quad\n\
+                      v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n\
+                      vt 0 0\nvt 1 0\nvt 1 1\nvt 0 1\n\
+                      vn 0 0 1\n\
+                      f 1/1/1 2/2/1 3/3/1 4/4/1\n";
+        let data = parse(source).unwrap();
+        assert_eq!(data.positions.len(), 4);
+        assert_eq!(data.faces.len(), 2);
+        assert_eq!(data.faces[0].positions, [0, 1, 2]);
+        assert_eq!(data.faces[1].positions, [0, 2, 3]);
+        assert_eq!(data.faces[1].uvs, Some([0, 2, 3]));
+        assert_eq!(data.faces[1].normals, Some([0, 0, 0]));
+        assert_eq!(data.faces[0].material, 0);
+        assert!((data.area(0) + data.area(1) - 1.0).abs() < 1.0e-12);
+    }
+
+    #[test]
+    fn corners_may_leave_out_coordinates_and_count_back() {
+        let source = "v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\n\
+                      f -3//1 -2//1 -1//1\n\
+                      f 1 2 3\n";
+        let data = parse(source).unwrap();
+        assert_eq!(data.faces[0].positions, [0, 1, 2]);
+        assert_eq!(data.faces[0].uvs, None);
+        assert_eq!(data.faces[0].normals, Some([0, 0, 0]));
+        assert_eq!(data.faces[1].normals, None);
+    }
+
+    #[test]
+    fn errors_give_their_line() {
+        assert_eq!(error_of("v 0 0 0\nv 1 0\n"), (2, String::from("expected at least 3 numbers")));
+        assert_eq!(error_of("v 0 0 x"), (1, String::from("'x' is not a number")));
+        assert_eq!(error_of("v 0 0 0\nv 1 0 0\nf 1 2"), (3, String::from("a face needs at least three corners")));
+        assert_eq!(error_of("v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 4"), (4, String::from("vertex index 4 is out of range (there are 3)")));
+        assert_eq!(error_of("v 0 0 0\nf 0 1 1"), (2, String::from("vertex index 0 is out of range (there are 1)")));
+        assert_eq!(error_of("v 0 0 0\nf 1/2 1 1"), (2, String::from("texture coordinate index 2 is out of range (there are 0)")));
+        assert_eq!(error_of("v 0 0 0\nf 1/// 1 1"), (2, String::from("'1///' is not a face corner")));
+    }
+
+    #[test]
+    fn materials_come_from_the_library() {
+        let dir = std::env::temp_dir().join(format!("obj_test_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("test.mtl"), "newmtl lamp\nKe 2 2 2\nnewmtl red\nKd 1 0 0\n").unwrap();
+        let source = "mtllib test.mtl\n\
+                      v 0 0 0\nv 1 0 0\nv 0 1 0\n\
+                      f 1 2 3\n\
+                      usemtl lamp\nf 1 2 3\n\
+                      usemtl red\nf 1 2 3\n\
+                      usemtl lamp\nf 1 2 3\n";
+        let data = parse_obj(source, "test.obj", &dir, grey());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let data = data.unwrap();
+        let materials: Vec<usize> = data.faces.iter().map(|face| face.material).collect();
+        assert_eq!(materials, vec![0, 1, 2, 1]);
+        assert_eq!(data.materials.len(), 3);
+        assert!((data.materials[1].average_emission() - 2.0).abs() < 1.0e-12);
+        assert_eq!(data.materials[2].average_emission(), 0.0);
+    }
+
+    #[test]
+    fn missing_materials_are_errors() {
+        assert_eq!(error_of("v 0 0 0\nusemtl red\n"), (2, String::from("material 'red' is not defined")));
+        let (line, message) = error_of("\nmtllib no_such_file.mtl\n");
+        assert_eq!(line, 2);
+        assert!(message.starts_with("cannot read material library 'no_such_file.mtl': "), "{}", message);
+    }
+}
@@ -4,11 +4,10 @@ use crate::vec3::{Vec3, Point3, random_unit_vector, random_cosine_direction};
 use crate::onb::Onb;
 
 use std::f64::consts::PI;
-use std::cell::RefCell;
-use std::rc::Rc;
+use std::sync::Arc;
 
-pub trait Pdf {
-    fn value(&mut self, direction: &Vec3) -> f64;
+pub trait Pdf: Send + Sync {
+    fn value(&self, direction: &Vec3) -> f64;
 
     fn generate(&self) -> Vec3;
 }
@@ -17,7 +16,7 @@ pub struct SpherePdf();
 
 impl Pdf for SpherePdf {
 
-    fn value(&mut self, _direction: &Vec3) -> f64 {
+    fn value(&self, _direction: &Vec3) -> f64 {
         1.0 / (4.0 * PI)
     }
 
@@ -31,7 +30,7 @@ pub struct CosinePdf {
 }
 
 impl Pdf for CosinePdf {
-    fn value(&mut self, direction: &Vec3) -> f64 {
+    fn value(&self, direction: &Vec3) -> f64 {
         let cosine_theta = Vec3::dot(Vec3::unit_vector(*direction), self.uvw.w());
         (0.0_f64).max(cosine_theta/PI)
     }
@@ -44,38 +43,151 @@ impl Pdf for CosinePdf {
 impl CosinePdf {
     pub fn new(w: &Vec3) -> Self {
         let mut x = Onb(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 0.0));
-        x.build_from_w(&w);
+        x.build_from_w(w);
         CosinePdf {uvw: x}
     }
 }
 
 pub struct HittablePdf {
-    pub objects:  Rc<RefCell<dyn Hittable>>,
+    pub objects:  Arc<dyn Hittable>,
     pub origin: Point3,
 }
 
 impl Pdf for HittablePdf {
-    fn value(&mut self, direction: &Vec3) -> f64 {
-        self.objects.borrow_mut().pdf_value(&self.origin, direction)
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.objects.pdf_value(&self.origin, direction)
     }
 
     fn generate(&self) -> Vec3 {
-        self.objects.borrow().random(&self.origin)
+        self.objects.random(&self.origin)
     }
 }
 
-pub struct MixturePdf(pub Rc<RefCell<dyn Pdf>>, pub Rc<RefCell<dyn Pdf>>);
+pub struct MixturePdf(pub Arc<dyn Pdf>, pub Arc<dyn Pdf>);
 
 impl Pdf for MixturePdf {
-    fn value(&mut self, direction: &Vec3) -> f64 {
-        0.5 * self.0.borrow_mut().value(direction) + 0.5 * self.1.borrow_mut().value(direction)
+    fn value(&self, direction: &Vec3) -> f64 {
+        0.5 * self.0.value(direction) + 0.5 * self.1.value(direction)
     }
 
     fn generate(&self) -> Vec3 {
         if random_double() < 0.5 {
-            return self.0.borrow().generate();
+            self.0.generate()
         } else {
-            return self.1.borrow().generate();
+            self.1.generate()
         }
     }
-}
\ No newline at end of file
+}
+
+// Picks one of several pdfs, each with a chance in proportion to its weight
+pub struct WeightedMixturePdf {
+    // Weights add up to 1
+    pdfs: Vec<(f64, Arc<dyn Pdf>)>,
+}
+
+impl WeightedMixturePdf {
+    // Pdfs without a positive weight are left out
+    pub fn new(pdfs: Vec<(f64, Arc<dyn Pdf>)>) -> Self {
+        let total: f64 = pdfs.iter().map(|(w, _)| w.max(0.0)).sum();
+        WeightedMixturePdf {
+            pdfs: pdfs.into_iter().filter(|(w, _)| *w > 0.0).map(|(w, pdf)| (w / total, pdf)).collect(),
+        }
+    }
+}
+
+impl Pdf for WeightedMixturePdf {
+    fn value(&self, direction: &Vec3) -> f64 {
+        self.pdfs.iter().map(|(w, pdf)| w * pdf.value(direction)).sum()
+    }
+
+    fn generate(&self) -> Vec3 {
+        let mut x = random_double();
+        for (w, pdf) in &self.pdfs {
+            if x < *w {
+                return pdf.generate();
+            }
+            x -= w;
+        }
+        match self.pdfs.last() {
+            Some((_, pdf)) => pdf.generate(),
+            None => random_unit_vector(),
+        }
+    }
+}
+
+// Checks that a density over directions integrates to one and matches the
+// directions drawn from it, for the tests of the modules that sample. The
+// sphere is cut into cells even in cos(theta) and phi, so every cell covers
+// the same solid angle.
+#[cfg(test)]
+pub mod checks {
+    use crate::vec3::Vec3;
+
+    use std::f64::consts::PI;
+
+    // Cells of the histogram along cos(theta); twice as many along phi
+    const BANDS: usize = 8;
+    // Points per cell and axis for the integral of the density over a cell
+    const CELL_POINTS: usize = 24;
+
+    fn direction(z: f64, phi: f64) -> Vec3 {
+        let r = (1.0 - z*z).max(0.0).sqrt();
+        Vec3(r * phi.cos(), r * phi.sin(), z)
+    }
+
+    fn cell_of(v: &Vec3) -> Option<usize> {
+        let length = v.length();
+        if length <= 0.0 || !length.is_finite() {
+            return None;
+        }
+        let z = (v.z() / length).clamp(-1.0, 1.0);
+        let phi = v.y().atan2(v.x()).rem_euclid(2.0*PI);
+        let band = (((z + 1.0) / 2.0 * BANDS as f64) as usize).min(BANDS - 1);
+        let sector = ((phi / (2.0*PI) * (2*BANDS) as f64) as usize).min(2*BANDS - 1);
+        Some(band * 2*BANDS + sector)
+    }
+
+    // Integral of `f` over the cells from band0 to band1 and sector0 to
+    // sector1 of a grid of `n` bands, by the midpoint rule
+    fn integral(f: &impl Fn(&Vec3) -> f64, n: usize, bands: (usize, usize), sectors: (usize, usize)) -> f64 {
+        let dz = 2.0 / n as f64;
+        let dphi = PI / n as f64;
+        let mut sum = 0.0;
+        for i in bands.0..bands.1 {
+            for j in sectors.0..sectors.1 {
+                sum += f(&direction(-1.0 + (i as f64 + 0.5) * dz, (j as f64 + 0.5) * dphi));
+            }
+        }
+        sum * dz * dphi
+    }
+
+    // Integral of `f` over all directions, with `n` bands of cells
+    pub fn sphere_integral(f: impl Fn(&Vec3) -> f64, n: usize) -> f64 {
+        integral(&f, n, (0, n), (0, 2*n))
+    }
+
+    // Half the summed difference between the share of `samples` directions
+    // from `generate` in each cell and the integral of `value` over it: 0
+    // when they agree, 1 when they have nothing in common. Directions that
+    // are zero or not finite count as missing.
+    pub fn histogram_distance(mut generate: impl FnMut() -> Vec3, value: impl Fn(&Vec3) -> f64, samples: usize) -> f64 {
+        let mut counts = vec![0usize; 2*BANDS*BANDS];
+        for _ in 0..samples {
+            if let Some(cell) = cell_of(&generate()) {
+                counts[cell] += 1;
+            }
+        }
+
+        let mut distance = 0.0;
+        for band in 0..BANDS {
+            for sector in 0..2*BANDS {
+                let bands = (band * CELL_POINTS, (band + 1) * CELL_POINTS);
+                let sectors = (sector * CELL_POINTS, (sector + 1) * CELL_POINTS);
+                let expected = integral(&value, BANDS * CELL_POINTS, bands, sectors);
+                let found = counts[band * 2*BANDS + sector] as f64 / samples as f64;
+                distance += (found - expected).abs();
+            }
+        }
+        0.5 * distance
+    }
+}
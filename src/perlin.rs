@@ -1,7 +1,7 @@
 use crate::vec3::Point3;
 use crate::vec3::Vec3;
 
-use rand::Rng;
+use crate::rtweekend::random_int;
 
 pub struct Perlin {
     #[allow(dead_code)]
@@ -13,23 +13,30 @@ pub struct Perlin {
       
 }
 
+impl Default for Perlin {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Perlin {
     pub fn new() -> Self {
         let mut ranvec: [Vec3; 256] = [Vec3(0.0, 0.0, 0.0); 256];
 
-        for i in 0..256 {
-            ranvec[i] = Vec3::unit_vector(Vec3::random_range(-1.0, 1.0));
+        for v in ranvec.iter_mut() {
+            *v = Vec3::unit_vector(Vec3::random_range(-1.0, 1.0));
         }
 
         Perlin {
             point_count: 256,
-            ranvec: ranvec,
+            ranvec,
             perm_x: Perlin::perlin_generate_perm(),
             perm_y: Perlin::perlin_generate_perm(),
             perm_z: Perlin::perlin_generate_perm(),
         }
     }
 
+    #[allow(clippy::needless_range_loop)]
     pub fn noise(&self, p: &Point3) -> f64 {
         let mut u = p.x() - p.x().floor();
         let mut v = p.y() - p.y().floor();
@@ -53,7 +60,7 @@ impl Perlin {
         }
 
 
-        return Perlin::perlin_interp(c, u, v, w);
+        Perlin::perlin_interp(c, u, v, w)
     }
 
     pub fn turb(&self, p: &Point3) -> f64 {
@@ -70,29 +77,26 @@ impl Perlin {
         accum.abs()
     }
 
-    fn permute(p: &mut [u32; 256], n: u32) -> () {
-        let mut rng = rand::thread_rng();
-
+    fn permute(p: &mut [u32; 256], n: u32) {
         for i in (1..n).rev() {
-            let target: u32 = rng.gen_range(0..i);
-            let tmp: u32 = p[i as usize];
-            p[i as usize] = p[target as usize];
-            p[target as usize] = tmp;
+            let target: u32 = random_int(0, i as i64 - 1) as u32;
+            p.swap(i as usize, target as usize);
         }
     }
 
     fn perlin_generate_perm() -> [u32; 256] {
         let mut p : [u32; 256] = [0; 256];
 
-        for i in 0..p.len() {
-            p[i] =  i as u32;
+        for (i, v) in p.iter_mut().enumerate() {
+            *v = i as u32;
         }
 
         Perlin::permute(&mut p, 256);
   
-        return p;
+        p
     }
 
+    #[allow(clippy::needless_range_loop)]
     fn perlin_interp(c: [[[Vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
         let uu = u*u*(3.0-(2.0*u));
         let vv = v*v*(3.0-(2.0*v));
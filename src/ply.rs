@@ -0,0 +1,534 @@
+// Stanford PLY import, in the ascii, binary_little_endian and
+// binary_big_endian formats.
+//
+// The `vertex` element gives the positions (x, y, z), and optionally normals
+// (nx, ny, nz), texture coordinates (u, v or s, t) and colours (red, green,
+// blue, as 0-255 for integer properties and 0-1 for float ones). The `face`
+// element lists the corners of each polygon in `vertex_indices`; polygons are
+// split into triangle fans. Other elements and properties are skipped.
+//
+// Vertex colours are handed back as a VertexColorTexture. The texture needs
+// coordinates of its own, so the faces only get them when the mesh is
+// coloured by it; with a material from the caller they keep the file's u, v.
+
+use crate::vec3::Vec3;
+use crate::vec3::Color;
+
+use crate::material::Material;
+use crate::material::Lambertian;
+
+use crate::texture::VertexColorTexture;
+
+use crate::mesh::MeshData;
+use crate::mesh::MeshFace;
+use crate::mesh::MeshError;
+use crate::mesh::TriangleMesh;
+
+use std::fs;
+use std::sync::Arc;
+
+// Loads a PLY file as a mesh with a single material. Without one, the mesh
+// is a Lambertian with the vertex colours, or a grey one if there are none.
+pub fn load_ply(path: &str, material: Option<Arc<dyn Material>>) -> Result<TriangleMesh, MeshError> {
+    let bytes = fs::read(path)?;
+    let (mut data, colors) = parse_ply(&bytes, path)?;
+    let material: Arc<dyn Material> = match (material, colors) {
+        (Some(material), _) => material,
+        (None, Some(colors)) => {
+            use_vertex_colors(&mut data);
+            Arc::new(Lambertian { albedo: Arc::new(colors) })
+        },
+        (None, None) => Arc::new(Lambertian::new(&Color(0.8, 0.8, 0.8))),
+    };
+    data.materials.push(material);
+    Ok(TriangleMesh::new(data))
+}
+
+// Parses the contents of a PLY file. `file` is only used in error messages.
+// All faces refer to material 0, which is left for the caller to add. The
+// faces keep the u, v of the file even when there are colours, see
+// use_vertex_colors.
+pub fn parse_ply(bytes: &[u8], file: &str) -> Result<(MeshData, Option<VertexColorTexture>), MeshError> {
+    let error = |line: usize, message: String| MeshError::Parse { file: file.to_string(), line, message };
+
+    let (header, body_start, body_line) = parse_header(bytes).map_err(|(line, message)| error(line, message))?;
+    let mut body = Body {
+        bytes,
+        pos: body_start,
+        format: header.format,
+        line: body_line,
+    };
+    // Binary data has no lines to point at
+    let body_error = |body: &Body, message: String| error(if body.format == Format::Ascii { body.line } else { 0 }, message);
+
+    let vertex_count = match header.elements.iter().find(|e| e.name == "vertex") {
+        Some(element) => element.count,
+        None => return Err(error(0, String::from("there is no vertex element"))),
+    };
+
+    let mut data = MeshData::new();
+    let mut colors: Vec<Color> = Vec::new();
+    let mut corner_colors: Vec<[Color; 3]> = Vec::new();
+
+    for element in &header.elements {
+        let find = |names: &[&str]| element.properties.iter().position(|p| !p.is_list && names.contains(&p.name.as_str()));
+        let position = [find(&["x"]), find(&["y"]), find(&["z"])];
+        let normal = [find(&["nx"]), find(&["ny"]), find(&["nz"])];
+        let uv = [find(&["u", "s", "texture_u", "texture_s"]), find(&["v", "t", "texture_v", "texture_t"])];
+        let color = [find(&["red", "diffuse_red"]), find(&["green", "diffuse_green"]), find(&["blue", "diffuse_blue"])];
+        let indices = element.properties.iter().position(|p| p.is_list && (p.name == "vertex_indices" || p.name == "vertex_index"));
+
+        if element.name == "vertex" && position.iter().any(|p| p.is_none()) {
+            return Err(error(0, String::from("vertices need x, y and z")));
+        }
+        if element.name == "face" && indices.is_none() {
+            return Err(error(0, String::from("faces need vertex_indices")));
+        }
+
+        let mut scalars = vec![0.0; element.properties.len()];
+        let mut list: Vec<f64> = Vec::new();
+        for _ in 0..element.count {
+            for (k, property) in element.properties.iter().enumerate() {
+                if !property.is_list {
+                    scalars[k] = body.read(property.value_type).map_err(|message| body_error(&body, message))?;
+                    continue;
+                }
+                let n = body.read(property.count_type).map_err(|message| body_error(&body, message))?;
+                if n < 0.0 || n.fract() != 0.0 {
+                    return Err(body_error(&body, format!("'{}' is not a list length", n)));
+                }
+                if Some(k) == indices {
+                    list.clear();
+                }
+                for _ in 0..n as usize {
+                    let item = body.read(property.value_type).map_err(|message| body_error(&body, message))?;
+                    if Some(k) == indices {
+                        list.push(item);
+                    }
+                }
+            }
+
+            match element.name.as_str() {
+                "vertex" => {
+                    let at = |i: [Option<usize>; 3]| Vec3(scalars[i[0].unwrap()], scalars[i[1].unwrap()], scalars[i[2].unwrap()]);
+                    data.positions.push(at(position));
+                    if normal.iter().all(|i| i.is_some()) {
+                        data.normals.push(at(normal));
+                    }
+                    if let [Some(u), Some(v)] = uv {
+                        data.uvs.push((scalars[u], scalars[v]));
+                    }
+                    if color.iter().all(|i| i.is_some()) {
+                        let scale = |i: Option<usize>| scalars[i.unwrap()] / element.properties[i.unwrap()].value_type.full_scale();
+                        colors.push(Color(scale(color[0]), scale(color[1]), scale(color[2])));
+                    }
+                },
+                "face" => {
+                    if list.len() < 3 {
+                        return Err(body_error(&body, String::from("a face needs at least three corners")));
+                    }
+                    let mut corners = Vec::with_capacity(list.len());
+                    for &index in &list {
+                        if index < 0.0 || index.fract() != 0.0 || index >= vertex_count as f64 {
+                            return Err(body_error(&body, format!("vertex index {} is out of range (there are {})", index, vertex_count)));
+                        }
+                        corners.push(index as usize);
+                    }
+                    for k in 1..corners.len() - 1 {
+                        let face = [corners[0], corners[k], corners[k + 1]];
+                        data.faces.push(MeshFace {
+                            positions: face,
+                            normals: None,
+                            uvs: None,
+                            material: 0,
+                        });
+                    }
+                },
+                _ => {},
+            }
+        }
+    }
+
+    if data.positions.iter().any(|p| !(p.x().is_finite() && p.y().is_finite() && p.z().is_finite())) {
+        return Err(error(0, String::from("a vertex position is not a finite number")));
+    }
+
+    // Normals and coordinates are per vertex, so they share the position indices
+    let has_normals = data.normals.len() == data.positions.len();
+    let has_uvs = data.uvs.len() == data.positions.len();
+    let has_colors = !colors.is_empty() && colors.len() == data.positions.len();
+    for face in data.faces.iter_mut() {
+        if has_normals {
+            face.normals = Some(face.positions);
+        }
+        if has_uvs {
+            face.uvs = Some(face.positions);
+        }
+        if has_colors {
+            let [a, b, c] = face.positions;
+            corner_colors.push([colors[a], colors[b], colors[c]]);
+        }
+    }
+
+    let texture = if has_colors { Some(VertexColorTexture { corner_colors }) } else { None };
+    Ok((data, texture))
+}
+
+// Replaces the texture coordinates of the faces by the ones the
+// VertexColorTexture of parse_ply reads its colours from
+fn use_vertex_colors(data: &mut MeshData) {
+    data.uvs.clear();
+    for (i, face) in data.faces.iter_mut().enumerate() {
+        data.uvs.extend_from_slice(&VertexColorTexture::face_uvs(i));
+        face.uvs = Some([3*i, 3*i + 1, 3*i + 2]);
+    }
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Format {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Option<Scalar> {
+        match name {
+            "char" | "int8" => Some(Scalar::I8),
+            "uchar" | "uint8" => Some(Scalar::U8),
+            "short" | "int16" => Some(Scalar::I16),
+            "ushort" | "uint16" => Some(Scalar::U16),
+            "int" | "int32" => Some(Scalar::I32),
+            "uint" | "uint32" => Some(Scalar::U32),
+            "float" | "float32" => Some(Scalar::F32),
+            "double" | "float64" => Some(Scalar::F64),
+            _ => None,
+        }
+    }
+
+    fn size(&self) -> usize {
+        match self {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        }
+    }
+
+    // The value that stands for full intensity in a colour property
+    fn full_scale(&self) -> f64 {
+        match self {
+            Scalar::I8 => i8::MAX as f64,
+            Scalar::U8 => u8::MAX as f64,
+            Scalar::I16 => i16::MAX as f64,
+            Scalar::U16 => u16::MAX as f64,
+            Scalar::I32 => i32::MAX as f64,
+            Scalar::U32 => u32::MAX as f64,
+            Scalar::F32 | Scalar::F64 => 1.0,
+        }
+    }
+}
+
+struct Property {
+    name: String,
+    is_list: bool,
+    // Type of the list length; unused for scalar properties
+    count_type: Scalar,
+    value_type: Scalar,
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+struct Header {
+    format: Format,
+    elements: Vec<Element>,
+}
+
+// Reads the header. Returns it with the offset and line number where the body
+// starts, or the line and message of an error.
+fn parse_header(bytes: &[u8]) -> Result<(Header, usize, usize), (usize, String)> {
+    let mut header = Header { format: Format::Ascii, elements: Vec::new() };
+    let mut format_seen = false;
+    let mut pos = 0;
+    let mut line = 0;
+
+    loop {
+        let end = match bytes[pos..].iter().position(|&b| b == b'\n') {
+            Some(n) => pos + n,
+            None => return Err((line, String::from("the header has no end_header"))),
+        };
+        line += 1;
+        let text = String::from_utf8_lossy(&bytes[pos..end]).into_owned();
+        pos = end + 1;
+
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        if line == 1 {
+            if tokens != ["ply"] {
+                return Err((line, String::from("not a PLY file")));
+            }
+            continue;
+        }
+
+        match tokens.first().copied() {
+            Some("format") => {
+                header.format = match tokens.get(1).copied() {
+                    Some("ascii") => Format::Ascii,
+                    Some("binary_little_endian") => Format::BinaryLittleEndian,
+                    Some("binary_big_endian") => Format::BinaryBigEndian,
+                    _ => return Err((line, format!("unknown format '{}'", tokens[1..].join(" ")))),
+                };
+                format_seen = true;
+            },
+            Some("element") => {
+                let count = match (tokens.get(1), tokens.get(2).and_then(|n| n.parse::<usize>().ok())) {
+                    (Some(_), Some(count)) => count,
+                    _ => return Err((line, String::from("expected 'element NAME COUNT'"))),
+                };
+                header.elements.push(Element { name: tokens[1].to_string(), count, properties: Vec::new() });
+            },
+            Some("property") => {
+                let element = match header.elements.last_mut() {
+                    Some(element) => element,
+                    None => return Err((line, String::from("property before any element"))),
+                };
+                let property = match tokens[1..] {
+                    ["list", count_type, value_type, name] => match (Scalar::parse(count_type), Scalar::parse(value_type)) {
+                        (Some(count_type), Some(value_type)) => Property { name: name.to_string(), is_list: true, count_type, value_type },
+                        _ => return Err((line, format!("unknown type in '{}'", text.trim()))),
+                    },
+                    [value_type, name] => match Scalar::parse(value_type) {
+                        Some(value_type) => Property { name: name.to_string(), is_list: false, count_type: value_type, value_type },
+                        None => return Err((line, format!("unknown type '{}'", value_type))),
+                    },
+                    _ => return Err((line, String::from("expected 'property TYPE NAME' or 'property list COUNT_TYPE TYPE NAME'"))),
+                };
+                element.properties.push(property);
+            },
+            Some("end_header") => break,
+            Some("comment") | Some("obj_info") | None => {},
+            Some(keyword) => return Err((line, format!("unknown header line '{}'", keyword))),
+        }
+    }
+
+    if !format_seen {
+        return Err((line, String::from("the header has no format line")));
+    }
+    Ok((header, pos, line + 1))
+}
+
+// Reads the values of the body one at a time
+struct Body<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    format: Format,
+    // Line of the next value, for ascii files
+    line: usize,
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, value_type: Scalar) -> Result<f64, String> {
+        match self.format {
+            Format::Ascii => self.read_ascii(),
+            _ => self.read_binary(value_type),
+        }
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, String> {
+        while self.pos < self.bytes.len() && self.bytes[self.pos].is_ascii_whitespace() {
+            if self.bytes[self.pos] == b'\n' {
+                self.line += 1;
+            }
+            self.pos += 1;
+        }
+        let start = self.pos;
+        while self.pos < self.bytes.len() && !self.bytes[self.pos].is_ascii_whitespace() {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return Err(String::from("unexpected end of file"));
+        }
+
+        let token = String::from_utf8_lossy(&self.bytes[start..self.pos]);
+        match token.parse::<f64>() {
+            Ok(x) => Ok(x),
+            Err(_) => Err(format!("'{}' is not a number", token)),
+        }
+    }
+
+    fn read_binary(&mut self, value_type: Scalar) -> Result<f64, String> {
+        let size = value_type.size();
+        if self.pos + size > self.bytes.len() {
+            return Err(String::from("unexpected end of file"));
+        }
+
+        // Bytes are put in little endian order, whatever the file uses
+        let mut raw = [0u8; 8];
+        raw[..size].copy_from_slice(&self.bytes[self.pos..self.pos + size]);
+        if self.format == Format::BinaryBigEndian {
+            raw[..size].reverse();
+        }
+        self.pos += size;
+
+        let x = match value_type {
+            Scalar::I8 => raw[0] as i8 as f64,
+            Scalar::U8 => raw[0] as f64,
+            Scalar::I16 => i16::from_le_bytes([raw[0], raw[1]]) as f64,
+            Scalar::U16 => u16::from_le_bytes([raw[0], raw[1]]) as f64,
+            Scalar::I32 => i32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            Scalar::U32 => u32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            Scalar::F32 => f32::from_le_bytes([raw[0], raw[1], raw[2], raw[3]]) as f64,
+            Scalar::F64 => f64::from_le_bytes(raw),
+        };
+        Ok(x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::hittable::Hittable;
+    use crate::hittable::HitRecord;
+    use crate::material::DefaultMaterial;
+    use crate::ray::Ray;
+    use crate::texture::Texture;
+    use crate::vec3::Point3;
+
+    // A unit square in z = 0 with texture coordinates and a colour per corner
+    const COLORED_SQUARE: &str = "ply\n\
+                                  format ascii 1.0\n\
+                                  comment a square\n\
+                                  element vertex 4\n\
+                                  property float x\nproperty float y\nproperty float z\n\
+                                  property float u\nproperty float v\n\
+                                  property uchar red\nproperty uchar green\nproperty uchar blue\n\
+                                  element face 1\n\
+                                  property list uchar int vertex_indices\n\
+                                  end_header\n\
+                                  0 0 0 0.0 0.0 255 0 0\n\
+                                  1 0 0 0.5 0.0 0 255 0\n\
+                                  1 1 0 0.5 0.5 0 0 255\n\
+                                  0 1 0 0.0 0.5 255 255 255\n\
+                                  4 0 1 2 3\n";
+
+    fn error_of(bytes: &[u8]) -> (usize, String) {
+        match parse_ply(bytes, "test.ply") {
+            Err(MeshError::Parse { line, message, .. }) => (line, message),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("the file was read"),
+        }
+    }
+
+    // Where a ray straight down onto z = 0 at (x, y) hits the mesh
+    fn hit_at(mesh: &dyn Hittable, x: f64, y: f64) -> HitRecord {
+        let r = Ray { origin: Point3(x, y, 1.0), direction: Vec3(0.0, 0.0, -1.0), tm: 0.0, wavelength: 0.0 };
+        let mut rec = HitRecord {
+            p: Point3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 0.0, 0.0),
+            mat_ptr: Arc::new(DefaultMaterial),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+        };
+        assert!(mesh.hit(&r, 0.001, f64::INFINITY, &mut rec));
+        rec
+    }
+
+    fn load(source: &str, material: Option<Arc<dyn Material>>) -> TriangleMesh {
+        let path = std::env::temp_dir().join(format!("ply_test_{}_{}.ply", std::process::id(), material.is_some()));
+        fs::write(&path, source).unwrap();
+        let mesh = load_ply(&path.to_string_lossy(), material);
+        fs::remove_file(&path).unwrap();
+        mesh.unwrap()
+    }
+
+    #[test]
+    fn colors_keep_the_texture_coordinates() {
+        let (data, colors) = parse_ply(COLORED_SQUARE.as_bytes(), "test.ply").unwrap();
+        assert_eq!(data.faces.len(), 2);
+        assert_eq!(data.uvs, vec![(0.0, 0.0), (0.5, 0.0), (0.5, 0.5), (0.0, 0.5)]);
+        assert_eq!(data.faces[1].uvs, Some([0, 2, 3]));
+
+        let colors = colors.unwrap();
+        assert_eq!(colors.corner_colors.len(), 2);
+        assert!((colors.corner_colors[1][2] - Color(1.0, 1.0, 1.0)).near_zero());
+    }
+
+    #[test]
+    fn an_override_material_sees_the_files_coordinates() {
+        let mesh = load(COLORED_SQUARE, Some(Arc::new(DefaultMaterial)));
+        let rec = hit_at(&mesh, 0.5, 0.25);
+        assert!((rec.u - 0.25).abs() < 1.0e-9 && (rec.v - 0.125).abs() < 1.0e-9, "u {} v {}", rec.u, rec.v);
+    }
+
+    #[test]
+    fn without_a_material_the_mesh_has_the_vertex_colors() {
+        let mesh = load(COLORED_SQUARE, None);
+        let colors = VertexColorTexture {
+            corner_colors: parse_ply(COLORED_SQUARE.as_bytes(), "test.ply").unwrap().1.unwrap().corner_colors,
+        };
+        // Near the red corner, and halfway along the edge from green to blue
+        let rec = hit_at(&mesh, 0.01, 0.001);
+        let c = colors.value(rec.u, rec.v, &rec.p);
+        assert!(c.x() > 0.98 && c.y() < 0.02 && c.z() < 0.02);
+        let rec = hit_at(&mesh, 1.0 - 1.0e-9, 0.5);
+        let c = colors.value(rec.u, rec.v, &rec.p);
+        assert!(c.x() < 1.0e-6 && (c.y() - 0.5).abs() < 1.0e-6 && (c.z() - 0.5).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn reads_binary_files() {
+        let mut bytes = b"ply\n\
+                          format binary_big_endian 1.0\n\
+                          element vertex 3\n\
+                          property double x\nproperty double y\nproperty double z\n\
+                          element face 1\n\
+                          property list uchar uint vertex_index\n\
+                          end_header\n".to_vec();
+        for p in [[0.0f64, 0.0, 0.0], [2.0, 0.0, 0.0], [0.0, 2.0, 0.0]] {
+            for x in p {
+                bytes.extend_from_slice(&x.to_be_bytes());
+            }
+        }
+        bytes.push(3);
+        for i in [0u32, 1, 2] {
+            bytes.extend_from_slice(&i.to_be_bytes());
+        }
+        let (data, colors) = parse_ply(&bytes, "test.ply").unwrap();
+        assert!(colors.is_none());
+        assert!((data.positions[1] - Point3(2.0, 0.0, 0.0)).near_zero());
+        assert_eq!(data.faces[0].positions, [0, 1, 2]);
+        assert_eq!(data.faces[0].uvs, None);
+        assert!((data.area(0) - 2.0).abs() < 1.0e-12);
+
+        bytes.truncate(bytes.len() - 2);
+        assert_eq!(error_of(&bytes).0, 0);
+    }
+
+    #[test]
+    fn errors_give_their_line() {
+        assert_eq!(error_of(b"obj\n"), (1, String::from("not a PLY file")));
+        assert_eq!(error_of(b"ply\nformat ascii 1.0\n"), (2, String::from("the header has no end_header")));
+        assert_eq!(error_of(b"ply\nformat ascii 1.0\nelement face 0\nproperty list uchar int vertex_indices\nend_header\n"),
+                   (0, String::from("there is no vertex element")));
+        let source = COLORED_SQUARE.replace("4 0 1 2 3", "3 0 1 7");
+        assert_eq!(error_of(source.as_bytes()), (20, String::from("vertex index 7 is out of range (there are 4)")));
+    }
+}
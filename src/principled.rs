@@ -0,0 +1,273 @@
+// A principled material after Burley, "Physically-Based Shading at Disney"
+// (SIGGRAPH 2012), with transmission like in its 2015 extension.
+//
+// One material covers plastics, metals, glass, varnished wood and cloth
+// through a handful of parameters, most of them from 0 to 1. Each of them is
+// a texture, so it can vary over the surface:
+//
+//   base_color            the diffuse colour, the colour of a metal and of the glass
+//   metallic              from a dielectric (0) to a metal (1)
+//   roughness             of the specular reflection and the glass, from a mirror (0) to 1
+//   specular              reflectance of a dielectric, 0.5 for the usual 4%
+//   specular_tint         tints the reflection of a dielectric towards the base colour
+//   sheen, sheen_tint     extra reflection at grazing angles, for cloth
+//   clearcoat             a colourless glossy layer on top, like varnish
+//   clearcoat_roughness
+//   transmission          from opaque (0) to glass (1)
+//   ior                   index of refraction of the glass
+//
+// Textures for single numbers are read as the mean of their channels.
+//
+// The material blends an opaque part, Burley's diffuse with sheen and a GGX
+// specular lobe, with rough glass in proportion to transmission times
+// (1 - metallic), and adds the clear coat on top. Directions are drawn from
+// each lobe with a chance in proportion to a rough guess of its weight.
+// Inside a transmissive object only the glass is seen, and the glass is
+// tinted by the base colour on the way in only.
+
+use crate::vec3::Vec3;
+use crate::vec3::Color;
+
+use crate::color::luminance;
+use crate::material::Material;
+use crate::material::ScatterRecord;
+use crate::microfacet::Ggx;
+use crate::microfacet::ShadingFrame;
+use crate::microfacet::MicrofacetReflectionPdf;
+use crate::microfacet::MicrofacetDielectricPdf;
+use crate::microfacet::reflection;
+use crate::microfacet::dielectric_scattering;
+use crate::pdf::Pdf;
+use crate::pdf::CosinePdf;
+use crate::pdf::WeightedMixturePdf;
+
+use crate::ray::Ray;
+use crate::hittable::HitRecord;
+
+use crate::texture::Texture;
+use crate::texture::SolidColor;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    pub specular: Arc<dyn Texture>,
+    pub specular_tint: Arc<dyn Texture>,
+    pub sheen: Arc<dyn Texture>,
+    pub sheen_tint: Arc<dyn Texture>,
+    pub clearcoat: Arc<dyn Texture>,
+    pub clearcoat_roughness: Arc<dyn Texture>,
+    pub transmission: Arc<dyn Texture>,
+    pub ior: Arc<dyn Texture>,
+}
+
+impl Principled {
+    // A rough plastic of `base_color`. The other parameters are those of
+    // Disney's model: metallic 0, roughness 0.5, specular 0.5, specular_tint
+    // 0, sheen 0, sheen_tint 0.5, clearcoat 0, clearcoat_roughness 0.03,
+    // transmission 0 and ior 1.5.
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        Principled {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            specular_tint: constant(0.0),
+            sheen: constant(0.0),
+            sheen_tint: constant(0.5),
+            clearcoat: constant(0.0),
+            clearcoat_roughness: constant(0.03),
+            transmission: constant(0.0),
+            ior: constant(1.5),
+        }
+    }
+
+    // Reads the textures where the ray hit, and sets up the lobes. None when
+    // the ray comes from behind the shading normal.
+    fn lobes(&self, r_in: &Ray, rec: &HitRecord) -> Option<Lobes> {
+        let frame = ShadingFrame::new(&rec.normal, &Vec3(1.0, 0.0, 0.0));
+        let wo = frame.to_local(&-Vec3::unit_vector(r_in.direction()));
+        if wo.z() <= 0.0 {
+            return None;
+        }
+
+        let base_color = self.base_color.value(rec.u, rec.v, &rec.p);
+        let metallic = scalar(&self.metallic, rec).clamp(0.0, 1.0);
+        let roughness = scalar(&self.roughness, rec).clamp(0.0, 1.0);
+        let transmission = scalar(&self.transmission, rec).clamp(0.0, 1.0);
+        let ior = scalar(&self.ior, rec).max(1.0);
+
+        let glass = transmission * (1.0 - metallic);
+        // Only the far side of a transmissive surface is ever hit from
+        // behind, from inside the object. Other surfaces are two-sided.
+        let inside = !rec.front_face && glass > 0.0;
+
+        let tint = if luminance(base_color) > 0.0 { base_color / luminance(base_color) } else { Color(1.0, 1.0, 1.0) };
+        let specular_tint = scalar(&self.specular_tint, rec).clamp(0.0, 1.0);
+        let dielectric_specular = 0.08 * scalar(&self.specular, rec).max(0.0) * lerp(&Color(1.0, 1.0, 1.0), &tint, specular_tint);
+        let sheen_tint = scalar(&self.sheen_tint, rec).clamp(0.0, 1.0);
+        let clearcoat_roughness = scalar(&self.clearcoat_roughness, rec);
+
+        Some(Lobes {
+            frame,
+            normal: rec.normal,
+            wo,
+            base_color,
+            metallic,
+            roughness,
+            specular_color: lerp(&dielectric_specular, &base_color, metallic),
+            sheen_color: scalar(&self.sheen, rec).max(0.0) * lerp(&Color(1.0, 1.0, 1.0), &tint, sheen_tint),
+            clearcoat: if inside { 0.0 } else { scalar(&self.clearcoat, rec).max(0.0) },
+            glass: if inside { 1.0 } else { glass },
+            inside,
+            eta: if rec.front_face { ior } else { 1.0 / ior },
+            specular: Ggx::from_roughness(roughness, roughness),
+            clearcoat_distribution: Ggx::from_roughness(clearcoat_roughness, clearcoat_roughness),
+        })
+    }
+}
+
+impl Material for Principled {
+    fn scatter(&self, r_in: &Ray, rec: &HitRecord, srec: &mut ScatterRecord) -> bool {
+        let lobes = match self.lobes(r_in, rec) {
+            Some(lobes) => lobes,
+            None => return false,
+        };
+        srec.attenuation = Color(1.0, 1.0, 1.0);
+        srec.pdf_ptr = Arc::new(lobes.pdf());
+        srec.skip_pdf = false;
+        true
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray, rec: &HitRecord, scattered: &Ray) -> f64 {
+        match self.lobes(r_in, rec) {
+            Some(lobes) => lobes.pdf().value(&scattered.direction()),
+            None => 0.0,
+        }
+    }
+
+    fn scattering_color(&self, r_in: &Ray, rec: &HitRecord, _srec: &ScatterRecord, scattered: &Ray) -> Color {
+        match self.lobes(r_in, rec) {
+            Some(lobes) => lobes.scattering(&scattered.direction()),
+            None => Color(0.0, 0.0, 0.0),
+        }
+    }
+
+    fn diffuse_albedo(&self, rec: &HitRecord) -> Color {
+        let metallic = scalar(&self.metallic, rec).clamp(0.0, 1.0);
+        let transmission = scalar(&self.transmission, rec).clamp(0.0, 1.0);
+        let glass = transmission * (1.0 - metallic);
+        (1.0 - glass) * (1.0 - metallic) * self.base_color.value(rec.u, rec.v, &rec.p)
+    }
+}
+
+// The material at one hit, with the textures read
+struct Lobes {
+    frame: ShadingFrame,
+    normal: Vec3,
+    wo: Vec3,
+    base_color: Color,
+    metallic: f64,
+    roughness: f64,
+    // Reflectance at normal incidence of the specular lobe
+    specular_color: Color,
+    sheen_color: Color,
+    clearcoat: f64,
+    // Weight of the glass against the opaque part
+    glass: f64,
+    inside: bool,
+    eta: f64,
+    specular: Ggx,
+    clearcoat_distribution: Ggx,
+}
+
+impl Lobes {
+    // What the material scatters from `direction` towards wo, times the
+    // cosine of `direction`
+    fn scattering(&self, direction: &Vec3) -> Color {
+        let wo = self.wo;
+        let wi = self.frame.to_local(&Vec3::unit_vector(*direction));
+        let mut color = Color(0.0, 0.0, 0.0);
+
+        let opaque = 1.0 - self.glass;
+        if wi.z() > 0.0 && opaque > 0.0 {
+            let cos_d = Vec3::dot(wi, Vec3::unit_vector(wo + wi));
+
+            // Burley's diffuse, which brightens rough surfaces at grazing
+            // angles and darkens smooth ones
+            let fd90 = 0.5 + 2.0 * self.roughness * cos_d * cos_d;
+            let fd = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z())) * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z()));
+            let sheen = schlick_weight(cos_d) * self.sheen_color;
+            color += opaque * (1.0 - self.metallic) * wi.z() * (fd / PI * self.base_color + sheen);
+
+            if let Some((m, value)) = reflection(&self.specular, &wo, &wi) {
+                let fresnel = lerp(&self.specular_color, &Color(1.0, 1.0, 1.0), schlick_weight(Vec3::dot(wo, m)));
+                color += opaque * value * fresnel;
+            }
+        }
+
+        if wi.z() > 0.0 && self.clearcoat > 0.0 {
+            if let Some((m, value)) = reflection(&self.clearcoat_distribution, &wo, &wi) {
+                let fresnel = 0.04 + 0.96 * schlick_weight(Vec3::dot(wo, m));
+                color += 0.25 * self.clearcoat * fresnel * value * Color(1.0, 1.0, 1.0);
+            }
+        }
+
+        if self.glass > 0.0 {
+            let value = dielectric_scattering(&self.specular, self.eta, &wo, &wi);
+            let tint = if wi.z() < 0.0 && !self.inside { self.base_color } else { Color(1.0, 1.0, 1.0) };
+            color += self.glass * value * tint;
+        }
+        color
+    }
+
+    fn pdf(&self) -> WeightedMixturePdf {
+        let mut pdfs: Vec<(f64, Arc<dyn Pdf>)> = Vec::new();
+        if !self.inside {
+            let opaque = 1.0 - self.glass;
+            // The specular lobe gets a fair share even on dielectrics, whose
+            // reflectance is small but concentrated in the highlights
+            pdfs.push((opaque * (1.0 - self.metallic), Arc::new(CosinePdf::new(&self.normal))));
+            pdfs.push((opaque * luminance(self.specular_color).max(0.25), Arc::new(MicrofacetReflectionPdf {
+                frame: self.frame,
+                wo: self.wo,
+                distribution: self.specular,
+            })));
+            pdfs.push((0.25 * self.clearcoat, Arc::new(MicrofacetReflectionPdf {
+                frame: self.frame,
+                wo: self.wo,
+                distribution: self.clearcoat_distribution,
+            })));
+        }
+        pdfs.push((self.glass, Arc::new(MicrofacetDielectricPdf {
+            frame: self.frame,
+            wo: self.wo,
+            distribution: self.specular,
+            eta: self.eta,
+        })));
+        WeightedMixturePdf::new(pdfs)
+    }
+}
+
+fn constant(value: f64) -> Arc<dyn Texture> {
+    Arc::new(SolidColor::new(value, value, value))
+}
+
+// A number from a texture, the mean of its channels
+fn scalar(texture: &Arc<dyn Texture>, rec: &HitRecord) -> f64 {
+    let c = texture.value(rec.u, rec.v, &rec.p);
+    (c.x() + c.y() + c.z()) / 3.0
+}
+
+// (1 - cos)^5, how much Schlick's approximation moves the reflectance
+// towards 1 at grazing angles
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1.0 - cos_theta.clamp(0.0, 1.0)).powi(5)
+}
+
+fn lerp(a: &Color, b: &Color, t: f64) -> Color {
+    (1.0 - t) * *a + t * *b
+}
@@ -6,6 +6,10 @@ pub struct Ray {
     pub origin: Point3,
     pub direction: Vec3,
     pub tm: f64, 
+    // Wavelength in nanometres of the light the ray carries, or 0 for light
+    // of all wavelengths, as RGB. Set by dispersive materials and kept for
+    // the rest of the path.
+    pub wavelength: f64,
 }
 
 impl Ray {
@@ -24,4 +28,8 @@ impl Ray {
     pub fn time(self) -> f64 {
         self.tm
     }
+
+    pub fn wavelength(self) -> f64 {
+        self.wavelength
+    }
 }
\ No newline at end of file
@@ -0,0 +1,236 @@
+use crate::vec3::Color;
+
+use crate::camera::Camera;
+
+use crate::ray::Ray;
+
+use crate::film::Film;
+
+use crate::integrator::Integrator;
+
+use crate::rtweekend::random_double;
+use crate::rtweekend::seed_random;
+
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use std::thread;
+use std::time::Duration;
+use std::time::Instant;
+
+// Pixels are spread so that the first and the last of a row are at the
+// edges of the view, as in the book, which takes two of them each way
+pub const MIN_IMAGE_SIZE: u64 = 2;
+
+// Shortest time between two updates of the progress line
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Copy, Clone)]
+pub struct RenderSettings {
+    pub image_width: u64,
+    pub image_height: u64,
+    pub samples_per_pixel: u64,
+    pub max_depth: u64,
+    // Width and height of the square tiles handed out to the worker threads
+    pub tile_size: u64,
+    // Number of worker threads. 0 uses every available core.
+    pub threads: usize,
+    // With a seed every tile starts its random numbers from a value derived
+    // from it, so the image does not depend on how tiles are spread over threads.
+    pub seed: Option<u64>,
+}
+
+impl RenderSettings {
+    pub fn new(image_width: u64, image_height: u64, samples_per_pixel: u64, max_depth: u64) -> Self {
+        RenderSettings {
+            image_width,
+            image_height,
+            samples_per_pixel,
+            max_depth,
+            tile_size: 32,
+            threads: 0,
+            seed: None,
+        }
+    }
+}
+
+// Rectangle of pixels [x0, x1) x [y0, y1). Row 0 is the top of the image.
+#[derive(Copy, Clone)]
+pub struct Tile {
+    pub x0: u64,
+    pub y0: u64,
+    pub x1: u64,
+    pub y1: u64,
+}
+
+pub struct Renderer {
+    pub integrator: Arc<dyn Integrator>,
+    pub camera: Camera,
+    pub background: Color,
+    pub settings: RenderSettings,
+}
+
+impl Renderer {
+    pub fn new(integrator: Arc<dyn Integrator>, camera: Camera, background: Color, settings: RenderSettings) -> Self {
+        Renderer {
+            integrator,
+            camera,
+            background,
+            settings,
+        }
+    }
+
+    // Renders the full image with `samples_per_pixel` samples per pixel.
+    pub fn render(&self) -> Film {
+        let mut film = Film::new(self.settings.image_width, self.settings.image_height);
+        self.render_pass(&mut film, self.settings.samples_per_pixel);
+        // Ends the progress line
+        eprintln!();
+        film
+    }
+
+    // Renders passes until `budget` is used up or every pixel has
+    // `samples_per_pixel` samples. The first pass of one sample is always
+    // taken. Later passes are sized from the time the earlier ones took.
+    pub fn render_for(&self, budget: Duration) -> Film {
+        let start = Instant::now();
+        let mut film = Film::new(self.settings.image_width, self.settings.image_height);
+        let mut done: u64 = 0;
+        let mut pass: u64 = 1;
+
+        while done < self.settings.samples_per_pixel {
+            let samples = pass.min(self.settings.samples_per_pixel - done);
+            self.render_pass(&mut film, samples);
+            done += samples;
+
+            let elapsed = start.elapsed();
+            if elapsed >= budget {
+                break;
+            }
+            let per_sample = elapsed.as_secs_f64() / done as f64;
+            let affordable = ((budget - elapsed).as_secs_f64() / per_sample) as u64;
+            if affordable == 0 {
+                break;
+            }
+            // Passes at most double, so a slow part of the image cannot overshoot by much
+            pass = affordable.min(done);
+        }
+        eprintln!();
+        film
+    }
+
+    // Adds `samples` more samples to every pixel of `film`, one tile per job.
+    // Progress is shown on one line of stderr, which is redrawn a few times a
+    // second and left open for the next pass.
+    pub fn render_pass(&self, film: &mut Film, samples: u64) {
+        // Tells passes apart when seeding, since earlier passes left their samples in the film
+        let samples_before = film.sample_count(0, 0);
+        let tiles = self.tiles();
+        let next_tile = AtomicUsize::new(0);
+        let tiles_left = AtomicUsize::new(tiles.len());
+        let shared_film = Mutex::new(film);
+        let last_report: Mutex<Option<Instant>> = Mutex::new(None);
+
+        thread::scope(|s| {
+            for _ in 0..self.thread_count() {
+                s.spawn(|| {
+                    loop {
+                        let index = next_tile.fetch_add(1, Ordering::Relaxed);
+                        if index >= tiles.len() {
+                            break;
+                        }
+
+                        let tile = tiles[index];
+                        if let Some(seed) = self.settings.seed {
+                            seed_random(tile_seed(seed, samples_before, index as u64));
+                        }
+                        let sums = self.render_tile(&tile, samples);
+
+                        let mut film = shared_film.lock().unwrap();
+                        let mut k = 0;
+                        for y in tile.y0..tile.y1 {
+                            for x in tile.x0..tile.x1 {
+                                film.add_samples(x, y, sums[k], samples);
+                                k += 1;
+                            }
+                        }
+                        drop(film);
+
+                        // Counted under the lock, so the last tile is also the last report
+                        let mut last_report = last_report.lock().unwrap();
+                        let left = tiles_left.fetch_sub(1, Ordering::Relaxed) - 1;
+                        if left == 0 || last_report.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL) {
+                            eprint!("\rSamples per pixel: {}, tiles remaining: {}    ", samples_before + samples, left);
+                            *last_report = Some(Instant::now());
+                        }
+                    }
+                });
+            }
+        });
+    }
+
+    pub fn tiles(&self) -> Vec<Tile> {
+        let size = self.settings.tile_size.max(1);
+        let mut tiles = Vec::new();
+
+        let mut y0 = 0;
+        while y0 < self.settings.image_height {
+            let mut x0 = 0;
+            while x0 < self.settings.image_width {
+                tiles.push(Tile {
+                    x0,
+                    y0,
+                    x1: (x0 + size).min(self.settings.image_width),
+                    y1: (y0 + size).min(self.settings.image_height),
+                });
+                x0 += size;
+            }
+            y0 += size;
+        }
+        tiles
+    }
+
+    // Returns the sum of `samples` radiance samples for each pixel of the tile,
+    // in row-major order.
+    fn render_tile(&self, tile: &Tile, samples: u64) -> Vec<Color> {
+        let image_width = self.settings.image_width;
+        let image_height = self.settings.image_height;
+        // Smaller images are refused by the scene and command line parsers,
+        // but would divide by zero here
+        let last_column = (image_width.max(MIN_IMAGE_SIZE) - 1) as f64;
+        let last_row = (image_height.max(MIN_IMAGE_SIZE) - 1) as f64;
+        let mut sums = Vec::with_capacity(((tile.x1 - tile.x0)*(tile.y1 - tile.y0)) as usize);
+
+        for y in tile.y0..tile.y1 {
+            // The camera counts scanlines from the bottom of the image
+            let j = image_height - 1 - y;
+            for i in tile.x0..tile.x1 {
+                let mut pixel_color: Color = Color(0.0, 0.0, 0.0);
+                for _k in 0..samples {
+                    let u: f64 = (i as f64 + random_double()) / last_column;
+                    let v: f64 = (j as f64 + random_double()) / last_row;
+                    let r: Ray = self.camera.get_ray(u, v);
+                    pixel_color += self.integrator.ray_color(&r, &self.background, self.settings.max_depth);
+                }
+                sums.push(pixel_color);
+            }
+        }
+        sums
+    }
+
+    fn thread_count(&self) -> usize {
+        if self.settings.threads > 0 {
+            return self.settings.threads;
+        }
+        thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+    }
+}
+
+// Mixes the render seed, the pass and the tile into one seed (splitmix64).
+fn tile_seed(seed: u64, samples_before: u64, tile: u64) -> u64 {
+    let mut z = seed ^ samples_before.wrapping_mul(0x9e3779b97f4a7c15) ^ tile.wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
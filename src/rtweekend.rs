@@ -1,9 +1,23 @@
-use rand::random;
+use rand::Rng;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+use std::cell::RefCell;
 use std::f64::consts::PI;
 
+thread_local! {
+    // Every thread has its own generator, seeded from the OS until seed_random is called
+    static RNG: RefCell<StdRng> = RefCell::new(StdRng::from_entropy());
+}
+
+// Restarts the random numbers of the calling thread from `seed`, so the same
+// seed gives the same sequence.
+pub fn seed_random(seed: u64) {
+    RNG.with(|rng| *rng.borrow_mut() = StdRng::seed_from_u64(seed));
+}
+
 // Returns a random f64(real) number in from [0, 1)
 pub fn random_double() -> f64 {
-    random::<f64>()
+    RNG.with(|rng| rng.borrow_mut().gen::<f64>())
 }
 
 // Returns a random f64(real) number in [min,max).
@@ -11,6 +25,11 @@ pub fn random_double_range(min: f64, max: f64) -> f64 {
     min + (max - min)*random_double()
 }
 
+// Returns a random integer in [min,max].
+pub fn random_int(min: i64, max: i64) -> i64 {
+    random_double_range(min as f64, (max+1) as f64) as i64
+}
+
 pub fn degrees_to_radians(degrees: f64) -> f64 {
     (degrees*PI)/180.0
 }
@@ -18,5 +37,5 @@ pub fn degrees_to_radians(degrees: f64) -> f64 {
 pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
     if x < min { return min };
     if x > max { return max };
-    return x;
+    x
 }
\ No newline at end of file
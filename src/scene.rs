@@ -0,0 +1,1366 @@
+// Loader for scene description files.
+//
+// A scene file is plain text with one directive per line. Everything after a
+// '#' is a comment. A directive is a keyword followed by bare words and
+// key=value parameters. Vectors and colours are written as x,y,z (a single
+// number is used for all three components), and values containing spaces can
+// be put in double quotes.
+//
+//   image width=500 aspect=1 spp=100 max_depth=50     (or height= instead of aspect=)
+//   camera lookfrom=P lookat=P vup=V vfov=F aperture=F focus_dist=F time0=F time1=F
+//   background color=C
+//   background gradient top=C bottom=C              (the sky of the first book if left out)
+//   background image file=PATH intensity=F rotate=DEGREES   (equirectangular .hdr or .exr,
+//                                                   intensity= and rotate= optional)
+//   background sky elevation=DEGREES azimuth=DEGREES turbidity=F intensity=F
+//                                                   (sun and sky, see sky.rs; 45, 0, 3 and 0.1
+//                                                   if left out)
+//   lights sampling=tree | power | uniform          (tree if left out, see light_sampler.rs)
+//
+//   texture NAME solid color=C
+//   texture NAME checker even=C odd=C
+//   texture NAME noise scale=F
+//   texture NAME image file=PATH
+//
+//   material NAME lambertian albedo=C | texture=T
+//   material NAME metal albedo=C fuzz=F
+//   material NAME conductor metal=NAME roughness=F  (gold, silver, copper, aluminium or iron;
+//                                          or eta=C k=C, the complex index of refraction)
+//   material NAME conductor ... roughness_u=F roughness_v=F tangent=V
+//                                          (roughness along and across tangent=, 1,0,0 if
+//                                          left out, for brushed metals; see microfacet.rs)
+//   material NAME dielectric ir=F
+//   material NAME rough_dielectric ir=F roughness=F  (frosted glass, exact Fresnel; 0 if left out)
+//   material NAME dispersive_dielectric glass=NAME  (bk7, fused_silica or diamond)
+//   material NAME dispersive_dielectric cauchy_a=F cauchy_b=F cauchy_c=F  (c is 0 if left out)
+//   material NAME dispersive_dielectric sellmeier_b=V sellmeier_c=V
+//                                          (wavelengths in micrometres, see spectrum.rs)
+//   material NAME principled base_color=C metallic=F roughness=F specular=F specular_tint=F
+//                            sheen=F sheen_tint=F clearcoat=F clearcoat_roughness=F
+//                            transmission=F ior=F
+//                                          (any of them a texture name instead; base_color is 0.8
+//                                          if left out, see principled.rs for the others)
+//   material NAME coated base=M ior=F roughness=F color=C thickness=F
+//                                          (a clear coat over material M, defined earlier; ior
+//                                          1.5, roughness 0 and no absorption if left out; color=
+//                                          gets through a coat of thickness 1, see coated.rs)
+//   material NAME diffuse_light emit=C | texture=T intensity=F two_sided
+//                                          (or power=W instead of intensity=, the power of each
+//                                          object or instance with the material, as placed in
+//                                          the world; not for objects inside a prototype)
+//   material NAME isotropic albedo=C | texture=T
+//
+//   point_light position=P color=C intensity=F
+//   spot_light position=P direction=V cone=DEGREES falloff=DEGREES color=C intensity=F
+//                                          (lookat=P instead of direction=; falloff= is where
+//                                          the light starts to fade, cone= if left out)
+//   directional_light direction=V color=C intensity=F
+//                                          (see delta_light.rs; color= and intensity= are 1
+//                                          if left out. Not allowed in groups.)
+//
+//   sphere center=P radius=F material=M
+//   moving_sphere center0=P center1=P time0=F time1=F radius=F material=M
+//   xy_rect x0=F x1=F y0=F y1=F k=F material=M     (xz_rect and yz_rect alike)
+//   box min=P max=P material=M
+//   triangle v0=P v1=P v2=P material=M     (optionally n0= n1= n2= normals and uv0= uv1= uv2= as u,v)
+//   mesh file=PATH material=M              (.obj, .ply or binary .stl; material= is used where the
+//                                          file names none, else PLY vertex colours or grey)
+//
+//   group list | bvh
+//   ...
+//   end
+//
+//   prototype NAME [list | bvh]            (bvh if left out)
+//   ...
+//   end
+//   instance NAME material=M               (material= optional)
+//
+// A diffuse light only lights the front of a surface: the outside of a
+// sphere or box, the side of a rectangle its axis points to, and the side
+// of a triangle from which its corners go round anticlockwise. The bare word
+// `flip` on an object swaps its front and back.
+//
+// Objects and groups take the transforms rotate_x=DEGREES, rotate_y=DEGREES,
+// rotate_z=DEGREES, scale=F or scale=V and translate=V, applied in the order
+// they are written. An object with density=F is not a
+// surface but the boundary of a constant medium, and color=C is the albedo of
+// its phase function. Image paths are relative to the scene file.
+//
+// Every object that gives off light is sampled directly, found with
+// collect_lights once the scene is read. The bare word `important` (or
+// `light`) adds an object that gives off none, like a glass sphere, to the
+// sampled ones as well.
+//
+// Groups are built when their `end` is read, so a bvh over moving objects
+// uses the shutter times of a camera declared above it.
+//
+// A prototype is a group that is not put into the world. Instead each
+// `instance` of it places the same geometry again, with its transforms and,
+// if given, a material used in place of the prototype's own. The instances
+// of a group are kept in one InstanceSet, a BVH over the instances above the
+// BVHs of the prototypes, so repeating a mesh costs a matrix per copy. The
+// lights in a prototype are found in each instance. A prototype cannot hold
+// important objects, but an instance can be one.
+
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+use crate::vec3::Color;
+
+use crate::camera::Camera;
+
+use crate::hittable::Hittable;
+use crate::hittable::collect_lights;
+use crate::hittable::area_light_power;
+use crate::hittable::RotateY;
+use crate::hittable::Translate;
+use crate::hittable::FlipFace;
+
+use crate::hittable_list::HittableList;
+
+use crate::material::Material;
+use crate::material::DefaultMaterial;
+use crate::material::Lambertian;
+use crate::material::Metal;
+use crate::material::Conductor;
+use crate::material::Dialectric;
+use crate::material::RoughDielectric;
+use crate::material::DispersiveDielectric;
+use crate::material::DiffuseLight;
+use crate::material::Isotropic;
+use crate::principled::Principled;
+use crate::coated::Coated;
+use crate::spectrum::Dispersion;
+
+use crate::texture::Texture;
+use crate::texture::SolidColor;
+use crate::texture::CheckerTexture;
+use crate::texture::NoiseTexture;
+use crate::texture::ImageTexture;
+
+use crate::perlin::Perlin;
+
+use crate::sphere::Sphere;
+
+use crate::moving_sphere::MovingSphere;
+
+use crate::aarect::XYRect;
+use crate::aarect::XZRect;
+use crate::aarect::YZRect;
+
+use crate::abox::ABox;
+
+use crate::triangle::Triangle;
+
+use crate::mesh::load_mesh;
+
+use crate::transform::Transform;
+
+use crate::mat4::Mat4;
+
+use crate::constant_medium::ConstantMedium;
+
+use crate::linear_bvh::LinearBvh;
+
+use crate::instance::InstanceSetBuilder;
+
+use crate::light_sampler::LightSampler;
+use crate::light_sampler::LightStrategy;
+use crate::bvh_build::BvhError;
+
+use crate::environment::Environment;
+use crate::environment::GradientEnvironment;
+use crate::environment::ImageEnvironment;
+use crate::sky::SkyEnvironment;
+
+use crate::delta_light::DeltaLight;
+use crate::delta_light::PointLight;
+use crate::delta_light::SpotLight;
+use crate::delta_light::DirectionalLight;
+
+use crate::integrator::MisPathIntegrator;
+
+use crate::renderer::Renderer;
+use crate::renderer::RenderSettings;
+use crate::renderer::MIN_IMAGE_SIZE;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::Arc;
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(io::Error),
+    // A problem with the scene description, at a line of the file (counted from 1)
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(e) => write!(f, "{}", e),
+            SceneError::Parse { line, message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> Self {
+        SceneError::Io(e)
+    }
+}
+
+// Everything Camera::new needs except the aspect ratio, which comes from the
+// image size.
+#[derive(Copy, Clone)]
+pub struct CameraSettings {
+    pub lookfrom: Point3,
+    pub lookat: Point3,
+    pub vup: Vec3,
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+    pub time0: f64,
+    pub time1: f64,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CameraSettings {
+    pub fn new() -> Self {
+        CameraSettings {
+            lookfrom: Point3(0.0, 0.0, 0.0),
+            lookat: Point3(0.0, 0.0, -1.0),
+            vup: Vec3(0.0, 1.0, 0.0),
+            vfov: 90.0,
+            aperture: 0.0,
+            focus_dist: 10.0,
+            time0: 0.0,
+            time1: 1.0,
+        }
+    }
+
+    pub fn camera(&self, aspect_ratio: f64) -> Camera {
+        Camera::new(self.lookfrom, self.lookat, self.vup, self.vfov, aspect_ratio, self.aperture, self.focus_dist, self.time0, self.time1)
+    }
+}
+
+pub struct Scene {
+    pub camera: CameraSettings,
+    pub background: Color,
+    pub world: Arc<dyn Hittable>,
+    // What is sampled directly, usually a LightSampler over the lights and
+    // important objects. None when the scene has neither.
+    pub lights: Option<Arc<dyn Hittable>>,
+    // Seen by rays that hit nothing instead of `background`, if set
+    pub environment: Option<Arc<dyn Environment>>,
+    // Point, spot and directional lights
+    pub delta_lights: Vec<Arc<dyn DeltaLight>>,
+    // Image size, samples and depth from the `image` directive
+    pub settings: RenderSettings,
+}
+
+impl Scene {
+    pub fn load(path: &str) -> Result<Scene, SceneError> {
+        let source = fs::read_to_string(path)?;
+        let base_dir = Path::new(path).parent().unwrap_or(Path::new(""));
+        Scene::parse(&source, base_dir)
+    }
+
+    // Parses a scene description. Relative image paths are looked up in `base_dir`.
+    pub fn parse(source: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+        let mut parser = Parser::new(base_dir);
+        for (i, text) in source.lines().enumerate() {
+            let line = i + 1;
+            let tokens = tokenize(text).map_err(|message| SceneError::Parse { line, message })?;
+            if tokens.is_empty() {
+                continue;
+            }
+            let mut d = Directive::new(line, tokens)?;
+            parser.directive(&mut d)?;
+            d.finish()?;
+        }
+        parser.finish()
+    }
+
+    // A path tracer for the scene, set up with the scene's camera and image settings.
+    pub fn renderer(&self) -> Renderer {
+        let aspect_ratio = self.settings.image_width as f64 / self.settings.image_height as f64;
+        let integrator = Arc::new(MisPathIntegrator {
+            world: self.world.clone(),
+            lights: self.lights.clone(),
+            environment: self.environment.clone(),
+            delta_lights: self.delta_lights.clone(),
+        });
+        Renderer::new(integrator, self.camera.camera(aspect_ratio), self.background, self.settings)
+    }
+}
+
+// Splits a line into tokens at whitespace, dropping the comment. Double
+// quotes group characters into one token and are removed.
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut token = String::new();
+    let mut in_token = false;
+    let mut in_quotes = false;
+
+    for c in text.chars() {
+        if in_quotes {
+            if c == '"' {
+                in_quotes = false;
+            } else {
+                token.push(c);
+            }
+        } else if c == '"' {
+            in_quotes = true;
+            in_token = true;
+        } else if c == '#' {
+            break;
+        } else if c.is_whitespace() {
+            if in_token {
+                tokens.push(std::mem::take(&mut token));
+                in_token = false;
+            }
+        } else {
+            token.push(c);
+            in_token = true;
+        }
+    }
+
+    if in_quotes {
+        return Err(String::from("unterminated quote"));
+    }
+    if in_token {
+        tokens.push(token);
+    }
+    Ok(tokens)
+}
+
+// One line of the scene file: the keyword and bare words, then the key=value
+// parameters in the order they were written. Parameters are taken out as they
+// are used, so anything left over at the end was not understood.
+#[derive(Clone)]
+struct Directive {
+    line: usize,
+    words: Vec<String>,
+    params: Vec<(String, String)>,
+}
+
+impl Directive {
+    fn new(line: usize, tokens: Vec<String>) -> Result<Self, SceneError> {
+        let mut words = Vec::new();
+        let mut params = Vec::new();
+        for token in tokens {
+            match token.split_once('=') {
+                Some((key, value)) => {
+                    if params.iter().any(|(k, _)| k == key) {
+                        return Err(SceneError::Parse { line, message: format!("'{}' is given twice", key) });
+                    }
+                    params.push((key.to_string(), value.to_string()));
+                },
+                None => words.push(token),
+            }
+        }
+        if words.is_empty() {
+            return Err(SceneError::Parse { line, message: String::from("line does not start with a keyword") });
+        }
+        Ok(Directive {
+            line,
+            words,
+            params,
+        })
+    }
+
+    fn keyword(&self) -> &str {
+        &self.words[0]
+    }
+
+    fn error(&self, message: String) -> SceneError {
+        SceneError::Parse { line: self.line, message }
+    }
+
+    // The n-th bare word after the keyword
+    fn word(&self, n: usize, what: &str) -> Result<String, SceneError> {
+        match self.words.get(n + 1) {
+            Some(word) => Ok(word.clone()),
+            None => Err(self.error(format!("'{}' needs a {}", self.keyword(), what))),
+        }
+    }
+
+    // Removes the bare word `word` if present
+    fn flag(&mut self, word: &str) -> bool {
+        match self.words.iter().skip(1).position(|w| w == word) {
+            Some(i) => {
+                self.words.remove(i + 1);
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn take(&mut self, key: &str) -> Option<String> {
+        let i = self.params.iter().position(|(k, _)| k == key)?;
+        Some(self.params.remove(i).1)
+    }
+
+    fn require(&mut self, key: &str) -> Result<String, SceneError> {
+        match self.take(key) {
+            Some(value) => Ok(value),
+            None => Err(self.error(format!("'{}' needs {}=", self.keyword(), key))),
+        }
+    }
+
+    fn parse_f64(&self, key: &str, value: &str) -> Result<f64, SceneError> {
+        match value.trim().parse::<f64>() {
+            Ok(x) if x.is_finite() => Ok(x),
+            _ => Err(self.error(format!("{}={} is not a number", key, value))),
+        }
+    }
+
+    fn parse_vec3(&self, key: &str, value: &str) -> Result<Vec3, SceneError> {
+        let parts: Vec<&str> = value.split(',').collect();
+        match parts.len() {
+            1 => {
+                let x = self.parse_f64(key, parts[0])?;
+                Ok(Vec3(x, x, x))
+            },
+            3 => Ok(Vec3(self.parse_f64(key, parts[0])?, self.parse_f64(key, parts[1])?, self.parse_f64(key, parts[2])?)),
+            _ => Err(self.error(format!("{}={} is not a vector (write x,y,z)", key, value))),
+        }
+    }
+
+    fn f64(&mut self, key: &str) -> Result<Option<f64>, SceneError> {
+        match self.take(key) {
+            Some(value) => Ok(Some(self.parse_f64(key, &value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn require_f64(&mut self, key: &str) -> Result<f64, SceneError> {
+        let value = self.require(key)?;
+        self.parse_f64(key, &value)
+    }
+
+    fn vec3(&mut self, key: &str) -> Result<Option<Vec3>, SceneError> {
+        match self.take(key) {
+            Some(value) => Ok(Some(self.parse_vec3(key, &value)?)),
+            None => Ok(None),
+        }
+    }
+
+    fn require_vec3(&mut self, key: &str) -> Result<Vec3, SceneError> {
+        let value = self.require(key)?;
+        self.parse_vec3(key, &value)
+    }
+
+    // Texture coordinates, written as u,v
+    fn uv(&mut self, key: &str) -> Result<Option<(f64, f64)>, SceneError> {
+        let value = match self.take(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        match value.split_once(',') {
+            Some((u, v)) => Ok(Some((self.parse_f64(key, u)?, self.parse_f64(key, v)?))),
+            None => Err(self.error(format!("{}={} is not a texture coordinate (write u,v)", key, value))),
+        }
+    }
+
+    fn require_uv(&mut self, key: &str) -> Result<(f64, f64), SceneError> {
+        match self.uv(key)? {
+            Some(uv) => Ok(uv),
+            None => Err(self.error(format!("'{}' needs {}=", self.keyword(), key))),
+        }
+    }
+
+    fn u64(&mut self, key: &str) -> Result<Option<u64>, SceneError> {
+        match self.take(key) {
+            Some(value) => match value.parse::<u64>() {
+                Ok(n) if n > 0 => Ok(Some(n)),
+                _ => Err(self.error(format!("{}={} is not a positive whole number", key, value))),
+            },
+            None => Ok(None),
+        }
+    }
+
+    // Takes the transform parameters, in the order they were written
+    fn transforms(&mut self) -> Result<Vec<ObjectTransform>, SceneError> {
+        let mut transforms = Vec::new();
+        let mut i = 0;
+        while i < self.params.len() {
+            let (key, value) = self.params[i].clone();
+            match key.as_str() {
+                "rotate_y" => transforms.push(ObjectTransform::RotateY(self.parse_f64(&key, &value)?)),
+                "translate" => transforms.push(ObjectTransform::Translate(self.parse_vec3(&key, &value)?)),
+                "rotate_x" => transforms.push(ObjectTransform::Matrix(Mat4::rotate_x(self.parse_f64(&key, &value)?))),
+                "rotate_z" => transforms.push(ObjectTransform::Matrix(Mat4::rotate_z(self.parse_f64(&key, &value)?))),
+                "scale" => {
+                    let factors = self.parse_vec3(&key, &value)?;
+                    if factors.x() == 0.0 || factors.y() == 0.0 || factors.z() == 0.0 {
+                        return Err(self.error(format!("{}={} scales by zero", key, value)));
+                    }
+                    transforms.push(ObjectTransform::Matrix(Mat4::scale(factors)));
+                },
+                _ => {
+                    i += 1;
+                    continue;
+                }
+            }
+            self.params.remove(i);
+        }
+        Ok(transforms)
+    }
+
+    // Complains about words and parameters nobody asked for
+    fn finish(&self) -> Result<(), SceneError> {
+        if let Some(word) = self.words.get(1) {
+            return Err(self.error(format!("unexpected '{}' in '{}'", word, self.keyword())));
+        }
+        if let Some((key, _)) = self.params.first() {
+            return Err(self.error(format!("'{}' has no parameter '{}'", self.keyword(), key)));
+        }
+        Ok(())
+    }
+}
+
+#[derive(Copy, Clone)]
+enum ObjectTransform {
+    RotateY(f64),
+    Translate(Vec3),
+    // Anything else, by matrix
+    Matrix(Mat4),
+}
+
+fn apply_transforms(object: Arc<dyn Hittable>, transforms: &[ObjectTransform]) -> Arc<dyn Hittable> {
+    let mut object = object;
+    for transform in transforms {
+        object = match *transform {
+            ObjectTransform::RotateY(angle) => Arc::new(RotateY::new(object, angle)),
+            ObjectTransform::Translate(offset) => Arc::new(Translate::new(object, offset)),
+            // The matrices come from the parser, which only makes invertible ones
+            ObjectTransform::Matrix(matrix) => Arc::new(Transform::new(object, matrix).unwrap()),
+        };
+    }
+    object
+}
+
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum GroupKind {
+    List,
+    Bvh,
+}
+
+// The same transforms as one matrix
+fn transform_matrix(transforms: &[ObjectTransform]) -> Mat4 {
+    let mut matrix = Mat4::identity();
+    for transform in transforms {
+        let step = match *transform {
+            ObjectTransform::RotateY(angle) => Mat4::rotate_y(angle),
+            ObjectTransform::Translate(offset) => Mat4::translate(offset),
+            ObjectTransform::Matrix(step) => step,
+        };
+        matrix = step * matrix;
+    }
+    matrix
+}
+
+// A `diffuse_light` with power=, made into a DiffuseLight for each object
+struct PowerLight {
+    texture: Arc<dyn Texture>,
+    power: f64,
+    two_sided: bool,
+}
+
+impl PowerLight {
+    fn light(&self) -> DiffuseLight {
+        DiffuseLight::new_texture(self.texture.clone()).with_two_sides(self.two_sided)
+    }
+}
+
+// Objects collected between `group` (or `prototype`) and `end`. The outermost
+// group is the world.
+struct Group {
+    line: usize,
+    kind: GroupKind,
+    // Name of the prototype the group defines, if it is one
+    prototype: Option<String>,
+    transforms: Vec<ObjectTransform>,
+    objects: Vec<Arc<dyn Hittable>>,
+    instances: InstanceSetBuilder,
+    // Objects tagged `important`, with the line that tagged them
+    important: Vec<(usize, Arc<dyn Hittable>)>,
+}
+
+impl Group {
+    fn new(line: usize, kind: GroupKind, transforms: Vec<ObjectTransform>) -> Self {
+        Group {
+            line,
+            kind,
+            prototype: None,
+            transforms,
+            objects: Vec::new(),
+            instances: InstanceSetBuilder::new(),
+            important: Vec::new(),
+        }
+    }
+
+    // Puts the instances into the objects, as one InstanceSet
+    fn build_instances(&mut self, time0: f64, time1: f64) -> Result<(), SceneError> {
+        if self.instances.is_empty() {
+            return Ok(());
+        }
+        let instances = std::mem::take(&mut self.instances);
+        match instances.build(time0, time1) {
+            Ok(set) => self.objects.push(Arc::new(set)),
+            Err(e) => return Err(SceneError::Parse { line: self.line, message: format!("instances: {}", e) }),
+        }
+        Ok(())
+    }
+}
+
+struct Parser<'a> {
+    base_dir: &'a Path,
+    camera: CameraSettings,
+    background: Color,
+    environment: Option<Arc<dyn Environment>>,
+    delta_lights: Vec<Arc<dyn DeltaLight>>,
+    settings: RenderSettings,
+    textures: HashMap<String, Arc<dyn Texture>>,
+    materials: HashMap<String, Arc<dyn Material>>,
+    // Diffuse lights given by their power, which is turned into radiance
+    // for each object from its area
+    power_lights: HashMap<String, PowerLight>,
+    prototypes: HashMap<String, Arc<dyn Hittable>>,
+    light_strategy: LightStrategy,
+    groups: Vec<Group>,
+}
+
+impl<'a> Parser<'a> {
+    fn new(base_dir: &'a Path) -> Self {
+        Parser {
+            base_dir,
+            camera: CameraSettings::new(),
+            background: Color(0.0, 0.0, 0.0),
+            environment: None,
+            delta_lights: Vec::new(),
+            settings: RenderSettings::new(400, 225, 100, 50),
+            textures: HashMap::new(),
+            materials: HashMap::new(),
+            power_lights: HashMap::new(),
+            prototypes: HashMap::new(),
+            light_strategy: LightStrategy::Tree,
+            groups: vec![Group::new(0, GroupKind::List, Vec::new())],
+        }
+    }
+
+    fn directive(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        match d.keyword() {
+            "image" => self.image(d),
+            "camera" => self.camera(d),
+            "background" => self.background(d),
+            "lights" => {
+                self.light_strategy = match d.require("sampling")?.as_str() {
+                    "tree" => LightStrategy::Tree,
+                    "power" => LightStrategy::Power,
+                    "uniform" => LightStrategy::Uniform,
+                    other => return Err(d.error(format!("unknown light sampling '{}'", other))),
+                };
+                Ok(())
+            },
+            "texture" => self.texture(d),
+            "material" => self.material(d),
+            "group" => self.group(d),
+            "prototype" => self.prototype(d),
+            "end" => self.end(d),
+            "instance" => self.instance(d),
+            "point_light" | "spot_light" | "directional_light" => self.delta_light(d),
+            "sphere" | "moving_sphere" | "xy_rect" | "xz_rect" | "yz_rect" | "box" | "triangle" | "mesh" => self.object(d),
+            keyword => Err(d.error(format!("unknown directive '{}'", keyword))),
+        }
+    }
+
+    fn background(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        if d.words.len() == 1 {
+            self.background = d.require_vec3("color")?;
+            self.environment = None;
+            return Ok(());
+        }
+
+        let kind = d.word(0, "kind (gradient, image or sky)")?;
+        d.words.truncate(1);
+        let environment: Arc<dyn Environment> = match kind.as_str() {
+            "gradient" => {
+                let sky = GradientEnvironment::sky();
+                Arc::new(GradientEnvironment {
+                    bottom: d.vec3("bottom")?.unwrap_or(sky.bottom),
+                    top: d.vec3("top")?.unwrap_or(sky.top),
+                })
+            },
+            "image" => {
+                let file = d.require("file")?;
+                let intensity = d.f64("intensity")?.unwrap_or(1.0);
+                let rotate = d.f64("rotate")?.unwrap_or(0.0);
+                let path = self.base_dir.join(&file);
+                match ImageEnvironment::open(&path.to_string_lossy(), intensity, rotate) {
+                    Ok(environment) => Arc::new(environment),
+                    Err(e) => return Err(d.error(format!("cannot load image '{}': {}", path.display(), e))),
+                }
+            },
+            "sky" => Arc::new(SkyEnvironment::new(
+                d.f64("elevation")?.unwrap_or(45.0),
+                d.f64("azimuth")?.unwrap_or(0.0),
+                d.f64("turbidity")?.unwrap_or(3.0),
+                d.f64("intensity")?.unwrap_or(0.1),
+            )),
+            _ => return Err(d.error(format!("unknown background kind '{}'", kind))),
+        };
+        self.environment = Some(environment);
+        Ok(())
+    }
+
+    fn image(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        let aspect_ratio = self.settings.image_width as f64 / self.settings.image_height as f64;
+        if let Some(width) = d.u64("width")? {
+            self.settings.image_width = width;
+        }
+        let height = d.u64("height")?;
+        let aspect = d.f64("aspect")?;
+        self.settings.image_height = match (height, aspect) {
+            (Some(_), Some(_)) => return Err(d.error(String::from("give either height= or aspect=, not both"))),
+            (Some(height), None) => height,
+            (None, Some(aspect)) if aspect > 0.0 => ((self.settings.image_width as f64 / aspect) as u64).max(MIN_IMAGE_SIZE),
+            (None, Some(_)) => return Err(d.error(String::from("aspect= must be positive"))),
+            (None, None) => ((self.settings.image_width as f64 / aspect_ratio) as u64).max(MIN_IMAGE_SIZE),
+        };
+        if self.settings.image_width < MIN_IMAGE_SIZE || self.settings.image_height < MIN_IMAGE_SIZE {
+            return Err(d.error(format!("the image must be at least {} pixels wide and high", MIN_IMAGE_SIZE)));
+        }
+        if let Some(spp) = d.u64("spp")? {
+            self.settings.samples_per_pixel = spp;
+        }
+        if let Some(max_depth) = d.u64("max_depth")? {
+            self.settings.max_depth = max_depth;
+        }
+        Ok(())
+    }
+
+    fn camera(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        let c = &mut self.camera;
+        if let Some(lookfrom) = d.vec3("lookfrom")? { c.lookfrom = lookfrom; }
+        if let Some(lookat) = d.vec3("lookat")? { c.lookat = lookat; }
+        if let Some(vup) = d.vec3("vup")? { c.vup = vup; }
+        if let Some(vfov) = d.f64("vfov")? { c.vfov = vfov; }
+        if let Some(aperture) = d.f64("aperture")? { c.aperture = aperture; }
+        if let Some(focus_dist) = d.f64("focus_dist")? { c.focus_dist = focus_dist; }
+        if let Some(time0) = d.f64("time0")? { c.time0 = time0; }
+        if let Some(time1) = d.f64("time1")? { c.time1 = time1; }
+
+        if (c.lookfrom - c.lookat).length_square() == 0.0 {
+            return Err(d.error(String::from("lookfrom and lookat are the same point")));
+        }
+        Ok(())
+    }
+
+    fn texture(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        let name = d.word(0, "name")?;
+        let kind = d.word(1, "kind (solid, checker, noise or image)")?;
+        d.words.truncate(1);
+
+        let texture: Arc<dyn Texture> = match kind.as_str() {
+            "solid" => Arc::new(SolidColor { color_value: d.require_vec3("color")? }),
+            "checker" => Arc::new(CheckerTexture::new(d.require_vec3("even")?, d.require_vec3("odd")?)),
+            "noise" => Arc::new(NoiseTexture { noise: Perlin::new(), scale: d.f64("scale")?.unwrap_or(1.0) }),
+            "image" => {
+                let file = d.require("file")?;
+                let path = self.base_dir.join(&file);
+                match ImageTexture::open(&path.to_string_lossy()) {
+                    Ok(texture) => Arc::new(texture),
+                    Err(e) => return Err(d.error(format!("cannot load image '{}': {}", path.display(), e))),
+                }
+            },
+            _ => return Err(d.error(format!("unknown texture kind '{}'", kind))),
+        };
+        self.textures.insert(name, texture);
+        Ok(())
+    }
+
+    // A colour given as color_key=C, or a texture by name as texture=T
+    fn color_or_texture(&self, d: &mut Directive, color_key: &str) -> Result<Arc<dyn Texture>, SceneError> {
+        match (d.vec3(color_key)?, d.take("texture")) {
+            (Some(_), Some(_)) => Err(d.error(format!("give either {}= or texture=, not both", color_key))),
+            (Some(c), None) => Ok(Arc::new(SolidColor { color_value: c })),
+            (None, Some(name)) => match self.textures.get(&name) {
+                Some(texture) => Ok(texture.clone()),
+                None => Err(d.error(format!("no texture named '{}'", name))),
+            },
+            (None, None) => Err(d.error(format!("'{}' needs {}= or texture=", d.keyword(), color_key))),
+        }
+    }
+
+    fn material(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        let name = d.word(0, "name")?;
+        let kind = d.word(1, "kind (lambertian, metal, conductor, dielectric, rough_dielectric, dispersive_dielectric, principled, coated, diffuse_light or isotropic)")?;
+        let two_sided = kind == "diffuse_light" && d.flag("two_sided");
+        d.words.truncate(1);
+
+        let material: Arc<dyn Material> = match kind.as_str() {
+            "lambertian" => Arc::new(Lambertian { albedo: self.color_or_texture(d, "albedo")? }),
+            "metal" => Arc::new(Metal { albedo: d.require_vec3("albedo")?, fuzz: d.f64("fuzz")?.unwrap_or(0.0).min(1.0) }),
+            "conductor" => Arc::new(self.conductor(d)?),
+            "dielectric" => Arc::new(Dialectric { ir: d.require_f64("ir")? }),
+            "principled" => Arc::new(self.principled(d)?),
+            "coated" => Arc::new(self.coated(d)?),
+            "rough_dielectric" => Arc::new(RoughDielectric::new(d.require_f64("ir")?, d.f64("roughness")?.unwrap_or(0.0))),
+            "dispersive_dielectric" => Arc::new(DispersiveDielectric::new(self.dispersion(d)?)),
+            "diffuse_light" => {
+                let texture = self.color_or_texture(d, "emit")?;
+                let light = DiffuseLight::new_texture(texture.clone()).with_two_sides(two_sided);
+                match (d.f64("intensity")?, d.f64("power")?) {
+                    (Some(_), Some(_)) => return Err(d.error(String::from("give either intensity= or power=, not both"))),
+                    (None, Some(power)) => {
+                        self.power_lights.insert(name.clone(), PowerLight { texture, power, two_sided });
+                        Arc::new(light.with_power(power, 1.0))
+                    },
+                    (intensity, None) => {
+                        self.power_lights.remove(&name);
+                        Arc::new(light.with_intensity(intensity.unwrap_or(1.0)))
+                    },
+                }
+            },
+            "isotropic" => Arc::new(Isotropic::new_texture(self.color_or_texture(d, "albedo")?)),
+            _ => return Err(d.error(format!("unknown material kind '{}'", kind))),
+        };
+        if kind != "diffuse_light" {
+            self.power_lights.remove(&name);
+        }
+        self.materials.insert(name, material);
+        Ok(())
+    }
+
+    // A named metal with metal=, or its own eta= and k=, which may also
+    // change those of the named one
+    fn conductor(&self, d: &mut Directive) -> Result<Conductor, SceneError> {
+        let (eta, k) = match d.take("metal") {
+            Some(metal) => match Conductor::preset(&metal) {
+                Some((eta, k)) => (d.vec3("eta")?.unwrap_or(eta), d.vec3("k")?.unwrap_or(k)),
+                None => return Err(d.error(format!("unknown metal '{}'", metal))),
+            },
+            None => (d.require_vec3("eta")?, d.require_vec3("k")?),
+        };
+        let roughness = d.f64("roughness")?.unwrap_or(0.0);
+        let roughness_u = d.f64("roughness_u")?.unwrap_or(roughness);
+        let roughness_v = d.f64("roughness_v")?.unwrap_or(roughness);
+        let tangent = d.vec3("tangent")?.unwrap_or(Vec3(1.0, 0.0, 0.0));
+        Ok(Conductor::anisotropic(eta, k, roughness_u, roughness_v, tangent))
+    }
+
+    // A number or colour given as key=V, or a texture by name as key=T
+    fn value_or_texture(&self, d: &mut Directive, key: &str) -> Result<Option<Arc<dyn Texture>>, SceneError> {
+        let value = match d.take(key) {
+            Some(value) => value,
+            None => return Ok(None),
+        };
+        if let Some(texture) = self.textures.get(&value) {
+            return Ok(Some(texture.clone()));
+        }
+        Ok(Some(Arc::new(SolidColor { color_value: d.parse_vec3(key, &value)? })))
+    }
+
+    fn principled(&self, d: &mut Directive) -> Result<Principled, SceneError> {
+        let base_color = self.value_or_texture(d, "base_color")?;
+        let mut material = Principled::new(base_color.unwrap_or_else(|| Arc::new(SolidColor::new(0.8, 0.8, 0.8))));
+        let parameters: [(&str, &mut Arc<dyn Texture>); 10] = [
+            ("metallic", &mut material.metallic),
+            ("roughness", &mut material.roughness),
+            ("specular", &mut material.specular),
+            ("specular_tint", &mut material.specular_tint),
+            ("sheen", &mut material.sheen),
+            ("sheen_tint", &mut material.sheen_tint),
+            ("clearcoat", &mut material.clearcoat),
+            ("clearcoat_roughness", &mut material.clearcoat_roughness),
+            ("transmission", &mut material.transmission),
+            ("ior", &mut material.ior),
+        ];
+        for (key, texture) in parameters {
+            if let Some(value) = self.value_or_texture(d, key)? {
+                *texture = value;
+            }
+        }
+        Ok(material)
+    }
+
+    // A named glass with glass=, or Cauchy's or Sellmeier's coefficients
+    fn dispersion(&self, d: &mut Directive) -> Result<Dispersion, SceneError> {
+        if let Some(glass) = d.take("glass") {
+            return match Dispersion::preset(&glass) {
+                Some(dispersion) => Ok(dispersion),
+                None => Err(d.error(format!("unknown glass '{}'", glass))),
+            };
+        }
+        if let Some(a) = d.f64("cauchy_a")? {
+            return Ok(Dispersion::Cauchy { a, b: d.require_f64("cauchy_b")?, c: d.f64("cauchy_c")?.unwrap_or(0.0) });
+        }
+        let b = d.require_vec3("sellmeier_b")?;
+        let c = d.require_vec3("sellmeier_c")?;
+        Ok(Dispersion::Sellmeier { b: [b.x(), b.y(), b.z()], c: [c.x(), c.y(), c.z()] })
+    }
+
+    fn coated(&self, d: &mut Directive) -> Result<Coated, SceneError> {
+        let base_name = match d.take("base") {
+            Some(name) => name,
+            None => return Err(d.error(String::from("'coated' needs base=, the material under the coat"))),
+        };
+        let base = match self.materials.get(&base_name) {
+            Some(material) => material.clone(),
+            None => return Err(d.error(format!("no material named '{}'", base_name))),
+        };
+        let material = Coated::new(base, d.f64("ior")?.unwrap_or(1.5), d.f64("roughness")?.unwrap_or(0.0));
+        Ok(material.with_absorption(d.vec3("color")?.unwrap_or(Color(1.0, 1.0, 1.0)), d.f64("thickness")?.unwrap_or(1.0)))
+    }
+
+    fn object(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        let is_important = d.flag("important");
+        let is_light = d.flag("light");
+        let flip = d.flag("flip");
+        let density = d.f64("density")?;
+
+        let material_name = d.take("material");
+        let mut material = match &material_name {
+            Some(name) => match self.materials.get(name) {
+                Some(material) => Some(material.clone()),
+                None => return Err(d.error(format!("no material named '{}'", name))),
+            },
+            None => None,
+        };
+
+        let kind = d.keyword().to_string();
+        // A light given by its power gets a material of its own, which learns
+        // the area of the object in the world once the object is made
+        let mut power_light: Option<(Arc<DiffuseLight>, Mat4)> = None;
+        if let Some(light) = material_name.and_then(|name| self.power_lights.get(&name)) {
+            let group_matrix = self.power_light_matrix(d)?;
+            let light = Arc::new(light.light().with_power_for_later_area(light.power));
+            material = Some(light.clone());
+            power_light = Some((light, group_matrix));
+        }
+
+        // A medium boundary only needs a shape, so its material may be left
+        // out, and meshes usually bring their own
+        let mat: Arc<dyn Material> = match &material {
+            Some(material) => material.clone(),
+            None if density.is_some() || kind == "mesh" => Arc::new(DefaultMaterial),
+            None => return Err(d.error(format!("'{}' needs material=", kind))),
+        };
+
+        let shape = self.shape(d, &kind, mat, material)?;
+        let shape: Arc<dyn Hittable> = if flip { Arc::new(FlipFace::new(shape)) } else { shape };
+
+        let transforms = d.transforms()?;
+        let mut object = apply_transforms(shape, &transforms);
+
+        if let Some((light, group_matrix)) = power_light {
+            let area = object.area(&group_matrix);
+            if area <= 0.0 {
+                return Err(d.error(String::from("a light given by its power needs an object with an area")));
+            }
+            light.set_area(area);
+        }
+
+        if let Some(density) = density {
+            if density <= 0.0 {
+                return Err(d.error(String::from("density= must be positive")));
+            }
+            let color = d.vec3("color")?.unwrap_or(Color(1.0, 1.0, 1.0));
+            object = Arc::new(ConstantMedium::new(object, density, color));
+        }
+
+        let group = self.groups.last_mut().unwrap();
+        // Objects that give off light are found anyway
+        if (is_important || is_light) && object.emitted_power() <= 0.0 {
+            group.important.push((d.line, object.clone()));
+        }
+        group.objects.push(object);
+        Ok(())
+    }
+
+    // The shape of an object directive, with `mat` as its material. Meshes
+    // use `material` where the file names none.
+    fn shape(&self, d: &mut Directive, kind: &str, mat: Arc<dyn Material>, material: Option<Arc<dyn Material>>) -> Result<Arc<dyn Hittable>, SceneError> {
+        let shape: Arc<dyn Hittable> = match kind {
+            "sphere" => Arc::new(Sphere {
+                center: d.require_vec3("center")?,
+                radius: d.require_f64("radius")?,
+                mat_ptr: mat,
+            }),
+            "moving_sphere" => Arc::new(MovingSphere {
+                center0: d.require_vec3("center0")?,
+                center1: d.require_vec3("center1")?,
+                time0: d.f64("time0")?.unwrap_or(0.0),
+                time1: d.f64("time1")?.unwrap_or(1.0),
+                radius: d.require_f64("radius")?,
+                mat_ptr: mat,
+            }),
+            "xy_rect" => Arc::new(XYRect::new(d.require_f64("x0")?, d.require_f64("x1")?, d.require_f64("y0")?, d.require_f64("y1")?, d.require_f64("k")?, mat)),
+            "xz_rect" => Arc::new(XZRect::new(d.require_f64("x0")?, d.require_f64("x1")?, d.require_f64("z0")?, d.require_f64("z1")?, d.require_f64("k")?, mat)),
+            "yz_rect" => Arc::new(YZRect::new(d.require_f64("y0")?, d.require_f64("y1")?, d.require_f64("z0")?, d.require_f64("z1")?, d.require_f64("k")?, mat)),
+            "box" => Arc::new(ABox::new(&d.require_vec3("min")?, &d.require_vec3("max")?, mat)),
+            "mesh" => {
+                let path = self.base_dir.join(d.require("file")?);
+                match load_mesh(&path.to_string_lossy(), material) {
+                    Ok(mesh) => Arc::new(mesh),
+                    Err(e) => return Err(d.error(format!("cannot load mesh '{}': {}", path.display(), e))),
+                }
+            },
+            _ => {
+                let vertices = [d.require_vec3("v0")?, d.require_vec3("v1")?, d.require_vec3("v2")?];
+                let mut triangle = Triangle::new(vertices[0], vertices[1], vertices[2], mat);
+                if let Some(n0) = d.vec3("n0")? {
+                    triangle.normals = [n0, d.require_vec3("n1")?, d.require_vec3("n2")?];
+                }
+                if let Some(uv0) = d.uv("uv0")? {
+                    triangle.uvs = [uv0, d.require_uv("uv1")?, d.require_uv("uv2")?];
+                }
+                Arc::new(triangle)
+            },
+        };
+        Ok(shape)
+    }
+
+    fn group(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        let kind = match d.word(0, "kind (list or bvh)")?.as_str() {
+            "list" => GroupKind::List,
+            "bvh" => GroupKind::Bvh,
+            other => return Err(d.error(format!("unknown group kind '{}'", other))),
+        };
+        d.words.truncate(1);
+
+        let transforms = d.transforms()?;
+        self.groups.push(Group::new(d.line, kind, transforms));
+        Ok(())
+    }
+
+    fn prototype(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        let name = d.word(0, "name")?;
+        let kind = match d.words.get(2).map(|word| word.as_str()) {
+            None | Some("bvh") => GroupKind::Bvh,
+            Some("list") => GroupKind::List,
+            Some(other) => return Err(d.error(format!("unknown group kind '{}'", other))),
+        };
+        d.words.truncate(1);
+
+        let transforms = d.transforms()?;
+        let mut group = Group::new(d.line, kind, transforms);
+        group.prototype = Some(name);
+        self.groups.push(group);
+        Ok(())
+    }
+
+    fn instance(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        let is_important = d.flag("important");
+        let is_light = d.flag("light");
+        let name = d.word(0, "prototype name")?;
+        d.words.truncate(1);
+
+        let prototype = match self.prototypes.get(&name) {
+            Some(prototype) => prototype.clone(),
+            None => return Err(d.error(format!("no prototype named '{}'", name))),
+        };
+        let material_name = d.take("material");
+        let mut material = match &material_name {
+            Some(name) => match self.materials.get(name) {
+                Some(material) => Some(material.clone()),
+                None => return Err(d.error(format!("no material named '{}'", name))),
+            },
+            None => None,
+        };
+        let transforms = d.transforms()?;
+        let matrix = transform_matrix(&transforms);
+
+        // Like objects, but the prototype is there already
+        if let Some(light) = material_name.and_then(|name| self.power_lights.get(&name)) {
+            let area = prototype.area(&(self.power_light_matrix(d)? * matrix));
+            if area <= 0.0 {
+                return Err(d.error(String::from("a light given by its power needs an object with an area")));
+            }
+            material = Some(Arc::new(light.light().with_power(light.power, area)));
+        }
+
+        let group = self.groups.last_mut().unwrap();
+        // The parser only makes invertible transforms
+        group.instances.add(&prototype, matrix, material.as_ref());
+        // Instances that give off light are found anyway, as long as they
+        // have an area to sample
+        let emits = match &material {
+            Some(material) => area_light_power(prototype.area(&Mat4::identity()), material.as_ref()) > 0.0,
+            None => prototype.emitted_power() > 0.0,
+        };
+        if (is_important || is_light) && !emits {
+            group.important.push((d.line, apply_transforms(prototype, &transforms)));
+        }
+        Ok(())
+    }
+
+    // What the open groups do to an object put into the innermost one, for
+    // finding the area of a light given by its power. Inside a prototype
+    // every instance has an area of its own, so such lights are refused.
+    fn power_light_matrix(&self, d: &Directive) -> Result<Mat4, SceneError> {
+        let mut matrix = Mat4::identity();
+        for group in &self.groups {
+            if let Some(name) = &group.prototype {
+                return Err(d.error(format!("a light given by its power cannot be put in prototype '{}', give it to the instances instead", name)));
+            }
+            matrix = matrix * transform_matrix(&group.transforms);
+        }
+        Ok(matrix)
+    }
+
+    fn delta_light(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        // Groups may be transformed, and these lights cannot be
+        if self.groups.len() > 1 {
+            return Err(d.error(format!("'{}' cannot be put in a group or prototype", d.keyword())));
+        }
+        let color = d.vec3("color")?.unwrap_or(Color(1.0, 1.0, 1.0));
+        let intensity = d.f64("intensity")?.unwrap_or(1.0);
+
+        let light: Arc<dyn DeltaLight> = match d.keyword() {
+            "point_light" => Arc::new(PointLight { position: d.require_vec3("position")?, color, intensity }),
+            "spot_light" => {
+                let position = d.require_vec3("position")?;
+                let direction = match (d.vec3("direction")?, d.vec3("lookat")?) {
+                    (Some(direction), None) => direction,
+                    (None, Some(lookat)) => lookat - position,
+                    _ => return Err(d.error(String::from("give either direction= or lookat="))),
+                };
+                if direction.near_zero() {
+                    return Err(d.error(String::from("the spot light points nowhere")));
+                }
+                let cone = d.require_f64("cone")?;
+                let falloff = d.f64("falloff")?.unwrap_or(cone);
+                Arc::new(SpotLight::new(position, direction, cone, falloff, color, intensity))
+            },
+            _ => {
+                let direction = d.require_vec3("direction")?;
+                if direction.near_zero() {
+                    return Err(d.error(String::from("the directional light points nowhere")));
+                }
+                Arc::new(DirectionalLight::new(direction, color, intensity))
+            },
+        };
+        self.delta_lights.push(light);
+        Ok(())
+    }
+
+    fn end(&mut self, d: &mut Directive) -> Result<(), SceneError> {
+        if self.groups.len() < 2 {
+            return Err(d.error(String::from("'end' without a matching 'group' or 'prototype'")));
+        }
+        let mut group = self.groups.pop().unwrap();
+        group.build_instances(self.camera.time0, self.camera.time1)?;
+
+        let object: Arc<dyn Hittable> = match group.kind {
+            GroupKind::List => Arc::new(HittableList { objects: group.objects }),
+            GroupKind::Bvh => {
+                match LinearBvh::new(&group.objects, self.camera.time0, self.camera.time1) {
+                    Ok(node) => Arc::new(node),
+                    Err(e) => return Err(SceneError::Parse { line: group.line, message: format!("bvh group: {}", e) }),
+                }
+            },
+        };
+
+        if let Some(name) = group.prototype {
+            if let Some((line, _)) = group.important.first() {
+                return Err(SceneError::Parse { line: *line, message: format!("prototype '{}' cannot hold important objects, tag the instances instead", name) });
+            }
+            self.prototypes.insert(name, apply_transforms(object, &group.transforms));
+            return Ok(());
+        }
+
+        // Important objects inside the group move along with it
+        let parent = self.groups.last_mut().unwrap();
+        for (line, object) in group.important {
+            parent.important.push((line, apply_transforms(object, &group.transforms)));
+        }
+        parent.objects.push(apply_transforms(object, &group.transforms));
+        Ok(())
+    }
+
+    fn finish(mut self) -> Result<Scene, SceneError> {
+        if self.groups.len() > 1 {
+            let line = self.groups.last().unwrap().line;
+            return Err(SceneError::Parse { line, message: String::from("group is never closed with 'end'") });
+        }
+        let mut world = self.groups.pop().unwrap();
+        world.build_instances(self.camera.time0, self.camera.time1)?;
+
+        let objects: Arc<dyn Hittable> = Arc::new(HittableList { objects: world.objects });
+        let lights = collect_lights(&objects);
+        let important: Vec<Arc<dyn Hittable>> = world.important.iter().map(|(_, object)| object.clone()).collect();
+        let sampler: Option<Arc<dyn Hittable>> = if lights.is_empty() && important.is_empty() {
+            None
+        } else {
+            match LightSampler::new(&lights, &important, self.light_strategy, self.camera.time0, self.camera.time1) {
+                Ok(sampler) => Some(Arc::new(sampler)),
+                Err(BvhError::NoBoundingBox { index }) if index >= lights.len() => {
+                    return Err(SceneError::Parse { line: world.important[index - lights.len()].0, message: String::from("an important object needs a bounding box") });
+                },
+                Err(e) => return Err(SceneError::Parse { line: 0, message: format!("lights: {}", e) }),
+            }
+        };
+
+        Ok(Scene {
+            camera: self.camera,
+            background: self.background,
+            world: objects,
+            lights: sampler,
+            environment: self.environment,
+            delta_lights: self.delta_lights,
+            settings: self.settings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The line and message of the error the scene gives
+    fn error_of(source: &str) -> (usize, String) {
+        match Scene::parse(source, Path::new("")) {
+            Err(SceneError::Parse { line, message }) => (line, message),
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("the scene was read"),
+        }
+    }
+
+    #[test]
+    fn reads_a_scene() {
+        let source = "# a comment\n\
+                      image width=64 aspect=2 spp=8 max_depth=4\n\
+                      camera lookfrom=0,1,4 lookat=0,0,0 vfov=40\n\
+                      material white lambertian albedo=0.8   # trailing comment\n\
+                      material lamp diffuse_light emit=4\n\
+                      sphere center=0,0,0 radius=1 material=white\n\
+                      xz_rect x0=-1 x1=1 z0=-1 z1=1 k=3 material=lamp light\n";
+        let scene = match Scene::parse(source, Path::new("")) {
+            Ok(scene) => scene,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(scene.settings.image_width, 64);
+        assert_eq!(scene.settings.image_height, 32);
+        assert_eq!(scene.settings.samples_per_pixel, 8);
+        assert_eq!(scene.settings.max_depth, 4);
+        assert_eq!(scene.camera.vfov, 40.0);
+        assert!(scene.lights.is_some());
+    }
+
+    #[test]
+    fn quoted_values_keep_their_spaces() {
+        assert_eq!(tokenize("mesh file=\"my mesh.obj\" # x").unwrap(), vec!["mesh", "file=my mesh.obj"]);
+        assert_eq!(error_of("\n\nmesh file=\"open"), (3, String::from("unterminated quote")));
+    }
+
+    #[test]
+    fn errors_give_their_line() {
+        assert_eq!(error_of("image width=10\n\nfoo bar=1"), (3, String::from("unknown directive 'foo'")));
+        assert_eq!(error_of("=1"), (1, String::from("line does not start with a keyword")));
+        assert_eq!(error_of("camera vfov=1 vfov=2"), (1, String::from("'vfov' is given twice")));
+        assert_eq!(error_of("camera fov=40"), (1, String::from("'camera' has no parameter 'fov'")));
+        assert_eq!(error_of("camera vfov=40 extra"), (1, String::from("unexpected 'extra' in 'camera'")));
+        assert_eq!(error_of("camera vfov=wide"), (1, String::from("vfov=wide is not a number")));
+        assert_eq!(error_of("camera lookat=1,2"), (1, String::from("lookat=1,2 is not a vector (write x,y,z)")));
+        assert_eq!(error_of("camera lookfrom=0,0,0 lookat=0,0,0"), (1, String::from("lookfrom and lookat are the same point")));
+    }
+
+    #[test]
+    fn objects_need_their_parameters() {
+        let materials = "material white lambertian albedo=0.8\n";
+        assert_eq!(error_of(&format!("{}sphere center=0,0,0 material=white", materials)), (2, String::from("'sphere' needs radius=")));
+        assert_eq!(error_of(&format!("{}sphere center=0,0,0 radius=1", materials)), (2, String::from("'sphere' needs material=")));
+        assert_eq!(error_of("sphere center=0,0,0 radius=1 material=black"), (1, String::from("no material named 'black'")));
+    }
+
+    #[test]
+    fn groups_must_be_closed() {
+        let source = "material white lambertian albedo=0.8\n\
+                      group bvh\n\
+                      sphere center=0,0,0 radius=1 material=white\n";
+        assert_eq!(error_of(source), (2, String::from("group is never closed with 'end'")));
+    }
+
+    #[test]
+    fn prototypes_cannot_hold_important_objects() {
+        let source = "material glass dielectric ir=1.5\n\
+                      prototype marble\n\
+                      sphere center=0,0,0 radius=1 material=glass important\n\
+                      end\n";
+        assert_eq!(error_of(source).0, 3);
+        assert_eq!(error_of("instance marble"), (1, String::from("no prototype named 'marble'")));
+    }
+
+    #[test]
+    fn power_lights_are_spread_over_the_transformed_area() {
+        // A 1 x 1 rectangle stretched to 4 x 1, lit by power 4 pi on one side,
+        // has a radiance of 1
+        let source = "material lamp diffuse_light emit=1 power=12.566370614359172\n\
+                      xz_rect x0=0 x1=1 z0=0 z1=1 k=0 material=lamp scale=4,1,1\n";
+        let scene = match Scene::parse(source, Path::new("")) {
+            Ok(scene) => scene,
+            Err(e) => panic!("{}", e),
+        };
+        let power = scene.lights.unwrap().emitted_power();
+        assert!((power - 4.0 * std::f64::consts::PI).abs() < 1.0e-9, "power {}", power);
+    }
+
+    // The power of the lights the scene finds, which is the power= of each
+    // when its radiance comes from the area it has in the world
+    fn light_power(source: &str) -> f64 {
+        match Scene::parse(source, Path::new("")) {
+            Ok(scene) => scene.lights.unwrap().emitted_power(),
+            Err(e) => panic!("{}", e),
+        }
+    }
+
+    #[test]
+    fn power_lights_count_the_transforms_of_groups_and_instances() {
+        let power = 4.0 * std::f64::consts::PI;
+        let lamp = format!("material lamp diffuse_light emit=1 power={}\n", power);
+
+        let grouped = lamp.clone() + "group list scale=2\n\
+                                      xz_rect x0=0 x1=1 z0=0 z1=1 k=0 material=lamp scale=2,1,1\n\
+                                      end\n";
+        assert!((light_power(&grouped) - power).abs() < 1.0e-9, "power {}", light_power(&grouped));
+
+        let placed = lamp.clone() + "material grey lambertian albedo=0.5\n\
+                                     prototype panel\n\
+                                     xz_rect x0=0 x1=1 z0=0 z1=1 k=0 material=grey\n\
+                                     end\n\
+                                     group list scale=2\n\
+                                     instance panel material=lamp scale=3,1,1\n\
+                                     instance panel material=lamp\n\
+                                     end\n";
+        assert!((light_power(&placed) - 2.0 * power).abs() < 1.0e-9, "power {}", light_power(&placed));
+
+        let inside = lamp + "prototype panel\n\
+                             xz_rect x0=0 x1=1 z0=0 z1=1 k=0 material=lamp\n\
+                             end\n";
+        assert_eq!(error_of(&inside), (3, String::from("a light given by its power cannot be put in prototype 'panel', give it to the instances instead")));
+    }
+
+    #[test]
+    fn images_need_two_pixels_each_way() {
+        assert_eq!(error_of("image width=1 height=10"), (1, String::from("the image must be at least 2 pixels wide and high")));
+        assert_eq!(error_of("image width=10 aspect=0"), (1, String::from("aspect= must be positive")));
+        let scene = match Scene::parse("image width=4 aspect=100", Path::new("")) {
+            Ok(scene) => scene,
+            Err(e) => panic!("{}", e),
+        };
+        assert_eq!(scene.settings.image_height, MIN_IMAGE_SIZE);
+    }
+}
@@ -0,0 +1,220 @@
+// A clear daylight sky after Preetham, Shirley and Smits, "A Practical
+// Analytic Model for Daylight" (SIGGRAPH 1999).
+//
+// The sky is given by the sun's position and the turbidity, the haziness of
+// the air: about 2 for a very clear sky, 3 for a clear one and 6 or more for
+// a hazy one. The model gives the luminance and chromaticity of the sky in
+// every direction from the values at the zenith and the Perez distribution
+// function, and the colour of the sun from how much of its light is
+// scattered on its way through the air. Below the horizon, where the model
+// says nothing, the angle from the zenith is held at 90 degrees, so those
+// directions see the sky at the horizon.
+//
+// Luminances are in kcd/m^2, scaled by `intensity`. The default of 0.1
+// puts a clear sky at around 1, like the constant backgrounds of the books.
+//
+// The sun is a disc of its real size, which is far too small to be found by
+// following rays from a diffuse surface, so it is sampled directly: a
+// direction is picked either in the disc or from a table of the sky's
+// luminance, in proportion to the light coming from each.
+
+use crate::vec3::Vec3;
+use crate::vec3::Color;
+
+use crate::color::luminance;
+use crate::environment::Environment;
+use crate::environment::SphereDistribution;
+use crate::onb::Onb;
+use crate::rtweekend::random_double;
+use crate::rtweekend::degrees_to_radians;
+
+use std::f64::consts::PI;
+
+// Angular radius of the sun, in radians
+const SUN_RADIUS: f64 = 0.00465;
+// Luminance of the sun outside the atmosphere, in kcd/m^2
+const SUN_LUMINANCE: f64 = 2.0e6;
+// Size of the luminance table used to sample the sky
+const TABLE_WIDTH: usize = 64;
+const TABLE_HEIGHT: usize = 32;
+
+pub struct SkyEnvironment {
+    // Unit vector towards the sun
+    sun_direction: Vec3,
+    // Perez coefficients A to E for Y, x and y
+    perez: [[f64; 5]; 3],
+    // Y, x, y at the zenith, each divided by the Perez function there
+    zenith: [f64; 3],
+    sun_color: Color,
+    sun_cos_radius: f64,
+    intensity: f64,
+    // Chance of sampling the sun rather than the sky
+    sun_probability: f64,
+    sky_distribution: SphereDistribution,
+}
+
+impl SkyEnvironment {
+    // `elevation` is the angle of the sun above the horizon and `azimuth`
+    // its angle about the y axis from -z towards +x, both in degrees.
+    // Elevations are kept between 0 and 90 and turbidities between 1.7
+    // and 10, where the model holds.
+    pub fn new(elevation: f64, azimuth: f64, turbidity: f64, intensity: f64) -> Self {
+        let theta_sun = degrees_to_radians(90.0 - elevation.clamp(0.0, 90.0));
+        let phi_sun = degrees_to_radians(azimuth);
+        let t = turbidity.clamp(1.7, 10.0);
+
+        let sun_direction = Vec3(theta_sun.sin() * phi_sun.sin(), theta_sun.cos(), -theta_sun.sin() * phi_sun.cos());
+
+        let perez = [
+            [0.1787*t - 1.4630, -0.3554*t + 0.4275, -0.0227*t + 5.3251, 0.1206*t - 2.5771, -0.0670*t + 0.3703],
+            [-0.0193*t - 0.2592, -0.0665*t + 0.0008, -0.0004*t + 0.2125, -0.0641*t - 0.8989, -0.0033*t + 0.0452],
+            [-0.0167*t - 0.2608, -0.0950*t + 0.0092, -0.0079*t + 0.2102, -0.0441*t - 1.6537, -0.0109*t + 0.0529],
+        ];
+
+        let chi = (4.0/9.0 - t/120.0) * (PI - 2.0*theta_sun);
+        let zenith_y = (4.0453*t - 4.9710) * chi.tan() - 0.2155*t + 2.4192;
+        let th = theta_sun;
+        let th2 = th * th;
+        let th3 = th2 * th;
+        let zenith_x = t*t * (0.00166*th3 - 0.00375*th2 + 0.00209*th)
+            + t * (-0.02903*th3 + 0.06377*th2 - 0.03202*th + 0.00394)
+            + (0.11693*th3 - 0.21196*th2 + 0.06052*th + 0.25886);
+        let zenith_yc = t*t * (0.00275*th3 - 0.00610*th2 + 0.00317*th)
+            + t * (-0.04214*th3 + 0.08970*th2 - 0.04153*th + 0.00516)
+            + (0.15346*th3 - 0.26756*th2 + 0.06670*th + 0.26688);
+        let zenith_values = [zenith_y.max(0.0), zenith_x, zenith_yc];
+        let mut zenith = [0.0; 3];
+        for k in 0..3 {
+            zenith[k] = zenith_values[k] / perez_function(&perez[k], 1.0, theta_sun);
+        }
+
+        let mut sky = SkyEnvironment {
+            sun_direction,
+            perez,
+            zenith,
+            sun_color: intensity * SUN_LUMINANCE * sun_transmittance(theta_sun, t),
+            sun_cos_radius: SUN_RADIUS.cos(),
+            intensity,
+            sun_probability: 0.0,
+            sky_distribution: SphereDistribution::new(1, 1, &[0.0], 0.0),
+        };
+
+        // The sky's luminance at the centre of each cell of the table, and
+        // the light of the whole sky to weigh against the sun's
+        let mut weights = Vec::with_capacity(TABLE_WIDTH * TABLE_HEIGHT);
+        let mut sky_power = 0.0;
+        for j in 0..TABLE_HEIGHT {
+            let theta = PI * (j as f64 + 0.5) / TABLE_HEIGHT as f64;
+            for i in 0..TABLE_WIDTH {
+                let phi = 2.0*PI * ((i as f64 + 0.5) / TABLE_WIDTH as f64 - 0.5);
+                let direction = Vec3(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos());
+                let weight = luminance(sky.sky_value(&direction));
+                weights.push(weight);
+                sky_power += weight * theta.sin() * (2.0*PI / TABLE_WIDTH as f64) * (PI / TABLE_HEIGHT as f64);
+            }
+        }
+        sky.sky_distribution = SphereDistribution::new(TABLE_WIDTH, TABLE_HEIGHT, &weights, 0.0);
+
+        let sun_power = luminance(sky.sun_color) * sky.sun_solid_angle();
+        if sun_power + sky_power > 0.0 {
+            sky.sun_probability = sun_power / (sun_power + sky_power);
+        }
+        sky
+    }
+
+    // The same sky with no sun in it, for integrators that cannot sample
+    // it. Its light is then missing from the scene.
+    pub fn without_sun(mut self) -> Self {
+        self.sun_color = Color(0.0, 0.0, 0.0);
+        self.sun_probability = 0.0;
+        self
+    }
+
+    fn sun_solid_angle(&self) -> f64 {
+        2.0*PI * (1.0 - self.sun_cos_radius)
+    }
+
+    // The sky without the sun
+    fn sky_value(&self, direction: &Vec3) -> Color {
+        let d = Vec3::unit_vector(*direction);
+        // Directions below the horizon see the sky at the horizon
+        let cos_theta = d.y().max(0.001);
+        let cos_gamma = Vec3::dot(d, self.sun_direction).clamp(-1.0, 1.0);
+        let gamma = cos_gamma.acos();
+
+        let values: [f64; 3] = std::array::from_fn(|k| self.zenith[k] * perez_function(&self.perez[k], cos_theta, gamma));
+        let big_y = self.intensity * values[0].max(0.0);
+        xyy_to_rgb(values[1], values[2], big_y)
+    }
+}
+
+// The Perez sky distribution function for the angle theta from the zenith,
+// given by its cosine, and the angle gamma from the sun
+fn perez_function(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    let cos_gamma = gamma.cos();
+    (1.0 + c[0] * (c[1] / cos_theta).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * cos_gamma * cos_gamma)
+}
+
+// Chromaticity x, y and luminance Y to linear sRGB
+fn xyy_to_rgb(x: f64, y: f64, big_y: f64) -> Color {
+    if y <= 0.0 {
+        return Color(0.0, 0.0, 0.0);
+    }
+    let big_x = x / y * big_y;
+    let big_z = (1.0 - x - y) / y * big_y;
+    Color(
+        (3.2406*big_x - 1.5372*big_y - 0.4986*big_z).max(0.0),
+        (-0.9689*big_x + 1.8758*big_y + 0.0415*big_z).max(0.0),
+        (0.0557*big_x - 0.2040*big_y + 1.0570*big_z).max(0.0),
+    )
+}
+
+// Fraction of the sun's light in the red, green and blue reaching the
+// ground through Rayleigh scattering by the air and Mie scattering by
+// aerosols (Preetham et al., appendix A.1, without ozone and water vapour)
+fn sun_transmittance(theta_sun: f64, turbidity: f64) -> Color {
+    // Relative optical mass of the air on the way through the atmosphere
+    let m = 1.0 / (theta_sun.cos() + 0.15 * (93.885 - theta_sun.to_degrees()).powf(-1.253));
+    let beta = 0.04608*turbidity - 0.04586;
+    let alpha = 1.3;
+    // Wavelengths for red, green and blue, in micrometres
+    let lambda: [f64; 3] = [0.65, 0.55, 0.45];
+    let mut tau = [0.0; 3];
+    for k in 0..3 {
+        let rayleigh = 0.008735 * lambda[k].powf(-4.08);
+        let mie = beta * lambda[k].powf(-alpha);
+        tau[k] = (-m * (rayleigh + mie)).exp();
+    }
+    Color(tau[0], tau[1], tau[2])
+}
+
+impl Environment for SkyEnvironment {
+    fn value(&self, direction: &Vec3) -> Color {
+        let d = Vec3::unit_vector(*direction);
+        let sky = self.sky_value(&d);
+        if Vec3::dot(d, self.sun_direction) >= self.sun_cos_radius {
+            return sky + self.sun_color;
+        }
+        sky
+    }
+
+    fn pdf_value(&self, direction: &Vec3) -> f64 {
+        let d = Vec3::unit_vector(*direction);
+        let sun_pdf = if Vec3::dot(d, self.sun_direction) >= self.sun_cos_radius { 1.0 / self.sun_solid_angle() } else { 0.0 };
+        self.sun_probability * sun_pdf + (1.0 - self.sun_probability) * self.sky_distribution.pdf_value(&d)
+    }
+
+    fn sample(&self) -> Option<Vec3> {
+        if random_double() >= self.sun_probability {
+            return self.sky_distribution.sample();
+        }
+
+        // Uniform over the cone of the disc
+        let mut uvw = Onb(Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 0.0), Vec3(0.0, 0.0, 0.0));
+        uvw.build_from_w(&self.sun_direction);
+        let z = 1.0 - random_double() * (1.0 - self.sun_cos_radius);
+        let phi = 2.0*PI*random_double();
+        let r = (1.0 - z*z).max(0.0).sqrt();
+        Some(uvw.local(r * phi.cos(), r * phi.sin(), z))
+    }
+}
@@ -0,0 +1,142 @@
+// Light as wavelengths, for materials whose behaviour depends on them.
+//
+// The renderer works in RGB. A ray carries light of all wavelengths
+// (Ray::wavelength is 0) until it meets a material that sends each
+// wavelength its own way, like dispersive glass. There a hero wavelength is
+// drawn for the path, and the ray carries only that wavelength from then on,
+// weighted by its RGB colour over its density so that the colours of all
+// the wavelengths add up to white. The other wavelengths of the path would
+// leave the glass in other directions, so they are dropped (as in Wilkie et
+// al., "Hero Wavelength Spectral Sampling", EGSR 2014, for specular
+// interfaces). A spectral renderer would draw the wavelength at the camera
+// instead, and the materials keep whatever wavelength the ray brings.
+//
+// The colour of a wavelength comes from the CIE 1931 colour matching
+// functions, in the multi-lobe fit of Wyman, Sloan and Shirley ("Simple
+// Analytic Approximations to the CIE XYZ Color Matching Functions", JCGT
+// 2013), turned into linear sRGB. Colours outside of sRGB are clipped to it.
+
+use crate::vec3::Color;
+
+use crate::rtweekend::random_double;
+
+// Range of visible wavelengths drawn, in nanometres
+pub const WAVELENGTH_MIN: f64 = 360.0;
+pub const WAVELENGTH_MAX: f64 = 830.0;
+
+// How the index of refraction of a material changes with the wavelength.
+// Wavelengths in the formulas are in micrometres.
+#[derive(Copy, Clone)]
+pub enum Dispersion {
+    // n = a + b / λ² + c / λ⁴
+    Cauchy { a: f64, b: f64, c: f64 },
+    // n² = 1 + Σ b_i λ² / (λ² - c_i)
+    Sellmeier { b: [f64; 3], c: [f64; 3] },
+}
+
+impl Dispersion {
+    // Index of refraction at a wavelength in nanometres
+    pub fn ior(&self, wavelength: f64) -> f64 {
+        let l2 = (wavelength / 1000.0) * (wavelength / 1000.0);
+        match self {
+            Dispersion::Cauchy { a, b, c } => a + b / l2 + c / (l2*l2),
+            Dispersion::Sellmeier { b, c } => {
+                let mut n2 = 1.0;
+                for i in 0..3 {
+                    n2 += b[i] * l2 / (l2 - c[i]);
+                }
+                n2.max(1.0).sqrt()
+            },
+        }
+    }
+
+    // Sellmeier coefficients of common glasses: bk7 (Schott N-BK7, the usual
+    // crown glass of lenses and prisms), fused_silica (Malitson 1965) and
+    // diamond (Peter 1923)
+    pub fn preset(name: &str) -> Option<Dispersion> {
+        let dispersion = match name {
+            "bk7" => Dispersion::Sellmeier {
+                b: [1.03961212, 0.231792344, 1.01046945],
+                c: [0.00600069867, 0.0200179144, 103.560653],
+            },
+            "fused_silica" => Dispersion::Sellmeier {
+                b: [0.6961663, 0.4079426, 0.8974794],
+                c: [0.0684043*0.0684043, 0.1162414*0.1162414, 9.896161*9.896161],
+            },
+            "diamond" => Dispersion::Sellmeier {
+                b: [0.3306, 4.3356, 0.0],
+                c: [0.1750*0.1750, 0.1060*0.1060, 0.0],
+            },
+            _ => return None,
+        };
+        Some(dispersion)
+    }
+}
+
+// Linear sRGB colour of light of one wavelength in nanometres, with the
+// luminance of the CIE Y function, clipped to the colours sRGB can show
+pub fn wavelength_to_rgb(wavelength: f64) -> Color {
+    let x = 1.056 * lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * lobe(wavelength, 442.0, 16.0, 26.7)
+        - 0.065 * lobe(wavelength, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * lobe(wavelength, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * lobe(wavelength, 459.0, 26.0, 13.8);
+    Color(
+        (3.2404542*x - 1.5371385*y - 0.4985314*z).max(0.0),
+        (-0.9692660*x + 1.8760108*y + 0.0415560*z).max(0.0),
+        (0.0556434*x - 0.2040259*y + 1.0572252*z).max(0.0),
+    )
+}
+
+// A Gaussian with a different width on each side of its peak
+fn lobe(x: f64, mu: f64, sigma_below: f64, sigma_above: f64) -> f64 {
+    let t = (x - mu) / if x < mu { sigma_below } else { sigma_above };
+    (-0.5 * t * t).exp()
+}
+
+// Draws hero wavelengths in proportion to how much they add to the red,
+// green and blue of white light, in steps of a nanometre
+pub struct WavelengthSampler {
+    cdf: Vec<f64>,
+    // Colour of each step over the density it is drawn with
+    weights: Vec<Color>,
+}
+
+impl WavelengthSampler {
+    pub fn new() -> Self {
+        let steps = (WAVELENGTH_MAX - WAVELENGTH_MIN) as usize;
+        let colors: Vec<Color> = (0..steps).map(|i| wavelength_to_rgb(WAVELENGTH_MIN + i as f64 + 0.5)).collect();
+
+        // Scale each channel so that all the wavelengths together make white
+        let mut total = Color(0.0, 0.0, 0.0);
+        for c in &colors {
+            total += *c;
+        }
+        let colors: Vec<Color> = colors.iter().map(|c| Color(c.x() / total.x(), c.y() / total.y(), c.z() / total.z())).collect();
+
+        // Each channel now sums to 1, so the three together sum to 3
+        let mut cdf = Vec::with_capacity(steps);
+        let mut weights = Vec::with_capacity(steps);
+        let mut sum = 0.0;
+        for c in &colors {
+            let p = (c.x() + c.y() + c.z()) / 3.0;
+            sum += p;
+            cdf.push(sum);
+            weights.push(if p > 0.0 { *c / p } else { Color(0.0, 0.0, 0.0) });
+        }
+        WavelengthSampler { cdf, weights }
+    }
+
+    // A wavelength in nanometres and the colour a path carrying only it is
+    // weighted by. The weights average to white.
+    pub fn sample(&self) -> (f64, Color) {
+        let u = random_double() * self.cdf[self.cdf.len() - 1];
+        let i = self.cdf.partition_point(|&c| c <= u).min(self.cdf.len() - 1);
+        (WAVELENGTH_MIN + i as f64 + random_double(), self.weights[i])
+    }
+}
+
+impl Default for WavelengthSampler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,5 +1,4 @@
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 use std::f64::consts::PI;
 
 use crate::onb::Onb;
@@ -9,6 +8,7 @@ use crate::vec3::Point3;
 
 use crate::hittable::Hittable;
 use crate::hittable::HitRecord;
+use crate::hittable::area_light_power;
 use crate::ray::Ray;
 
 use crate::material::Material;
@@ -16,16 +16,18 @@ use crate::material::DefaultMaterial;
 
 use crate::aabb::AABB;
 
+use crate::mat4::Mat4;
+
 
 #[derive(Clone)]
 pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
-    pub mat_ptr: Rc<RefCell<dyn Material>>,
+    pub mat_ptr: Arc<dyn Material>,
 }
 
 impl Hittable for Sphere {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let oc: Vec3 = r.origin() - self.center;
         let a: f64 = r.direction().length_square();
         let half_b: f64 = Vec3::dot(oc, r.direction());
@@ -63,25 +65,25 @@ impl Hittable for Sphere {
         true
     }
 
-    fn pdf_value(&mut self, o: &Vec3, v: &Vec3) -> f64 {
+    fn pdf_value(&self, o: &Vec3, v: &Vec3) -> f64 {
         let mut rec: HitRecord = HitRecord {
             p: Point3(0.0, 0.0, 0.0),
             normal: Vec3(0.0, 0.0, 0.0),
-            mat_ptr: Rc::new(RefCell::new(DefaultMaterial)),
+            mat_ptr: Arc::new(DefaultMaterial),
             t: 0.0,
             u: 0.0,
             v: 0.0,
             front_face: false,
         };
 
-        if !self.hit(&Ray {origin: *o, direction: *v, tm: 0.0}, 0.001, f64::INFINITY, &mut rec) {
+        if !self.hit(&Ray {origin: *o, direction: *v, tm: 0.0, wavelength: 0.0}, 0.001, f64::INFINITY, &mut rec) {
             return 0.0;
         }
 
         let cos_theta_max = (1.0 - self.radius*self.radius/(self.center-*o).length_square()).sqrt();
         let solid_angle = 2.0*PI*(1.0 - cos_theta_max);
 
-        return 1.0/solid_angle;
+        1.0/solid_angle
     }
 
     fn random(&self, o: &Vec3) -> Vec3 {
@@ -91,10 +93,22 @@ impl Hittable for Sphere {
         uvw.build_from_w(&direction);
         uvw.local_vec(&Sphere::random_to_sphere(self.radius, distance_squared))
     }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        // The area of an ellipsoid takes a while, and most spheres are dark
+        if self.mat_ptr.average_emission() <= 0.0 {
+            return 0.0;
+        }
+        area_light_power(self.area(matrix), self.mat_ptr.as_ref())
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        ellipsoid_area(self.radius, matrix)
+    }
 }
 
 impl Sphere {
-    fn get_sphere(&self, p: &Point3, u: &mut f64, v: &mut f64) -> () {
+    fn get_sphere(&self, p: &Point3, u: &mut f64, v: &mut f64) {
         let theta = (-p.y()).acos();
         let phi = (-p.z()).atan2(p.x()) + PI;
         *u = phi / (2.0*PI);
@@ -110,6 +124,28 @@ impl Sphere {
         let x = phi.cos()*(1.0-z*z).sqrt();
         let y = phi.sin()*(1.0-z*z).sqrt();
 
-        return Vec3(x, y, z);
+        Vec3(x, y, z)
+    }
+}
+
+// Area of a sphere of `radius` moved by `matrix`, an ellipsoid, which has no
+// closed form. It is summed over small patches of the sphere, each growing by
+// the area of its two sides once moved. Exact for rotations and uniform
+// scales, and within about a ten thousandth for the others.
+pub fn ellipsoid_area(radius: f64, matrix: &Mat4) -> f64 {
+    const STEPS: usize = 64;
+    let mut sum = 0.0;
+    for i in 0..STEPS {
+        let z = -1.0 + 2.0*(i as f64 + 0.5)/STEPS as f64;
+        let r = (1.0 - z*z).sqrt();
+        for j in 0..2*STEPS {
+            let phi = PI*(j as f64 + 0.5)/STEPS as f64;
+            let n = Vec3(r*phi.cos(), r*phi.sin(), z);
+            let a = if n.x().abs() > 0.9 { Vec3(0.0, 1.0, 0.0) } else { Vec3(1.0, 0.0, 0.0) };
+            let t1 = Vec3::unit_vector(Vec3::cross(n, a));
+            let t2 = Vec3::cross(n, t1);
+            sum += matrix.transformed_area(&t1, &t2);
+        }
     }
-}
\ No newline at end of file
+    4.0*PI*radius*radius * sum / (2*STEPS*STEPS) as f64
+}
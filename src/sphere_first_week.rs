@@ -1,5 +1,4 @@
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 use std::f64::consts::PI;
 
 use crate::vec3::Vec3;
@@ -17,11 +16,11 @@ use crate::aabb_first_week::AABB;
 pub struct Sphere {
     pub center: Point3,
     pub radius: f64,
-    pub mat_ptr: Rc<RefCell<dyn Material>>,
+    pub mat_ptr: Arc<dyn Material>,
 }
 
 impl Hittable for Sphere {
-    fn hit(&mut self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
         let oc: Vec3 = r.origin() - self.center;
         let a: f64 = r.direction().length_square();
         let half_b: f64 = Vec3::dot(oc, r.direction());
@@ -61,7 +60,7 @@ impl Hittable for Sphere {
 }
 
 impl Sphere {
-    fn get_sphere(&self, p: &Point3, u: &mut f64, v: &mut f64) -> () {
+    fn get_sphere(&self, p: &Point3, u: &mut f64, v: &mut f64) {
         let theta = (-p.y()).acos();
         let phi = (-p.z()).atan2(p.x()) + PI;
         *u = phi / (2.0*PI);
@@ -0,0 +1,127 @@
+// Binary STL import.
+//
+// A binary STL file is an 80 byte header, a triangle count, and 50 bytes per
+// triangle: the facet normal, the three corners and an attribute word, all
+// little endian. The facet normals are not used, the winding of the corners
+// already tells which way a face looks. Corners are not shared between
+// triangles in STL, so every face gets three vertices of its own.
+
+use crate::vec3::Point3;
+
+use crate::material::Material;
+
+use crate::mesh::MeshData;
+use crate::mesh::MeshFace;
+use crate::mesh::MeshError;
+use crate::mesh::TriangleMesh;
+
+use std::fs;
+use std::sync::Arc;
+
+const HEADER_SIZE: usize = 84;
+const TRIANGLE_SIZE: usize = 50;
+
+pub fn load_stl(path: &str, material: Arc<dyn Material>) -> Result<TriangleMesh, MeshError> {
+    let bytes = fs::read(path)?;
+    let mut data = parse_stl(&bytes, path)?;
+    data.materials.push(material);
+    Ok(TriangleMesh::new(data))
+}
+
+// Parses the contents of a binary STL file. `file` is only used in error
+// messages. All faces refer to material 0, which is left for the caller to add.
+pub fn parse_stl(bytes: &[u8], file: &str) -> Result<MeshData, MeshError> {
+    let error = |message: String| MeshError::Parse { file: file.to_string(), line: 0, message };
+
+    if bytes.len() < HEADER_SIZE {
+        return Err(error(String::from("too short for a binary STL file")));
+    }
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    if bytes.len() < HEADER_SIZE + count * TRIANGLE_SIZE {
+        // ASCII files start with "solid", but so do the headers of some binary ones
+        if bytes.starts_with(b"solid") {
+            return Err(error(String::from("ASCII STL files are not supported, only binary ones")));
+        }
+        return Err(error(format!("the file ends before its {} triangles", count)));
+    }
+
+    let float = |offset: usize| f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]) as f64;
+
+    let mut data = MeshData::new();
+    for i in 0..count {
+        // The corners follow the 12 bytes of the facet normal
+        let start = HEADER_SIZE + i * TRIANGLE_SIZE + 12;
+        for corner in 0..3 {
+            let offset = start + 12 * corner;
+            let p = Point3(float(offset), float(offset + 4), float(offset + 8));
+            if !(p.x().is_finite() && p.y().is_finite() && p.z().is_finite()) {
+                return Err(error(format!("a corner of triangle {} is not a finite number", i)));
+            }
+            data.positions.push(p);
+        }
+        data.faces.push(MeshFace {
+            positions: [3*i, 3*i + 1, 3*i + 2],
+            normals: None,
+            uvs: None,
+            material: 0,
+        });
+    }
+
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A binary STL file with the given triangles
+    fn stl(triangles: &[[[f32; 3]; 3]]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 80];
+        bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for triangle in triangles {
+            bytes.extend_from_slice(&[0u8; 12]);
+            for corner in triangle {
+                for x in corner {
+                    bytes.extend_from_slice(&x.to_le_bytes());
+                }
+            }
+            bytes.extend_from_slice(&[0u8; 2]);
+        }
+        bytes
+    }
+
+    fn error_of(bytes: &[u8]) -> String {
+        match parse_stl(bytes, "test.stl") {
+            Err(MeshError::Parse { message, .. }) => message,
+            Err(e) => panic!("unexpected error: {}", e),
+            Ok(_) => panic!("the file was read"),
+        }
+    }
+
+    #[test]
+    fn every_triangle_has_its_own_corners() {
+        let bytes = stl(&[
+            [[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]],
+            [[1.0, 0.0, 0.0], [1.0, 1.0, 0.0], [0.0, 1.0, 0.0]],
+        ]);
+        let data = parse_stl(&bytes, "test.stl").unwrap();
+        assert_eq!(data.positions.len(), 6);
+        assert_eq!(data.faces[1].positions, [3, 4, 5]);
+        assert!((data.corners(1)[1] - Point3(1.0, 1.0, 0.0)).near_zero());
+        assert!((data.area(0) + data.area(1) - 1.0).abs() < 1.0e-12);
+    }
+
+    #[test]
+    fn short_and_ascii_files_are_refused() {
+        assert_eq!(error_of(b"solid"), "too short for a binary STL file");
+
+        let mut bytes = stl(&[[[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]]]);
+        bytes.truncate(bytes.len() - 1);
+        assert_eq!(error_of(&bytes), "the file ends before its 1 triangles");
+        bytes[..5].copy_from_slice(b"solid");
+        assert_eq!(error_of(&bytes), "ASCII STL files are not supported, only binary ones");
+
+        let bytes = stl(&[[[0.0, 0.0, 0.0], [f32::NAN, 0.0, 0.0], [0.0, 1.0, 0.0]]]);
+        assert_eq!(error_of(&bytes), "a corner of triangle 0 is not a finite number");
+    }
+}
@@ -3,13 +3,12 @@ use crate::vec3::Point3;
 
 use crate::perlin::Perlin;
 
-use std::rc::Rc;
-use std::cell::RefCell;
+use std::sync::Arc;
 
 use image::io::Reader;
 use image::ImageBuffer;
 
-pub trait Texture {
+pub trait Texture: Send + Sync {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color;
 }
 
@@ -32,17 +31,17 @@ impl SolidColor {
 }
 
 pub struct CheckerTexture {
-    pub odd: Rc<RefCell<dyn Texture>>,
-    pub even: Rc<RefCell<dyn Texture>>
+    pub odd: Arc<dyn Texture>,
+    pub even: Arc<dyn Texture>
 }
 
 impl Texture for CheckerTexture {
     fn value(&self, u: f64, v: f64, p: &Point3) -> Color {
         let sines = (10.0*p.x()).sin()*(10.0*p.y()).sin()*(10.0*p.z()).sin();
         if sines < 0.0 {
-            return self.odd.borrow().value(u, v, p);
+            self.odd.value(u, v, p)
         } else {
-            return self.even.borrow().value(u, v, p);
+            self.even.value(u, v, p)
         }
     }
 }
@@ -50,8 +49,8 @@ impl Texture for CheckerTexture {
 impl CheckerTexture {
     pub fn new(c1: Color, c2: Color) -> Self {
         CheckerTexture {
-            even: Rc::new(RefCell::new(SolidColor{color_value: c1})),
-            odd: Rc::new(RefCell::new(SolidColor{color_value: c2}))
+            even: Arc::new(SolidColor{color_value: c1}),
+            odd: Arc::new(SolidColor{color_value: c2})
         }
     }
 }
@@ -77,8 +76,8 @@ pub struct ImageTexture {
 
 impl Texture for ImageTexture {
     fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
-        let u = if u < 0.0 { 0.0 } else if u > 1.0 { 1.0 } else { u };
-        let v = 1.0 - {if v < 0.0 { 0.0 } else if v > 1.0 { 1.0 } else { v }};
+        let u = u.clamp(0.0, 1.0);
+        let v = 1.0 - v.clamp(0.0, 1.0);
 
         let mut i = (u * self.width as f64) as u32;
         let mut j = (v * self.height as f64) as u32;
@@ -95,8 +94,13 @@ impl Texture for ImageTexture {
 
 impl ImageTexture {
     pub fn new(filename: String) -> Self {
+        ImageTexture::open(&filename).unwrap()
+    }
+
+    // Like new, but hands back the error when the file is missing or cannot be decoded
+    pub fn open(filename: &str) -> Result<Self, image::ImageError> {
         let bytes_per_pixel: u32 = 3;
-        let limage = Reader::open(filename).unwrap().decode().unwrap().to_rgb8();
+        let limage = Reader::open(filename)?.decode()?.to_rgb8();
 
         let (width, height) = limage.dimensions();
 
@@ -107,11 +111,44 @@ impl ImageTexture {
 
         let bytes_per_scanline = bytes_per_pixel*width;
         
-        ImageTexture {
+        Ok(ImageTexture {
             data: limage,
-            width: width,
-            height: height,
-            bytes_per_scanline: bytes_per_scanline
+            width,
+            height,
+            bytes_per_scanline
+        })
+    }
+}
+
+// Colours given at the corners of each face of a mesh, blended across the
+// face. The mesh has to carry the texture coordinates from face_uvs, which
+// tell the face (u/2 rounded down) and the barycentric coordinates (the
+// fraction of u, and v) of a hit.
+pub struct VertexColorTexture {
+    pub corner_colors: Vec<[Color; 3]>,
+}
+
+impl Texture for VertexColorTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point3) -> Color {
+        if self.corner_colors.is_empty() {
+            return Color(0.0, 0.0, 0.0);
         }
+
+        let face = ((u / 2.0).floor().max(0.0) as usize).min(self.corner_colors.len() - 1);
+        let b1 = (u - 2.0 * face as f64).clamp(0.0, 1.0);
+        let b2 = v.clamp(0.0, 1.0);
+        let b0 = (1.0 - b1 - b2).max(0.0);
+
+        let [c0, c1, c2] = self.corner_colors[face];
+        b0*c0 + b1*c1 + b2*c2
     }
-}
\ No newline at end of file
+}
+
+impl VertexColorTexture {
+    // Texture coordinates of the three corners of a face. Faces are two
+    // units apart in u, so a hit on an edge is never taken for the next face.
+    pub fn face_uvs(face: usize) -> [(f64, f64); 3] {
+        let u = 2.0 * face as f64;
+        [(u, 0.0), (u + 1.0, 0.0), (u, 1.0)]
+    }
+}
@@ -0,0 +1,214 @@
+// Places an object in the world with an affine matrix, for the rotations,
+// scales and shears that Translate and RotateY cannot do.
+//
+// Rays are taken into object space with the inverse matrix. The direction is
+// not normalised, so t means the same on both sides and the hit record only
+// needs its point and normal taken back out.
+
+use crate::hittable::Hittable;
+use crate::hittable::HitRecord;
+use crate::hittable::collect_lights;
+use crate::ray::Ray;
+
+use crate::aabb::AABB;
+
+use crate::mat4::Mat4;
+
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+
+use std::sync::Arc;
+
+pub struct Transform {
+    ptr: Arc<dyn Hittable>,
+    // Object to world
+    matrix: Mat4,
+    // World to object
+    inverse: Mat4,
+}
+
+impl Hittable for Transform {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let object_r = Ray {
+            origin: self.inverse.transform_point(&r.origin()),
+            direction: self.inverse.transform_vector(&r.direction()),
+            tm: r.time(),
+            wavelength: r.wavelength(),
+        };
+
+        if !self.ptr.hit(&object_r, t_min, t_max, rec) {
+            return false;
+        }
+
+        // The inverse transpose keeps which side of the surface the ray is on,
+        // so the normal still faces the ray
+        rec.p = self.matrix.transform_point(&rec.p);
+        rec.normal = Vec3::unit_vector(self.inverse.transform_normal(&rec.normal));
+
+        true
+    }
+
+    // The corners of the object's box, moved into the world
+    fn bounding_box(&self, time0: f64, time1: f64, output_box: &mut AABB) -> bool {
+        let mut bbox = AABB {
+            minimum: Point3(0.0, 0.0, 0.0),
+            maximum: Point3(0.0, 0.0, 0.0),
+        };
+        if !self.ptr.bounding_box(time0, time1, &mut bbox) {
+            return false;
+        }
+
+        let mut min = Point3(f64::INFINITY, f64::INFINITY, f64::INFINITY);
+        let mut max = Point3(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY);
+        for i in 0..2 {
+            for j in 0..2 {
+                for k in 0..2 {
+                    let corner = Point3(
+                        if i == 0 { bbox.min().x() } else { bbox.max().x() },
+                        if j == 0 { bbox.min().y() } else { bbox.max().y() },
+                        if k == 0 { bbox.min().z() } else { bbox.max().z() },
+                    );
+                    let tester = self.matrix.transform_point(&corner);
+                    for c in 0..3 {
+                        min[c] = min[c].min(tester[c]);
+                        max[c] = max[c].max(tester[c]);
+                    }
+                }
+            }
+        }
+
+        *output_box = AABB {
+            minimum: min,
+            maximum: max
+        };
+        true
+    }
+
+    // The object gives the density over its own directions. Stretching space
+    // also stretches solid angles: a unit direction w seen through the linear
+    // map A of the inverse covers |det A| / |A w|^3 times as much solid angle
+    // in object space.
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let w = Vec3::unit_vector(*v);
+        let object_v = self.inverse.transform_vector(&w);
+        let stretch = object_v.length();
+        if stretch == 0.0 {
+            return 0.0;
+        }
+
+        let object_pdf = self.ptr.pdf_value(&self.inverse.transform_point(o), &object_v);
+        object_pdf * self.inverse.determinant3().abs() / (stretch * stretch * stretch)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        self.matrix.transform_vector(&self.ptr.random(&self.inverse.transform_point(o)))
+    }
+
+    // The shapes find their area once moved by the matrix
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        self.ptr.transformed_emitted_power(&(*matrix * self.matrix))
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        self.ptr.area(&(*matrix * self.matrix))
+    }
+
+    fn collect_lights(&self, _this: &Arc<dyn Hittable>, lights: &mut Vec<Arc<dyn Hittable>>) {
+        for light in collect_lights(&self.ptr) {
+            lights.push(Arc::new(Transform { ptr: light, matrix: self.matrix, inverse: self.inverse }));
+        }
+    }
+}
+
+impl Transform {
+    // None when the matrix cannot be inverted
+    pub fn new(p: Arc<dyn Hittable>, matrix: Mat4) -> Option<Self> {
+        let inverse = matrix.inverse()?;
+        Some(Transform {
+            ptr: p,
+            matrix,
+            inverse,
+        })
+    }
+
+    pub fn rotate_x(p: Arc<dyn Hittable>, angle: f64) -> Self {
+        Transform::new(p, Mat4::rotate_x(angle)).unwrap()
+    }
+
+    pub fn rotate_y(p: Arc<dyn Hittable>, angle: f64) -> Self {
+        Transform::new(p, Mat4::rotate_y(angle)).unwrap()
+    }
+
+    pub fn rotate_z(p: Arc<dyn Hittable>, angle: f64) -> Self {
+        Transform::new(p, Mat4::rotate_z(angle)).unwrap()
+    }
+
+    // None for a zero axis
+    pub fn rotate_axis(p: Arc<dyn Hittable>, axis: Vec3, angle: f64) -> Option<Self> {
+        if axis.length_square() == 0.0 {
+            return None;
+        }
+        Transform::new(p, Mat4::rotate_axis(axis, angle))
+    }
+
+    // None when a factor is zero
+    pub fn scale(p: Arc<dyn Hittable>, factors: Vec3) -> Option<Self> {
+        Transform::new(p, Mat4::scale(factors))
+    }
+
+    // See Mat4::look_at. None when the view direction is along `up`.
+    pub fn look_at(p: Arc<dyn Hittable>, eye: Point3, target: Point3, up: Vec3) -> Option<Self> {
+        Transform::new(p, Mat4::look_at(eye, target, up))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A density of 1 in every direction, to see the factor Transform adds
+    struct Flat;
+
+    impl Hittable for Flat {
+        fn hit(&self, _r: &Ray, _t_min: f64, _t_max: f64, _rec: &mut HitRecord) -> bool {
+            false
+        }
+
+        fn bounding_box(&self, _time0: f64, _time1: f64, _output_box: &mut AABB) -> bool {
+            false
+        }
+
+        fn pdf_value(&self, _o: &Point3, _v: &Vec3) -> f64 {
+            1.0
+        }
+    }
+
+    // Solid angle of the small triangle between three unit directions
+    fn solid_angle(a: Vec3, b: Vec3, c: Vec3) -> f64 {
+        0.5 * Vec3::cross(b - a, c - a).length()
+    }
+
+    #[test]
+    fn the_density_follows_the_change_of_solid_angle() {
+        let mut matrix = Mat4::rotate_axis(Vec3(0.3, 1.0, -0.2), 50.0) * Mat4::scale(Vec3(0.5, 2.0, 1.5));
+        matrix.m[1][2] = 0.8;
+        let transform = Transform::new(Arc::new(Flat), matrix).unwrap();
+        let inverse = matrix.inverse().unwrap();
+        let to_object = |w: Vec3| Vec3::unit_vector(inverse.transform_vector(&w));
+
+        // A small triangle of world directions around w and the triangle of
+        // object directions it maps to
+        let h = 1.0e-4;
+        for w in [Vec3(0.0, 0.0, 1.0), Vec3(1.0, 2.0, -0.5), Vec3(-0.7, 0.1, 0.3)] {
+            let w = Vec3::unit_vector(w);
+            let t1 = Vec3::unit_vector(Vec3::cross(w, Vec3(0.6, 0.8, 0.0)));
+            let t2 = Vec3::cross(w, t1);
+            let corners = [w, Vec3::unit_vector(w + h*t1), Vec3::unit_vector(w + h*t2)];
+            let world = solid_angle(corners[0], corners[1], corners[2]);
+            let object = solid_angle(to_object(corners[0]), to_object(corners[1]), to_object(corners[2]));
+
+            let pdf = transform.pdf_value(&Point3(1.0, 2.0, 3.0), &(3.0*w));
+            assert!((pdf - object / world).abs() < 1.0e-3 * pdf, "pdf {} ratio {}", pdf, object / world);
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use crate::hittable::Hittable;
+use crate::hittable::HitRecord;
+use crate::hittable::area_light_power;
+use crate::aabb::AABB;
+
+use crate::mat4::Mat4;
+
+use crate::ray::Ray;
+
+use crate::material::Material;
+use crate::material::DefaultMaterial;
+
+use crate::rtweekend::random_double;
+use crate::vec3::Vec3;
+use crate::vec3::Point3;
+
+use std::sync::Arc;
+
+pub struct Triangle {
+    pub vertices: [Point3; 3],
+    // Shading normals at the vertices. They are interpolated across the face.
+    pub normals: [Vec3; 3],
+    // Texture coordinates at the vertices
+    pub uvs: [(f64, f64); 3],
+    pub mat_ptr: Arc<dyn Material>,
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, r: &Ray, t_min: f64, t_max: f64, rec: &mut HitRecord) -> bool {
+        let [p0, p1, p2] = self.vertices;
+        let (t, b1, b2) = match intersect_triangle(r, &p0, &p1, &p2, t_min, t_max) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let b0 = 1.0 - b1 - b2;
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.u = b0*self.uvs[0].0 + b1*self.uvs[1].0 + b2*self.uvs[2].0;
+        rec.v = b0*self.uvs[0].1 + b1*self.uvs[1].1 + b2*self.uvs[2].1;
+        let shading_normal = b0*self.normals[0] + b1*self.normals[1] + b2*self.normals[2];
+        set_triangle_normal(rec, r, Vec3::cross(p1 - p0, p2 - p0), shading_normal);
+        rec.mat_ptr = self.mat_ptr.clone();
+        true
+    }
+
+    fn bounding_box(&self, _time0: f64, _time1: f64, output_box: &mut AABB) -> bool {
+        *output_box = triangle_box(&self.vertices[0], &self.vertices[1], &self.vertices[2]);
+        true
+    }
+
+    fn pdf_value(&self, o: &Point3, v: &Vec3) -> f64 {
+        let mut rec: HitRecord = HitRecord {
+            p: Point3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 0.0, 0.0),
+            mat_ptr: Arc::new(DefaultMaterial),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+        };
+
+        if !self.hit(&Ray { origin: *o, direction: *v, tm: 0.0, wavelength: 0.0 }, 0.001, f64::INFINITY, &mut rec) {
+            return 0.0;
+        }
+
+        // The geometric normal, as the shading normal does not tell how the face is tilted
+        let [p0, p1, p2] = self.vertices;
+        let n = Vec3::cross(p1 - p0, p2 - p0);
+        let area = 0.5*n.length();
+        let distance_squared = rec.t * rec.t * v.length_square();
+        let cosine = Vec3::dot(*v, n).abs() / (v.length() * n.length());
+
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, o: &Vec3) -> Vec3 {
+        random_point_on_triangle(&self.vertices[0], &self.vertices[1], &self.vertices[2]) - *o
+    }
+
+    fn transformed_emitted_power(&self, matrix: &Mat4) -> f64 {
+        area_light_power(self.area(matrix), self.mat_ptr.as_ref())
+    }
+
+    fn area(&self, matrix: &Mat4) -> f64 {
+        let [p0, p1, p2] = self.vertices;
+        0.5*matrix.transformed_area(&(p1 - p0), &(p2 - p0))
+    }
+}
+
+impl Triangle {
+    // A flat triangle. The texture coordinates are (0,0), (1,0) and (0,1) at
+    // the three corners.
+    pub fn new(p0: Point3, p1: Point3, p2: Point3, mp: Arc<dyn Material>) -> Self {
+        let n = Vec3::unit_vector(Vec3::cross(p1 - p0, p2 - p0));
+        Triangle {
+            vertices: [p0, p1, p2],
+            normals: [n, n, n],
+            uvs: [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)],
+            mat_ptr: mp,
+        }
+    }
+
+    pub fn with_vertex_data(vertices: [Point3; 3], normals: [Vec3; 3], uvs: [(f64, f64); 3], mp: Arc<dyn Material>) -> Self {
+        Triangle {
+            vertices,
+            normals,
+            uvs,
+            mat_ptr: mp,
+        }
+    }
+}
+
+// Möller–Trumbore ray/triangle intersection. Returns the ray parameter and the
+// barycentric coordinates of p1 and p2 at the hit point.
+pub fn intersect_triangle(r: &Ray, p0: &Point3, p1: &Point3, p2: &Point3, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let edge1 = *p1 - *p0;
+    let edge2 = *p2 - *p0;
+
+    let pvec = Vec3::cross(r.direction(), edge2);
+    let det = Vec3::dot(edge1, pvec);
+    // The ray is parallel to the triangle
+    if det.abs() < 1e-12 {
+        return None;
+    }
+    let inv_det = 1.0 / det;
+
+    let tvec = r.origin() - *p0;
+    let u = Vec3::dot(tvec, pvec) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let qvec = Vec3::cross(tvec, edge1);
+    let v = Vec3::dot(r.direction(), qvec) * inv_det;
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+
+    let t = Vec3::dot(edge2, qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, u, v))
+}
+
+// Sets the normal and front_face of a triangle hit. Which side was hit comes
+// from the geometric normal; the interpolated shading normal is turned to that
+// side, so smoothing cannot make a face look the wrong way round.
+pub fn set_triangle_normal(rec: &mut HitRecord, r: &Ray, geometric_normal: Vec3, shading_normal: Vec3) {
+    let geometric_normal = Vec3::unit_vector(geometric_normal);
+    let mut shading_normal = if shading_normal.length_square() > 0.0 { Vec3::unit_vector(shading_normal) } else { geometric_normal };
+    if Vec3::dot(shading_normal, geometric_normal) < 0.0 {
+        shading_normal = -shading_normal;
+    }
+
+    rec.front_face = Vec3::dot(r.direction(), geometric_normal) < 0.0;
+    rec.normal = if rec.front_face { shading_normal } else { -shading_normal };
+}
+
+// The bounding box of a triangle. Like the rectangles, flat sides are padded
+// a small amount so the box never has zero width.
+pub fn triangle_box(p0: &Point3, p1: &Point3, p2: &Point3) -> AABB {
+    let mut minimum = Point3(0.0, 0.0, 0.0);
+    let mut maximum = Point3(0.0, 0.0, 0.0);
+
+    for a in 0..3 {
+        minimum[a] = p0[a].min(p1[a]).min(p2[a]);
+        maximum[a] = p0[a].max(p1[a]).max(p2[a]);
+        if maximum[a] - minimum[a] < 0.0002 {
+            minimum[a] -= 0.0001;
+            maximum[a] += 0.0001;
+        }
+    }
+
+    AABB {
+        minimum,
+        maximum
+    }
+}
+
+// A point picked uniformly over the area of the triangle
+pub fn random_point_on_triangle(p0: &Point3, p1: &Point3, p2: &Point3) -> Point3 {
+    let s = random_double().sqrt();
+    let r2 = random_double();
+    (1.0 - s)**p0 + s*(1.0 - r2)**p1 + s*r2**p2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ray(origin: Point3, direction: Vec3) -> Ray {
+        Ray { origin, direction, tm: 0.0, wavelength: 0.0 }
+    }
+
+    fn empty_record() -> HitRecord {
+        HitRecord {
+            p: Point3(0.0, 0.0, 0.0),
+            normal: Vec3(0.0, 0.0, 0.0),
+            mat_ptr: Arc::new(DefaultMaterial),
+            t: 0.0,
+            u: 0.0,
+            v: 0.0,
+            front_face: false,
+        }
+    }
+
+    // The triangle (0,0,0), (2,0,0), (0,2,0), seen from above
+    fn down_at(x: f64, y: f64) -> Ray {
+        ray(Point3(x, y, 1.0), Vec3(0.0, 0.0, -2.0))
+    }
+
+    const P0: Point3 = Point3(0.0, 0.0, 0.0);
+    const P1: Point3 = Point3(2.0, 0.0, 0.0);
+    const P2: Point3 = Point3(0.0, 2.0, 0.0);
+
+    #[test]
+    fn hits_give_the_distance_and_barycentric_coordinates() {
+        let (t, b1, b2) = intersect_triangle(&down_at(0.5, 1.0), &P0, &P1, &P2, 0.0, f64::INFINITY).unwrap();
+        assert!((t - 0.5).abs() < 1.0e-12);
+        assert!((b1 - 0.25).abs() < 1.0e-12);
+        assert!((b2 - 0.5).abs() < 1.0e-12);
+    }
+
+    #[test]
+    fn rays_beside_behind_or_along_the_triangle_miss() {
+        assert!(intersect_triangle(&down_at(1.5, 1.5), &P0, &P1, &P2, 0.0, f64::INFINITY).is_none());
+        assert!(intersect_triangle(&down_at(-0.1, 1.0), &P0, &P1, &P2, 0.0, f64::INFINITY).is_none());
+        assert!(intersect_triangle(&down_at(1.0, -0.1), &P0, &P1, &P2, 0.0, f64::INFINITY).is_none());
+        // Outside the range of t
+        assert!(intersect_triangle(&down_at(0.5, 0.5), &P0, &P1, &P2, 0.0, 0.4).is_none());
+        assert!(intersect_triangle(&ray(Point3(0.5, 0.5, -1.0), Vec3(0.0, 0.0, -1.0)), &P0, &P1, &P2, 0.0, f64::INFINITY).is_none());
+        // In the plane of the triangle
+        assert!(intersect_triangle(&ray(Point3(-1.0, 0.5, 0.0), Vec3(1.0, 0.0, 0.0)), &P0, &P1, &P2, 0.0, f64::INFINITY).is_none());
+    }
+
+    #[test]
+    fn edges_and_corners_belong_to_the_triangle() {
+        for (x, y) in [(1.0, 0.0), (0.0, 1.0), (1.0, 1.0), (0.0, 0.0), (2.0, 0.0), (0.0, 2.0)] {
+            assert!(intersect_triangle(&down_at(x, y), &P0, &P1, &P2, 0.0, f64::INFINITY).is_some(), "({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn normals_and_texture_coordinates_are_interpolated() {
+        let triangle = Triangle::with_vertex_data(
+            [P0, P1, P2],
+            [Vec3(0.0, 0.0, 1.0), Vec3(1.0, 0.0, 1.0), Vec3(0.0, 1.0, 1.0)],
+            [(0.0, 0.0), (1.0, 0.0), (0.5, 1.0)],
+            Arc::new(DefaultMaterial),
+        );
+
+        let mut rec = empty_record();
+        assert!(triangle.hit(&down_at(0.5, 1.0), 0.001, f64::INFINITY, &mut rec));
+        assert!(rec.front_face);
+        assert!((rec.p - Point3(0.5, 1.0, 0.0)).near_zero());
+        // Weights 0.25, 0.25 and 0.5 for the three corners
+        assert!((rec.u - 0.5).abs() < 1.0e-12 && (rec.v - 0.5).abs() < 1.0e-12, "u {} v {}", rec.u, rec.v);
+        assert!((rec.normal - Vec3::unit_vector(Vec3(0.25, 0.5, 1.0))).near_zero());
+
+        // From below the same shading normal is turned to face the ray
+        assert!(triangle.hit(&ray(Point3(0.5, 1.0, -1.0), Vec3(0.0, 0.0, 1.0)), 0.001, f64::INFINITY, &mut rec));
+        assert!(!rec.front_face);
+        assert!((rec.normal + Vec3::unit_vector(Vec3(0.25, 0.5, 1.0))).near_zero());
+    }
+}
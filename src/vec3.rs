@@ -197,9 +197,9 @@ pub fn random_unit_vector() -> Vec3 {
 pub fn random_in_hemisphere(normal: Vec3) -> Vec3{
     let in_unit_sphere = random_in_unit_sphere();
     if Vec3::dot(in_unit_sphere, normal) > 0.0 {
-        return in_unit_sphere;
+        in_unit_sphere
     } else {
-        return -in_unit_sphere;
+        -in_unit_sphere
     }
 }
 